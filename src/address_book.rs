@@ -0,0 +1,67 @@
+//! The network addresses of the members of the cluster.
+//!
+//! Replicas identify each other by `NodeId`, which stays the same across restarts, while the
+//! address a node listens on may change. The `AddressBook` maps IDs to the latest known address,
+//! and is updated from the sender address carried by every message between nodes, under the ID
+//! presented in the handshake of its connection. Only cluster keys authenticate that ID (see
+//! `handshake`); without them a node can claim to be any member.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use NodeId;
+
+/// Maps the ID of each known node to its latest address.
+pub struct AddressBook {
+    addresses: HashMap<NodeId, SocketAddr>,
+}
+
+impl AddressBook {
+
+    /// Creates an `AddressBook` holding the provided addresses.
+    pub fn new(addresses: HashMap<NodeId, SocketAddr>) -> AddressBook {
+        AddressBook { addresses: addresses }
+    }
+
+    /// Records the address of the node.
+    ///
+    /// Returns the previous address, if it is different from the new one.
+    pub fn insert(&mut self, id: NodeId, addr: SocketAddr) -> Option<SocketAddr> {
+        match self.addresses.insert(id, addr) {
+            Some(previous) if previous != addr => Some(previous),
+            _ => None,
+        }
+    }
+
+    /// Returns the latest known address of the node.
+    pub fn get(&self, id: &NodeId) -> Option<SocketAddr> {
+        self.addresses.get(id).cloned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+
+    use address_book::AddressBook;
+    use NodeId;
+
+    #[test]
+    fn test_address_change() {
+        let id = NodeId::new();
+        let first = SocketAddr::from_str("127.0.0.1:1").unwrap();
+        let second = SocketAddr::from_str("127.0.0.1:2").unwrap();
+        let mut addresses = HashMap::new();
+        addresses.insert(id, first);
+        let mut book = AddressBook::new(addresses);
+
+        assert_eq!(Some(first), book.get(&id));
+        assert_eq!(None, book.insert(id, first));
+        assert_eq!(Some(first), book.insert(id, second));
+        assert_eq!(Some(second), book.get(&id));
+        assert_eq!(None, book.get(&NodeId::new()));
+    }
+}
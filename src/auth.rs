@@ -0,0 +1,227 @@
+//! Authentication of messages with shared cluster keys.
+//!
+//! On networks where TLS is not an option, the messages between `Server`s and from `Raft` clients
+//! can be authenticated with a secret shared by every node, by setting `Config::cluster_keys`.
+//! Each packed message is then sealed in a `Sealed` message along with its HMAC-SHA256 under the
+//! first key. A message is accepted if its HMAC matches under any of the keys, so that a cluster
+//! can go through a key rotation while some nodes still seal with the old key.
+//!
+//! Messages are sealed per connection. Each end of a connection announces a random nonce in its
+//! handshake, and the other end includes that nonce in the HMAC of every message it sends on the
+//! connection, along with the sequence number of the message. A message recorded on one connection
+//! is thus refused on any other, and a message replayed on its own connection is refused as stale.
+//!
+//! The handshake of each end carries an HMAC of the cluster ID, the `NodeId` of the sender and
+//! its nonce, so that only a holder of the keys can open a connection under a member's ID.
+
+use capnp::serialize_packed;
+use capnp::{MessageBuilder, MessageReader, MallocMessageBuilder, OwnedSpaceMessageReader, ReaderOptions};
+use openssl::crypto::hash::Type;
+use openssl::crypto::hmac::hmac;
+use openssl::crypto::rand::rand_bytes;
+
+use frame::pack;
+use messages_capnp::sealed;
+use {Error, ErrorKind, Result};
+
+/// The size of the nonce of a connection, in bytes.
+const NONCE_SIZE: usize = 16;
+
+/// Returns a fresh nonce for a new connection.
+pub fn nonce() -> Vec<u8> {
+    rand_bytes(NONCE_SIZE)
+}
+
+/// Seals the messages sent on a connection.
+pub struct Sealer {
+    /// The nonce announced by the other end of the connection.
+    nonce: Vec<u8>,
+    /// The sequence number of the next message.
+    sequence: u64,
+}
+
+impl Sealer {
+
+    /// Creates the sealer of a connection whose other end announced the nonce.
+    pub fn new(nonce: Vec<u8>) -> Sealer {
+        Sealer { nonce: nonce, sequence: 0 }
+    }
+
+    /// Seals the packed message with the first key, or returns it as is if there are no keys.
+    pub fn seal(&mut self, keys: &[Vec<u8>], message: Vec<u8>) -> Vec<u8> {
+        let key = match keys.first() {
+            Some(key) => key,
+            None => return message,
+        };
+        let sequence = self.sequence;
+        self.sequence += 1;
+        let mut builder = MallocMessageBuilder::new_default();
+        {
+            let mut sealed = builder.init_root::<sealed::Builder>();
+            sealed.set_mac(&mac(key, &self.nonce, sequence, &message));
+            sealed.set_sequence(sequence);
+            sealed.set_message(&message);
+        }
+        pack(&mut builder)
+    }
+}
+
+/// Opens the messages received on a connection.
+pub struct Opener {
+    /// The nonce this end announced.
+    nonce: Vec<u8>,
+    /// The lowest sequence number which has not been accepted yet. Messages lost to a failed
+    /// check leave a gap, so later sequence numbers are accepted as well.
+    next: u64,
+}
+
+impl Opener {
+
+    /// Creates the opener of a connection on which this end announced the nonce.
+    pub fn new(nonce: Vec<u8>) -> Opener {
+        Opener { nonce: nonce, next: 0 }
+    }
+
+    /// Verifies a sealed message under the keys, and returns the message it holds. Messages
+    /// which were already accepted, or which come before one which was, are refused. If there
+    /// are no keys, the message is returned as is.
+    pub fn unseal(&mut self, keys: &[Vec<u8>], reader: OwnedSpaceMessageReader)
+                  -> Result<OwnedSpaceMessageReader> {
+        if keys.is_empty() {
+            return Ok(reader);
+        }
+        let sealed = try!(reader.get_root::<sealed::Reader>());
+        let sequence = sealed.get_sequence();
+        let mac_bytes = try!(sealed.get_mac());
+        let message = try!(sealed.get_message());
+        if sequence < self.next
+                || !keys.iter().any(|key| {
+                    constant_time_eq(&mac(key, &self.nonce, sequence, message), mac_bytes)
+                }) {
+            return Err(Error::Raft(ErrorKind::Unauthenticated));
+        }
+        self.next = sequence + 1;
+        Ok(try!(serialize_packed::read_message(&mut &message[..], ReaderOptions::new())))
+    }
+}
+
+/// Returns the HMAC-SHA256 which authenticates a handshake under the key: that of the cluster ID,
+/// the ID of the sender and the nonce it announced.
+pub fn handshake_mac(key: &[u8], cluster_id: &str, sender: &[u8], nonce: &[u8]) -> Vec<u8> {
+    let mut input = b"handshake".to_vec();
+    for field in [cluster_id.as_bytes(), sender, nonce].iter() {
+        // Each field is preceded by its length, so that no two handshakes share an input.
+        input.extend((0..4).rev().map(|shift| (field.len() >> (shift * 8)) as u8));
+        input.extend(field.iter().cloned());
+    }
+    hmac(Type::SHA256, key, &input)
+}
+
+/// Checks the HMAC of a handshake under the keys. Every handshake is accepted if there are no
+/// keys.
+pub fn verify_handshake(keys: &[Vec<u8>], cluster_id: &str, sender: &[u8], nonce: &[u8],
+                        mac_bytes: &[u8]) -> Result<()> {
+    if keys.is_empty() || keys.iter().any(|key| {
+        constant_time_eq(&handshake_mac(key, cluster_id, sender, nonce), mac_bytes)
+    }) {
+        Ok(())
+    } else {
+        Err(Error::Raft(ErrorKind::Unauthenticated))
+    }
+}
+
+/// Returns the HMAC-SHA256 of the message under the key, bound to the nonce and the sequence
+/// number.
+fn mac(key: &[u8], nonce: &[u8], sequence: u64, message: &[u8]) -> Vec<u8> {
+    let mut input = Vec::with_capacity(nonce.len() + 8 + message.len());
+    input.extend(nonce.iter().cloned());
+    input.extend((0..8).rev().map(|shift| (sequence >> (shift * 8)) as u8));
+    input.extend(message.iter().cloned());
+    hmac(Type::SHA256, key, &input)
+}
+
+/// Compares the slices in a time which does not depend on where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod test {
+
+    use capnp::serialize_packed;
+    use capnp::{MessageBuilder, MessageReader, MallocMessageBuilder, ReaderOptions};
+
+    use auth::{Opener, Sealer};
+    use frame::pack;
+    use messages_capnp::{client_request, message};
+    use {Error, ErrorKind};
+
+    fn die_message() -> Vec<u8> {
+        let mut builder = MallocMessageBuilder::new_default();
+        builder.init_root::<message::Builder>().init_client_request().set_die("Testing.");
+        pack(&mut builder)
+    }
+
+    fn open(opener: &mut Opener, keys: &[Vec<u8>], sealed: &[u8]) -> bool {
+        let reader = serialize_packed::read_message(&mut &sealed[..], ReaderOptions::new()).unwrap();
+        match opener.unseal(keys, reader) {
+            Ok(reader) => {
+                let message = reader.get_root::<message::Reader>().unwrap();
+                match message.which() {
+                    Ok(message::Which::ClientRequest(Ok(request))) => match request.which() {
+                        Ok(client_request::Which::Die(Ok(reason))) => reason == "Testing.",
+                        _ => false,
+                    },
+                    _ => false,
+                }
+            },
+            Err(Error::Raft(ErrorKind::Unauthenticated)) => false,
+            Err(error) => panic!("Unexpected error: {:?}", error),
+        }
+    }
+
+    #[test]
+    fn test_no_keys() {
+        assert_eq!(die_message(), Sealer::new(b"nonce".to_vec()).seal(&[], die_message()));
+        assert!(open(&mut Opener::new(b"nonce".to_vec()), &[], &die_message()));
+    }
+
+    #[test]
+    fn test_wrong_key() {
+        let sealed = Sealer::new(b"nonce".to_vec()).seal(&[b"the cluster key!".to_vec()], die_message());
+        assert!(open(&mut Opener::new(b"nonce".to_vec()), &[b"the cluster key!".to_vec()], &sealed));
+        assert!(!open(&mut Opener::new(b"nonce".to_vec()), &[b"another key here".to_vec()], &sealed));
+    }
+
+    /// Tests that messages sealed with either key are accepted while keys are rotated.
+    #[test]
+    fn test_rotation() {
+        let old = b"the old cluster key".to_vec();
+        let new = b"the new cluster key".to_vec();
+        let sealed_old = Sealer::new(b"nonce".to_vec()).seal(&[old.clone(), new.clone()], die_message());
+        let sealed_new = Sealer::new(b"nonce".to_vec()).seal(&[new.clone(), old.clone()], die_message());
+        assert!(open(&mut Opener::new(b"nonce".to_vec()), &[old.clone(), new.clone()], &sealed_new));
+        assert!(open(&mut Opener::new(b"nonce".to_vec()), &[new.clone(), old.clone()], &sealed_old));
+        assert!(!open(&mut Opener::new(b"nonce".to_vec()), &[new.clone()], &sealed_old));
+    }
+
+    /// Tests that a message is refused on another connection, and when it is replayed on its own.
+    #[test]
+    fn test_replay() {
+        let keys = vec![b"the cluster key!".to_vec()];
+        let mut sealer = Sealer::new(b"nonce".to_vec());
+        let first = sealer.seal(&keys, die_message());
+        let second = sealer.seal(&keys, die_message());
+        assert!(!open(&mut Opener::new(b"other nonce".to_vec()), &keys, &first));
+
+        let mut opener = Opener::new(b"nonce".to_vec());
+        assert!(open(&mut opener, &keys, &first));
+        assert!(!open(&mut opener, &keys, &first));
+        assert!(open(&mut opener, &keys, &second));
+        assert!(!open(&mut opener, &keys, &first));
+        assert!(!open(&mut opener, &keys, &second));
+    }
+}
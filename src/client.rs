@@ -0,0 +1,818 @@
+//! Clients of a cluster.
+//!
+//! A `RaftClient` keeps a connection open to each node it sends client requests to, and reopens it
+//! once it is closed. Many client requests may be outstanding on a connection at once. Each
+//! request carries an ID chosen by the connection, which the node echoes in its response, so that
+//! responses can be matched to their requests in whatever order they arrive.
+//!
+//! The requests of a `Raft` to its related `Server` do not go over TCP. They are delivered through
+//! the channel of the `Server`'s event loop, along with a `Reply` through which the `Server` puts
+//! the outcome where the `Proposal` of the request waits for it.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::str::FromStr;
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use std::u32;
+
+use mio::EventLoopSender;
+use openssl::ssl::{SslContext, SslStream};
+use time;
+
+// Cap'n Proto
+use capnp::serialize_packed;
+use capnp::{MessageBuilder, MessageReader, MallocMessageBuilder, OwnedSpaceMessageReader,
+            ReaderOptions};
+use messages_capnp::{client_request, client_response, message};
+
+use auth::{self, Opener, Sealer};
+use frame::{self, FrameDecoder};
+use handshake;
+use tls;
+use status::{self, Status};
+use transport::Notification;
+use {Config, Error, ErrorKind, LogIndex, NodeId, Result};
+
+/// How long the thread owning a TLS session waits for a response before it writes the requests
+/// handed to it in the meantime, in milliseconds.
+const TLS_POLL_INTERVAL: u64 = 10;
+
+/// The size of the chunks a TLS session is read in.
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// A client of a cluster, which is not a member of it.
+///
+/// A `RaftClient` sends its requests to the leader of the cluster, which it finds by asking the
+/// members in turn. A `Raft` uses one to send the requests of its application.
+pub struct RaftClient {
+    /// The ID presented in handshakes: that of the related `Server` of a `Raft`, or a random one.
+    id: NodeId,
+    current_leader: Option<SocketAddr>,
+    /// The members of the cluster, in the order in which they are asked for the leader.
+    cluster_members: Vec<SocketAddr>,
+    config: Config,
+    /// The TLS context of requests, if TLS is enabled.
+    tls: Option<SslContext>,
+    /// The open connections to the nodes which requests were sent to, by address.
+    connections: HashMap<SocketAddr, Connection>,
+    /// The related `Server` of a `Raft`, which is sent requests without a connection.
+    local: Option<Local>,
+}
+
+impl RaftClient {
+
+    /// Creates a client of the cluster with the provided members, and finds its leader. Finding
+    /// the leader is retried like an append, until `Config::request_timeout` has passed.
+    ///
+    /// Only the client request settings of the `Config` are used, along with its TLS
+    /// certificates, cluster keys and cluster ID, which must match those of the cluster.
+    pub fn connect(cluster_members: &[SocketAddr], config: Config) -> Result<RaftClient> {
+        let mut client = try!(new(NodeId::new(), cluster_members.to_vec(), config));
+        let deadline = deadline(client.config.request_timeout);
+        let mut backoff = client.config.retry_backoff;
+        loop {
+            match client.refresh_leader_until(deadline) {
+                Ok(()) => return Ok(client),
+                Err(Error::Raft(ErrorKind::CannotProceed)) | Err(Error::Io(_)) => (),
+                Err(error) => return Err(error),
+            }
+            if remaining(deadline) <= backoff {
+                return Err(Error::Raft(ErrorKind::CannotProceed));
+            }
+            thread::sleep_ms(backoff as u32);
+            backoff = backoff.saturating_mul(2);
+        }
+    }
+
+    /// Sends a `ClientRequest` to the provided address, over the connection to it which is opened
+    /// if necessary, or through the event loop of the related `Server`, without waiting for the
+    /// response. Opening the connection and writing the request give up at the deadline.
+    fn send<F>(&mut self, addr: SocketAddr, deadline: u64, request: F) -> Result<Proposal>
+    where F: FnOnce(client_request::Builder) {
+        if let Some(ref mut local) = self.local {
+            if local.addr == addr {
+                return local.send(request);
+            }
+        }
+        if self.connections.get(&addr).map(|connection| connection.is_closed()).unwrap_or(true) {
+            self.connections.remove(&addr);
+            let connection = try!(Connection::open(addr, self.id, &self.config, self.tls.as_ref(),
+                                                   deadline));
+            self.connections.insert(addr, connection);
+        }
+        let sent = self.connections.get_mut(&addr).unwrap().send(deadline, request);
+        if sent.is_err() {
+            self.connections.remove(&addr);
+        }
+        sent
+    }
+
+    /// Replaces the cluster keys which authenticate the requests of this client.
+    pub fn set_cluster_keys(&mut self, keys: Vec<Vec<u8>>) -> Result<()> {
+        let config = Config { cluster_keys: keys, ..self.config.clone() };
+        try!(config.validate());
+        self.config = config;
+        // The connections authenticate their requests with the replaced keys.
+        self.connections.clear();
+        Ok(())
+    }
+
+    /// Appends an entry to the replicated log. This will only return once it's properly replicated
+    /// to a majority of nodes.
+    ///
+    /// The append follows redirects to the leader up to `Config::max_redirects` times, after which
+    /// it fails with `ErrorKind::TooManyRedirects`. It is retried with a backoff while the cluster
+    /// is between leaders or the leader is unreachable, and fails with `ErrorKind::CannotProceed`
+    /// if that is still the case once `Config::request_timeout` has passed. It fails with
+    /// `ErrorKind::Timeout` if the leader does not answer in that time.
+    ///
+    /// An append is only retried if it could not be sent. Once the entry has been sent, a failure
+    /// of the connection to the leader fails the append with `ErrorKind::OutcomeUnknown`, since the
+    /// entry may have been appended nonetheless.
+    pub fn append(&mut self, entry: &[u8]) -> Result<()> {
+        self.request_leader(|mut client_req| client_req.set_append(entry)).map(|_| ())
+    }
+
+    /// Appends the entries to the replicated log together, in a single request, and returns the
+    /// log index of each. This will only return once they are properly replicated to a majority of
+    /// nodes. It is retried like `append()`.
+    ///
+    /// Either every entry is applied, or the call fails. An entry of a batch which failed may
+    /// still be applied if the leader which appended it is elected again.
+    pub fn append_batch(&mut self, entries: &[&[u8]]) -> Result<Vec<LogIndex>> {
+        if entries.is_empty() {
+            return Ok(Vec::new());
+        }
+        let response = try!(self.request_leader(|client_req| {
+            let mut batch = client_req.init_append_batch(entries.len() as u32);
+            for (n, &entry) in entries.iter().enumerate() {
+                batch.set(n as u32, entry);
+            }
+        }));
+        match response {
+            Response::Appended(ref indexes) if indexes.len() == entries.len() => Ok(indexes.clone()),
+            _ => Err(Error::Raft(ErrorKind::BadResponse)),
+        }
+    }
+
+    /// Sends the request to the leader, and waits for its outcome. The request follows redirects
+    /// and is retried as described by `append()`.
+    fn request_leader<F>(&mut self, request: F) -> Result<Response>
+    where F: Fn(client_request::Builder) {
+        let deadline = deadline(self.config.request_timeout);
+        let mut redirects = 0;
+        let mut backoff = self.config.retry_backoff;
+        loop {
+            let outcome = match self.send_leader(deadline, &request) {
+                Ok(proposal) => wait(proposal, deadline),
+                Err(error) => Err(error),
+            };
+            match outcome {
+                Err(Error::Raft(ErrorKind::NotLeader(leader))) => {
+                    redirects += 1;
+                    if redirects > self.config.max_redirects {
+                        return Err(Error::Raft(ErrorKind::TooManyRedirects));
+                    }
+                    // Try again.
+                    self.current_leader = Some(leader);
+                    continue;
+                },
+                // The cluster is between leaders, or the leader could not be reached, so nothing
+                // was appended. The members are asked for the leader again.
+                Err(Error::Raft(ErrorKind::CannotProceed)) | Err(Error::Io(_)) => {
+                    self.current_leader = None;
+                },
+                // The leader may be gone, but only after the request reached it. Retrying could
+                // append the entry twice.
+                Err(Error::Raft(ErrorKind::OutcomeUnknown)) => {
+                    self.current_leader = None;
+                    return Err(Error::Raft(ErrorKind::OutcomeUnknown));
+                },
+                outcome => return outcome,
+            }
+            if remaining(deadline) <= backoff {
+                return Err(Error::Raft(ErrorKind::CannotProceed));
+            }
+            thread::sleep_ms(backoff as u32);
+            backoff = backoff.saturating_mul(2);
+        }
+    }
+
+    /// Sends an entry to the leader to be appended to the replicated log, without waiting for it
+    /// to be replicated. Many entries may be proposed before the first is applied; the returned
+    /// `Proposal` reports the outcome of this one.
+    pub fn propose(&mut self, entry: &[u8]) -> Result<Proposal> {
+        let deadline = deadline(self.config.request_timeout);
+        self.send_leader(deadline, |mut client_req| client_req.set_append(entry))
+    }
+
+    /// Sends the request to the leader, which is looked up first if it is not known, without
+    /// waiting for the response. Looking up the leader gives up at the deadline.
+    fn send_leader<F>(&mut self, deadline: u64, request: F) -> Result<Proposal>
+    where F: FnOnce(client_request::Builder) {
+        if self.current_leader.is_none() { try!(self.refresh_leader_until(deadline)); }
+        // We know current leader `is_some()` because `refresh_leader_until()` didn't fail.
+        let leader = self.current_leader.unwrap();
+        let sent = self.send(leader, deadline, request);
+        if sent.is_err() {
+            // The leader may be gone, so it is looked up again by the next request.
+            self.current_leader = None;
+        }
+        sent
+    }
+
+    /// Kills the node. Should only really be used for testing purposes.
+    /// Accepts a `SocketAddr` because if you're going to kill a node you should be able to pick
+    /// your victim.
+    pub fn die(&mut self, target: SocketAddr, reason: String) -> Result<()> {
+        if !self.cluster_members.contains(&target) {
+            return Err(Error::Raft(ErrorKind::NotInCluster))
+        }
+        let deadline = deadline(self.config.request_timeout);
+        let proposal = try!(self.send(target, deadline, |mut client_req| client_req.set_die(&reason)));
+        wait(proposal, deadline).map(|_| ())
+    }
+
+    /// Returns a snapshot of the state of the node at the provided address.
+    pub fn status(&mut self, target: SocketAddr) -> Result<Status> {
+        if !self.cluster_members.contains(&target) {
+            return Err(Error::Raft(ErrorKind::NotInCluster))
+        }
+        let deadline = deadline(self.config.request_timeout);
+        let proposal = try!(self.send(target, deadline, |mut client_req| client_req.set_status(())));
+        match try!(wait(proposal, deadline)) {
+            Response::Status(status) => Ok(status),
+            _ => Err(Error::Raft(ErrorKind::BadResponse)),
+        }
+    }
+
+    /// Refreshes the knowledge of the leader, by asking the members of the cluster in turn until
+    /// one of them knows it.
+    pub fn refresh_leader(&mut self) -> Result<()> {
+        let deadline = deadline(self.config.request_timeout);
+        self.refresh_leader_until(deadline)
+    }
+
+    /// Like `refresh_leader()`, but gives up at the deadline of the request which needs the
+    /// leader.
+    fn refresh_leader_until(&mut self, deadline: u64) -> Result<()> {
+        let mut last_error = Error::Raft(ErrorKind::CannotProceed);
+        for member in self.cluster_members.clone() {
+            let sent = self.send(member, deadline, |mut client_req| client_req.set_leader_refresh(()));
+            let outcome = match sent {
+                Ok(proposal) => wait(proposal, deadline),
+                Err(error) => Err(error),
+            };
+            match outcome {
+                Err(Error::Raft(ErrorKind::NotLeader(leader))) => {
+                    self.current_leader = Some(leader);
+                    return Ok(());
+                },
+                // The node answers with the address of the leader, even when it is the leader.
+                Ok(_) => last_error = Error::Raft(ErrorKind::BadResponse),
+                // Asking for the leader has no effect, so a connection failing after the request
+                // was sent only means that the member could not be reached.
+                Err(Error::Raft(ErrorKind::OutcomeUnknown)) => {
+                    debug!("Lost the connection to {} while refreshing the leader.", member);
+                    last_error = connection_lost();
+                },
+                Err(error) => {
+                    debug!("Unable to refresh the leader from {}: {:?}", member, error);
+                    last_error = error;
+                },
+            }
+        }
+        Err(last_error)
+    }
+}
+
+/// Creates a client of the cluster with the provided members, which presents the ID in its
+/// handshakes.
+pub fn new(id: NodeId, cluster_members: Vec<SocketAddr>, config: Config) -> Result<RaftClient> {
+    try!(config.validate());
+    if cluster_members.is_empty() {
+        return Err(Error::Raft(ErrorKind::InvalidConfig("the cluster has no members".to_string())));
+    }
+    let tls = match config.tls {
+        Some(ref tls) => Some(try!(tls::context(tls))),
+        None => None,
+    };
+    Ok(RaftClient {
+        id: id,
+        current_leader: None,
+        cluster_members: cluster_members,
+        config: config,
+        tls: tls,
+        connections: HashMap::new(),
+        local: None,
+    })
+}
+
+/// Sends the requests of the client to its first member, the related `Server` of a `Raft`, through
+/// the channel of the `Server`'s event loop.
+pub fn attach(client: &mut RaftClient, notifier: EventLoopSender<Notification>) {
+    client.local = Some(Local {
+        addr: client.cluster_members[0],
+        notifier: notifier,
+        next_request_id: 0,
+    });
+}
+
+/// Returns the deadline of a request started now which may take `timeout` milliseconds, as an
+/// absolute time in nanoseconds.
+fn deadline(timeout: u64) -> u64 {
+    time::precise_time_ns() + timeout * 1_000_000
+}
+
+/// Returns the number of milliseconds left until the deadline.
+fn remaining(deadline: u64) -> u64 {
+    deadline.saturating_sub(time::precise_time_ns()) / 1_000_000
+}
+
+/// Returns the time left until the deadline, which bounds a blocking socket operation. Fails with
+/// `ErrorKind::Timeout` once the deadline has passed.
+fn time_left(deadline: u64) -> Result<Duration> {
+    match remaining(deadline) {
+        0 => Err(Error::Raft(ErrorKind::Timeout)),
+        ms => Ok(Duration::from_millis(ms)),
+    }
+}
+
+/// Waits for the outcome of a request until the deadline, after which the request fails with
+/// `ErrorKind::Timeout`. The `Proposal` is dropped either way, so a response arriving after the
+/// deadline is discarded.
+fn wait(proposal: Proposal, deadline: u64) -> Result<Response> {
+    match proposal.outcome_timeout(remaining(deadline)) {
+        Some(outcome) => outcome,
+        None => Err(Error::Raft(ErrorKind::Timeout)),
+    }
+}
+
+/// The outcome of an entry appended to the replicated log, which becomes available once the
+/// leader has applied the entry, or once the append has failed.
+///
+/// An append fails with `ErrorKind::NotLeader` when the node which received it is not, or is no
+/// longer, the leader. The entry may then be appended again to the leader at the included address.
+pub struct Proposal {
+    slot: Arc<Slot>,
+    /// The ID of the request and the requests pending on its connection, from which the slot is
+    /// removed once the `Proposal` is dropped. `None` for the requests of a `Raft` to its related
+    /// `Server`.
+    awaited: Option<(u64, Arc<Mutex<Pending>>)>,
+}
+
+impl Proposal {
+
+    /// Blocks until the outcome of the append is available.
+    pub fn wait(self) -> Result<()> {
+        let mut outcome = self.slot.outcome.lock().unwrap();
+        loop {
+            if let Some(outcome) = outcome.take() {
+                return outcome.map(|_| ());
+            }
+            outcome = self.slot.ready.wait(outcome).unwrap();
+        }
+    }
+
+    /// Blocks until the outcome of the append is available, or until `ms` milliseconds have
+    /// passed, in which case `None` is returned. The outcome is returned only once.
+    pub fn wait_timeout(&self, ms: u64) -> Option<Result<()>> {
+        self.outcome_timeout(ms).map(|outcome| outcome.map(|_| ()))
+    }
+
+    /// Returns the outcome of the append if it is available, without blocking. The outcome is
+    /// returned only once.
+    pub fn poll(&self) -> Option<Result<()>> {
+        self.slot.outcome.lock().unwrap().take().map(|outcome| outcome.map(|_| ()))
+    }
+
+    /// Like `wait_timeout()`, but returns the response to the request.
+    fn outcome_timeout(&self, ms: u64) -> Option<Result<Response>> {
+        let deadline = time::precise_time_ns() + ms * 1_000_000;
+        let mut outcome = self.slot.outcome.lock().unwrap();
+        loop {
+            if outcome.is_some() {
+                return outcome.take();
+            }
+            let now = time::precise_time_ns();
+            if now >= deadline {
+                return None;
+            }
+            let remaining = cmp::min((deadline - now) / 1_000_000 + 1, u32::MAX as u64) as u32;
+            outcome = self.slot.ready.wait_timeout_ms(outcome, remaining).unwrap().0;
+        }
+    }
+}
+
+impl Drop for Proposal {
+    fn drop(&mut self) {
+        // Nothing waits for the response any longer, for instance because the request timed out.
+        if let Some((request_id, ref pending)) = self.awaited {
+            pending.lock().unwrap().slots.remove(&request_id);
+        }
+    }
+}
+
+/// A successful response to a client request.
+enum Response {
+    Success,
+    /// The log indexes of the entries of an appended batch.
+    Appended(Vec<LogIndex>),
+    Status(Status),
+}
+
+/// The place where the outcome of a request is put once it is known.
+struct Slot {
+    outcome: Mutex<Option<Result<Response>>>,
+    ready: Condvar,
+}
+
+impl Slot {
+
+    fn fill(&self, outcome: Result<Response>) {
+        *self.outcome.lock().unwrap() = Some(outcome);
+        self.ready.notify_all();
+    }
+}
+
+/// The means for the related `Server` of a `Raft` to answer one of its requests. The outcome is
+/// put in the slot of the `Proposal` of the request. A request dropped without an answer, for
+/// instance because the `Server` stopped, fails with `ErrorKind::OutcomeUnknown`.
+pub struct Reply {
+    slot: Option<Arc<Slot>>,
+}
+
+impl Reply {
+
+    /// Answers the request with the packed `ClientResponse`.
+    pub fn send(mut self, message: &[u8]) {
+        let outcome = serialize_packed::read_message(&mut &message[..], ReaderOptions::new())
+            .map_err(Error::from)
+            .and_then(|response| decode_response(&response))
+            .and_then(|(_, outcome)| outcome);
+        self.slot.take().unwrap().fill(outcome);
+    }
+}
+
+impl Drop for Reply {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot.take() {
+            slot.fill(Err(Error::Raft(ErrorKind::OutcomeUnknown)));
+        }
+    }
+}
+
+/// Returns the `Reply` to a request of a `Raft` to its related `Server`, and the `Proposal` which
+/// waits for it.
+pub fn reply() -> (Reply, Proposal) {
+    let slot = Arc::new(Slot { outcome: Mutex::new(None), ready: Condvar::new() });
+    (Reply { slot: Some(slot.clone()) }, Proposal { slot: slot, awaited: None })
+}
+
+/// The requests awaiting a response on a connection.
+struct Pending {
+    /// The slots of the outcome of each request whose `Proposal` is still held, by request ID.
+    slots: HashMap<u64, Arc<Slot>>,
+    /// Whether the connection has been closed, after which no response will arrive.
+    closed: bool,
+}
+
+/// A connection to a node, over which requests are sent without waiting for the previous ones to
+/// be answered. The responses are read by a dedicated thread. Over TLS, that thread owns the
+/// session, and writes the requests as well.
+struct Connection {
+    /// The sending half of the stream, or the `Requests` handed to the thread owning the TLS
+    /// session.
+    writer: Box<Write + Send>,
+    /// Seals the requests with the nonce announced by the node.
+    sealer: Sealer,
+    /// The underlying socket, shut down when the connection is dropped so that the reading thread
+    /// stops.
+    socket: TcpStream,
+    next_request_id: u64,
+    pending: Arc<Mutex<Pending>>,
+    config: Config,
+}
+
+impl Connection {
+
+    /// Opens a connection to the node at the address, and exchanges handshakes with it. Connecting
+    /// and the handshakes give up at the deadline.
+    fn open(addr: SocketAddr, id: NodeId, config: &Config, tls: Option<&SslContext>, deadline: u64)
+                -> Result<Connection> {
+        let socket = try!(TcpStream::connect_timeout(&addr, try!(time_left(deadline))));
+        // The timeouts apply to every handle on the socket, including the TLS stream.
+        try!(socket.set_read_timeout(Some(try!(time_left(deadline)))));
+        try!(socket.set_write_timeout(Some(try!(time_left(deadline)))));
+        let nonce = auth::nonce();
+        let handshake = handshake::handshake(&config.cluster_id, id, &nonce, &config.cluster_keys);
+        let handshake = try!(frame::frame(&handshake, config.max_message_size));
+        let opener = Opener::new(nonce);
+        let pending = Arc::new(Mutex::new(Pending { slots: HashMap::new(), closed: false }));
+        let (writer, their_nonce): (Box<Write + Send>, Vec<u8>) = match tls {
+            Some(context) => {
+                let mut stream = try!(SslStream::connect(context, try!(socket.try_clone())));
+                let their_nonce = try!(exchange_handshakes(&mut stream, &handshake, config));
+                // The session thread stops waiting for responses now and then to write requests.
+                try!(socket.set_read_timeout(Some(Duration::from_millis(TLS_POLL_INTERVAL))));
+                let (sender, requests) = mpsc::channel();
+                spawn_session(stream, requests, opener, pending.clone(), config.clone());
+                (Box::new(Requests { buffer: Vec::new(), sender: sender }), their_nonce)
+            },
+            None => {
+                let mut stream = try!(socket.try_clone());
+                let their_nonce = try!(exchange_handshakes(&mut stream, &handshake, config));
+                // The reading thread waits for responses for as long as it takes.
+                try!(socket.set_read_timeout(None));
+                let reader = try!(stream.try_clone());
+                spawn_reader(reader, opener, pending.clone(), config.clone());
+                (Box::new(stream), their_nonce)
+            },
+        };
+        Ok(Connection {
+            writer: writer,
+            sealer: Sealer::new(their_nonce),
+            socket: socket,
+            next_request_id: 0,
+            pending: pending,
+            config: config.clone(),
+        })
+    }
+
+    /// Returns whether the connection has been closed, for instance by the node.
+    fn is_closed(&self) -> bool {
+        self.pending.lock().unwrap().closed
+    }
+
+    /// Sends the `ClientRequest` initialized by `request`, without waiting for its response. The
+    /// outcome of a request other than an append is reported through a `Proposal` as well.
+    /// Writing the request gives up at the deadline.
+    fn send<F>(&mut self, deadline: u64, request: F) -> Result<Proposal>
+    where F: FnOnce(client_request::Builder) {
+        try!(self.socket.set_write_timeout(Some(try!(time_left(deadline)))));
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+        let mut message = MallocMessageBuilder::new_default();
+        {
+            let mut client_req = message.init_root::<message::Builder>().init_client_request();
+            client_req.set_request_id(request_id);
+            request(client_req);
+        }
+        let message = self.sealer.seal(&self.config.cluster_keys, frame::pack(&mut message));
+        let frame = try!(frame::frame(&message, self.config.max_message_size));
+
+        let slot = Arc::new(Slot { outcome: Mutex::new(None), ready: Condvar::new() });
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.closed { return Err(connection_lost()); }
+            // Registered before sending, so that the response can not arrive first.
+            pending.slots.insert(request_id, slot.clone());
+        }
+        if let Err(error) = self.writer.write_all(&frame).and_then(|_| self.writer.flush()) {
+            // Part of the request may have been written, and reach the node.
+            debug!("Unable to send request {}: {:?}", request_id, error);
+            self.pending.lock().unwrap().slots.remove(&request_id);
+            return Err(Error::Raft(ErrorKind::OutcomeUnknown));
+        }
+        Ok(Proposal { slot: slot, awaited: Some((request_id, self.pending.clone())) })
+    }
+}
+
+/// The `Server` running alongside a `Raft`, whose event loop receives requests directly.
+struct Local {
+    /// The address of the `Server`, which designates it as the target of a request.
+    addr: SocketAddr,
+    notifier: EventLoopSender<Notification>,
+    next_request_id: u64,
+}
+
+impl Local {
+
+    /// Sends the `ClientRequest` initialized by `request` to the event loop of the `Server`,
+    /// without waiting for its response.
+    fn send<F>(&mut self, request: F) -> Result<Proposal> where F: FnOnce(client_request::Builder) {
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+        let mut message = MallocMessageBuilder::new_default();
+        {
+            let mut client_req = message.init_root::<message::Builder>().init_client_request();
+            client_req.set_request_id(request_id);
+            request(client_req);
+        }
+        let (reply, proposal) = reply();
+        try!(self.notifier.send(Notification::Request(frame::pack(&mut message), reply))
+                 .map_err(|_| Error::Raft(ErrorKind::RelatedNodeDown)));
+        Ok(proposal)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let _ = self.socket.shutdown(Shutdown::Both);
+    }
+}
+
+/// The sending half of a TLS connection. A TLS session must not be used by two threads at once,
+/// so each request is handed, once flushed, to the thread which owns the session.
+struct Requests {
+    /// The request being written.
+    buffer: Vec<u8>,
+    sender: mpsc::Sender<Vec<u8>>,
+}
+
+impl Write for Requests {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend(buf.iter().cloned());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let request = mem::replace(&mut self.buffer, Vec::new());
+        self.sender.send(request).map_err(|_| {
+            io::Error::new(io::ErrorKind::ConnectionAborted, "the connection was closed")
+        })
+    }
+}
+
+/// Writes the framed handshake to the stream, checks the handshake of the other end, and returns
+/// the nonce it announced.
+fn exchange_handshakes<S>(stream: &mut S, handshake: &[u8], config: &Config) -> Result<Vec<u8>>
+where S: Read + Write {
+    try!(stream.write_all(handshake));
+    try!(stream.flush());
+    let reader = try!(frame::read_frame(stream, config.max_message_size));
+    let (_, nonce) = try!(handshake::verify(&config.cluster_id, &config.cluster_keys, &reader));
+    Ok(nonce)
+}
+
+/// Spawns the thread which reads the responses off the stream, and sends the outcome of each to
+/// its pending request. Once the stream fails, the connection is closed, and every request still
+/// pending fails with `ErrorKind::OutcomeUnknown`.
+fn spawn_reader<R>(mut stream: R, mut opener: Opener, pending: Arc<Mutex<Pending>>, config: Config)
+where R: Read + Send + 'static {
+    thread::spawn(move || {
+        loop {
+            match read_response(&mut stream, &mut opener, &config) {
+                Ok((request_id, outcome)) => deliver(&pending, request_id, outcome),
+                Err(error) => {
+                    debug!("Closing the client connection: {:?}", error);
+                    break;
+                },
+            }
+        }
+        close(&pending);
+    });
+}
+
+/// Spawns the thread which owns the TLS session of a connection. It writes the requests handed to
+/// it, and reads the responses in between, sending the outcome of each to its pending request.
+/// Once the session fails, the connection is closed, and every request still pending fails with
+/// `ErrorKind::OutcomeUnknown`.
+fn spawn_session(mut stream: SslStream<TcpStream>, requests: mpsc::Receiver<Vec<u8>>,
+                 mut opener: Opener, pending: Arc<Mutex<Pending>>, config: Config) {
+    thread::spawn(move || {
+        if let Err(error) = run_session(&mut stream, &requests, &mut opener, &pending, &config) {
+            debug!("Closing the client connection: {:?}", error);
+        }
+        close(&pending);
+    });
+}
+
+/// Runs the TLS session of a connection until it fails, or the `Connection` is dropped. Reads
+/// time out after `TLS_POLL_INTERVAL`, leaving any partial frame in the decoder, so that requests
+/// do not wait for a response to be written.
+fn run_session(stream: &mut SslStream<TcpStream>, requests: &mpsc::Receiver<Vec<u8>>,
+               opener: &mut Opener, pending: &Mutex<Pending>, config: &Config) -> Result<()> {
+    let mut decoder = FrameDecoder::new(config.max_message_size);
+    let mut chunk = [0; READ_CHUNK_SIZE];
+    loop {
+        loop {
+            match requests.try_recv() {
+                Ok(request) => {
+                    try!(stream.write_all(&request));
+                    try!(stream.flush());
+                },
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+        let wanted = cmp::min(try!(decoder.remaining()), READ_CHUNK_SIZE);
+        match stream.read(&mut chunk[..wanted]) {
+            Ok(0) => return Err(connection_lost()),
+            Ok(read) => {
+                decoder.extend(&chunk[..read]);
+                while let Some(frame) = try!(decoder.next_message()) {
+                    let response = try!(opener.unseal(&config.cluster_keys, frame));
+                    let (request_id, outcome) = try!(decode_response(&response));
+                    deliver(pending, request_id, outcome);
+                }
+            },
+            Err(ref error) if error.kind() == io::ErrorKind::WouldBlock
+                              || error.kind() == io::ErrorKind::TimedOut => (),
+            Err(error) => return Err(Error::from(error)),
+        }
+    }
+}
+
+/// Sends the outcome of a response to its pending request.
+fn deliver(pending: &Mutex<Pending>, request_id: u64, outcome: Result<Response>) {
+    match pending.lock().unwrap().slots.remove(&request_id) {
+        Some(slot) => slot.fill(outcome),
+        // The request timed out, or its `Proposal` was dropped.
+        None => debug!("Dropping the response to request {}, which is not awaited", request_id),
+    }
+}
+
+/// Marks the connection closed, and fails every request still pending on it with
+/// `ErrorKind::OutcomeUnknown`.
+fn close(pending: &Mutex<Pending>) {
+    let mut pending = pending.lock().unwrap();
+    pending.closed = true;
+    for (_, slot) in pending.slots.drain() {
+        slot.fill(Err(Error::Raft(ErrorKind::OutcomeUnknown)));
+    }
+}
+
+/// Reads a `ClientResponse` off the stream, and returns its request ID along with the outcome it
+/// reports.
+fn read_response<R>(stream: &mut R, opener: &mut Opener, config: &Config)
+                    -> Result<(u64, Result<Response>)>
+where R: Read {
+    let frame = try!(frame::read_frame(stream, config.max_message_size));
+    let response = try!(opener.unseal(&config.cluster_keys, frame));
+    decode_response(&response)
+}
+
+/// Decodes a received `ClientResponse`, and returns its request ID along with the outcome it
+/// reports.
+fn decode_response(response: &OwnedSpaceMessageReader) -> Result<(u64, Result<Response>)> {
+    let client_res = try!(client_response(response));
+    let outcome = match try!(client_res.which()) {
+        client_response::Which::Success(()) => Ok(Response::Success),
+        client_response::Which::Appended(Ok(indexes)) => {
+            Ok(Response::Appended((0..indexes.len()).map(|n| LogIndex::from(indexes.get(n))).collect()))
+        },
+        client_response::Which::Status(Ok(snapshot)) => status::decode(snapshot).map(Response::Status),
+        client_response::Which::NotLeader(Ok(leader_bytes)) => {
+            match SocketAddr::from_str(leader_bytes) {
+                Ok(leader) => Err(Error::Raft(ErrorKind::NotLeader(leader))),
+                Err(_) => Err(Error::Raft(ErrorKind::BadResponse)),
+            }
+        },
+        client_response::Which::UnknownLeader(()) => Err(Error::Raft(ErrorKind::CannotProceed)),
+        client_response::Which::InternalError(Ok(reason)) => {
+            Err(Error::Raft(ErrorKind::Halted(reason.to_string())))
+        },
+        client_response::Which::BadRequest(Ok(reason)) => {
+            Err(Error::Raft(ErrorKind::BadRequest(reason.to_string())))
+        },
+        _ => Err(Error::Raft(ErrorKind::BadResponse)),
+    };
+    Ok((client_res.get_request_id(), outcome))
+}
+
+/// Reads the `ClientResponse` out of a received message.
+fn client_response<'a>(response: &'a OwnedSpaceMessageReader) -> Result<client_response::Reader<'a>> {
+    match try!(try!(response.get_root::<message::Reader>()).which()) {
+        message::Which::ClientResponse(Ok(client_res)) => Ok(client_res),
+        _ => Err(Error::Raft(ErrorKind::BadResponse)),
+    }
+}
+
+/// The error of a request which could not be sent because its connection was closed.
+fn connection_lost() -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::ConnectionAborted, "the connection was closed"))
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::collections::HashMap;
+    use std::sync::{Arc, Condvar, Mutex};
+
+    use client::{deliver, Pending, Proposal, Response, Slot};
+
+    /// Tests that the slot of a request is removed once its `Proposal` is dropped, for instance
+    /// after it timed out, and that a response arriving later is dropped.
+    #[test]
+    fn test_dropped_proposal() {
+        let pending = Arc::new(Mutex::new(Pending { slots: HashMap::new(), closed: false }));
+        let slot = Arc::new(Slot { outcome: Mutex::new(None), ready: Condvar::new() });
+        pending.lock().unwrap().slots.insert(7, slot.clone());
+        let proposal = Proposal { slot: slot, awaited: Some((7, pending.clone())) };
+        assert!(proposal.wait_timeout(1).is_none());
+        drop(proposal);
+        assert!(pending.lock().unwrap().slots.is_empty());
+        deliver(&pending, 7, Ok(Response::Success));
+        assert!(pending.lock().unwrap().slots.is_empty());
+    }
+}
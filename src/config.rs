@@ -0,0 +1,184 @@
+//! Runtime tuning of a `Server`.
+//!
+//! Not to be confused with the cluster `Configuration`, which is replicated through the log, a
+//! `Config` holds the timing and resource limits of a single node. The defaults suit a cluster on
+//! a local network; nodes spread across a wide area network will want longer timeouts.
+
+use std::u32;
+
+use rand::Rng;
+
+use {Error, ErrorKind, Result, TlsConfig};
+
+/// The smallest allowed `max_message_size`. Every protocol message without entries must fit.
+const MIN_MESSAGE_SIZE: usize = 1024;
+
+/// The smallest allowed cluster key, in bytes.
+const MIN_KEY_SIZE: usize = 16;
+
+/// The timing and resource limits of a `Server`.
+///
+/// Create one with `Config::default()` and override the fields as needed. A `Config` is checked
+/// with `validate()` before a `Server` is started with it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// The minimum time a follower waits for a heartbeat before campaigning, in milliseconds.
+    pub election_timeout_min: u64,
+    /// The maximum time a follower waits for a heartbeat before campaigning, in milliseconds.
+    /// Each election timeout is picked at random between the minimum and the maximum.
+    pub election_timeout_max: u64,
+    /// The time between heartbeats sent by the leader, in milliseconds. Must be well below
+    /// `election_timeout_min`, or followers will campaign against a healthy leader.
+    pub heartbeat_interval: u64,
+    /// The maximum size of a packed message, in bytes.
+    pub max_message_size: usize,
+    /// The maximum number of open connections to peers and clients. Further connections are
+    /// refused.
+    pub max_connections: usize,
+    /// The time after which a connection without traffic is closed, in milliseconds. A
+    /// connection is closed after being idle for between one and two times this long.
+    pub connection_idle_timeout: u64,
+    /// The maximum number of log entries the leader sends in a single `AppendEntries` request.
+    pub max_append_entries: u64,
+    /// The certificates used to secure the connections of the node with TLS, or `None` to use
+    /// plain TCP. Either every node of a cluster uses TLS, or none does.
+    pub tls: Option<TlsConfig>,
+    /// The secret keys which authenticate messages, or none to leave messages unauthenticated.
+    /// Messages are sealed with the first key, and accepted if they are sealed with any of them.
+    /// Unauthenticated messages are dropped.
+    ///
+    /// The keys of a running cluster are rotated with `Raft::set_cluster_keys()` in three steps,
+    /// each applied to every node before the next:
+    ///
+    /// 1. Append the new key. Nodes keep sealing with the old key, and accept the new one.
+    /// 2. Move the new key first. Nodes seal with the new key, and still accept the old one.
+    /// 3. Remove the old key.
+    pub cluster_keys: Vec<Vec<u8>>,
+    /// The ID of the cluster. Nodes refuse connections from nodes of other clusters, so every
+    /// cluster sharing a network should have its own.
+    pub cluster_id: String,
+    /// The time a `Raft` spends on a client request, including its retries, before failing it
+    /// with `ErrorKind::Timeout`, or with `ErrorKind::CannotProceed` if no leader could be found,
+    /// in milliseconds.
+    pub request_timeout: u64,
+    /// The maximum number of times a `Raft` follows a redirect to another leader during a single
+    /// client request.
+    pub max_redirects: u32,
+    /// The time a `Raft` waits before retrying a client request which failed for lack of a
+    /// reachable leader, in milliseconds. The wait doubles with each retry of the request.
+    pub retry_backoff: u64,
+}
+
+impl Config {
+
+    /// Checks that the values are usable together.
+    pub fn validate(&self) -> Result<()> {
+        if self.election_timeout_min == 0 {
+            return invalid("election_timeout_min must be positive");
+        }
+        if self.election_timeout_max <= self.election_timeout_min {
+            return invalid("election_timeout_max must be greater than election_timeout_min");
+        }
+        if self.heartbeat_interval == 0 {
+            return invalid("heartbeat_interval must be positive");
+        }
+        if self.heartbeat_interval.saturating_mul(2) > self.election_timeout_min {
+            return invalid("heartbeat_interval must be at most half of election_timeout_min");
+        }
+        if self.max_message_size < MIN_MESSAGE_SIZE {
+            return invalid("max_message_size must be at least 1024 bytes");
+        }
+        if self.max_message_size > u32::MAX as usize {
+            // The length of a message is framed as a 32 bit integer.
+            return invalid("max_message_size must fit in 32 bits");
+        }
+        if self.max_connections == 0 {
+            return invalid("max_connections must be positive");
+        }
+        if self.connection_idle_timeout == 0 {
+            return invalid("connection_idle_timeout must be positive");
+        }
+        if self.max_append_entries == 0 {
+            return invalid("max_append_entries must be positive");
+        }
+        if self.cluster_id.is_empty() {
+            return invalid("cluster_id must not be empty");
+        }
+        if self.cluster_keys.iter().any(|key| key.len() < MIN_KEY_SIZE) {
+            return invalid("cluster keys must be at least 16 bytes");
+        }
+        if self.request_timeout == 0 {
+            return invalid("request_timeout must be positive");
+        }
+        if self.retry_backoff == 0 || self.retry_backoff >= self.request_timeout {
+            return invalid("retry_backoff must be positive and below request_timeout");
+        }
+        Ok(())
+    }
+
+    /// Picks a random election timeout, in milliseconds.
+    pub fn election_timeout<R>(&self, rng: &mut R) -> u64 where R: Rng {
+        rng.gen_range::<u64>(self.election_timeout_min, self.election_timeout_max)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            election_timeout_min: 150,
+            election_timeout_max: 300,
+            heartbeat_interval: 50,
+            max_message_size: 1 << 20,
+            max_connections: 128,
+            connection_idle_timeout: 60000,
+            max_append_entries: 64,
+            tls: None,
+            cluster_keys: Vec::new(),
+            cluster_id: "raft".to_string(),
+            request_timeout: 5000,
+            max_redirects: 8,
+            retry_backoff: 20,
+        }
+    }
+}
+
+fn invalid(reason: &str) -> Result<()> {
+    Err(Error::Raft(ErrorKind::InvalidConfig(reason.to_string())))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::Config;
+
+    #[test]
+    fn test_default_is_valid() {
+        Config::default().validate().unwrap();
+    }
+
+    #[test]
+    fn test_election_timeout_range() {
+        let config = Config { election_timeout_min: 300, election_timeout_max: 300, ..Config::default() };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_heartbeat_below_election_timeout() {
+        let config = Config { heartbeat_interval: 100, ..Config::default() };
+        assert!(config.validate().is_err());
+        let config = Config { heartbeat_interval: 75, ..Config::default() };
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn test_limits() {
+        assert!(Config { max_message_size: 512, ..Config::default() }.validate().is_err());
+        assert!(Config { max_connections: 0, ..Config::default() }.validate().is_err());
+        assert!(Config { connection_idle_timeout: 0, ..Config::default() }.validate().is_err());
+        assert!(Config { max_append_entries: 0, ..Config::default() }.validate().is_err());
+        assert!(Config { cluster_keys: vec![b"short".to_vec()], ..Config::default() }.validate().is_err());
+        assert!(Config { cluster_id: String::new(), ..Config::default() }.validate().is_err());
+        assert!(Config { request_timeout: 0, ..Config::default() }.validate().is_err());
+        assert!(Config { retry_backoff: 5000, ..Config::default() }.validate().is_err());
+    }
+}
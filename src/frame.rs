@@ -0,0 +1,223 @@
+//! Framing of packed messages on byte streams.
+//!
+//! Each message is written as a frame: its packed length as a 4 byte big-endian integer, followed
+//! by the packed message. Frames larger than the maximum message size are rejected, so that a
+//! misbehaving peer can not make a `Server` buffer arbitrary amounts of data.
+
+use std::io::{self, Read};
+
+// Cap'n Proto
+use capnp::serialize_packed;
+use capnp::{MallocMessageBuilder, OwnedSpaceMessageReader, ReaderOptions};
+
+use {Error, ErrorKind, Result};
+
+/// The length of a frame header.
+const HEADER_LEN: usize = 4;
+
+/// Packs the message.
+pub fn pack(builder: &mut MallocMessageBuilder) -> Vec<u8> {
+    let mut message = Vec::new();
+    serialize_packed::write_message(&mut message, builder)
+        .ok().expect("writing to a Vec can not fail");
+    message
+}
+
+/// Frames a packed message.
+pub fn frame(message: &[u8], max_message_size: usize) -> Result<Vec<u8>> {
+    if message.len() > max_message_size {
+        return Err(Error::Raft(ErrorKind::MessageTooLarge(message.len())));
+    }
+    let len = message.len() as u32;
+    let mut frame = Vec::with_capacity(HEADER_LEN + message.len());
+    frame.push((len >> 24) as u8);
+    frame.push((len >> 16) as u8);
+    frame.push((len >> 8) as u8);
+    frame.push(len as u8);
+    frame.extend(message.iter().cloned());
+    Ok(frame)
+}
+
+/// Reads a single frame from a blocking reader, and decodes its message.
+pub fn read_frame<R>(reader: &mut R, max_message_size: usize) -> Result<OwnedSpaceMessageReader>
+where R: Read {
+    let header = try!(read_exactly(reader, HEADER_LEN));
+    let len = decode_len(&header);
+    if len > max_message_size {
+        return Err(Error::Raft(ErrorKind::MessageTooLarge(len)));
+    }
+    let message = try!(read_exactly(reader, len));
+    Ok(try!(serialize_packed::read_message(&mut &message[..], ReaderOptions::new())))
+}
+
+/// Reads exactly `len` bytes.
+fn read_exactly<R>(reader: &mut R, len: usize) -> Result<Vec<u8>> where R: Read {
+    let mut buf = Vec::with_capacity(len);
+    try!(reader.by_ref().take(len as u64).read_to_end(&mut buf));
+    if buf.len() < len {
+        return Err(Error::Io(io::Error::new(io::ErrorKind::ConnectionAborted,
+                                            "the stream ended in the middle of a frame")));
+    }
+    Ok(buf)
+}
+
+fn decode_len(header: &[u8]) -> usize {
+    ((header[0] as usize) << 24) | ((header[1] as usize) << 16)
+        | ((header[2] as usize) << 8) | (header[3] as usize)
+}
+
+/// Incrementally decodes frames from the data read off a non-blocking stream, which may end
+/// anywhere within a frame.
+pub struct FrameDecoder {
+    /// Data which has been read, but not yet decoded.
+    buf: Vec<u8>,
+    max_message_size: usize,
+}
+
+impl FrameDecoder {
+
+    pub fn new(max_message_size: usize) -> FrameDecoder {
+        FrameDecoder { buf: Vec::new(), max_message_size: max_message_size }
+    }
+
+    /// Adds data read from the stream.
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buf.extend(data.iter().cloned());
+    }
+
+    /// Returns the number of bytes missing from the frame being read: the rest of its header, or
+    /// the rest of its message once the header is complete. Reading no more than this keeps at
+    /// most one frame buffered. Fails as soon as the header declares an oversized message.
+    pub fn remaining(&self) -> Result<usize> {
+        if self.buf.len() < HEADER_LEN {
+            return Ok(HEADER_LEN - self.buf.len());
+        }
+        let len = decode_len(&self.buf);
+        if len > self.max_message_size {
+            return Err(Error::Raft(ErrorKind::MessageTooLarge(len)));
+        }
+        Ok((HEADER_LEN + len).saturating_sub(self.buf.len()))
+    }
+
+    /// Returns the next message, or `None` if the next frame has not been read in full yet.
+    ///
+    /// After an error the stream can not be decoded any further, and should be closed.
+    pub fn next_message(&mut self) -> Result<Option<OwnedSpaceMessageReader>> {
+        if self.buf.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let len = decode_len(&self.buf);
+        if len > self.max_message_size {
+            return Err(Error::Raft(ErrorKind::MessageTooLarge(len)));
+        }
+        if self.buf.len() < HEADER_LEN + len {
+            return Ok(None);
+        }
+        let message = try!(serialize_packed::read_message(&mut &self.buf[HEADER_LEN..HEADER_LEN + len],
+                                                          ReaderOptions::new()));
+        self.buf.drain(..HEADER_LEN + len);
+        Ok(Some(message))
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use capnp::{MessageBuilder, MessageReader, MallocMessageBuilder, OwnedSpaceMessageReader};
+
+    use frame::{frame, pack, read_frame, FrameDecoder};
+    use messages_capnp::{client_request, message};
+    use {Error, ErrorKind};
+
+    fn die_message(reason: &str) -> Vec<u8> {
+        let mut builder = MallocMessageBuilder::new_default();
+        builder.init_root::<message::Builder>().init_client_request().set_die(reason);
+        pack(&mut builder)
+    }
+
+    fn die_reason(reader: &OwnedSpaceMessageReader) -> String {
+        let message = reader.get_root::<message::Reader>().unwrap();
+        match message.which() {
+            Ok(message::Which::ClientRequest(Ok(request))) => match request.which() {
+                Ok(client_request::Which::Die(Ok(reason))) => reason.to_string(),
+                _ => panic!("Unexpected request."),
+            },
+            _ => panic!("Unexpected message."),
+        }
+    }
+
+    /// Tests that frames split at every possible point are decoded once complete.
+    #[test]
+    fn test_partial_frames() {
+        let mut data = frame(&die_message("first"), 1024).unwrap();
+        data.extend(frame(&die_message("second"), 1024).unwrap().into_iter());
+
+        let mut decoder = FrameDecoder::new(1024);
+        let mut reasons = Vec::new();
+        for byte in data.iter() {
+            decoder.extend(&[*byte]);
+            while let Some(reader) = decoder.next_message().unwrap() {
+                reasons.push(die_reason(&reader));
+            }
+        }
+        assert_eq!(vec!["first".to_string(), "second".to_string()], reasons);
+    }
+
+    /// Tests that a message larger than a default ring buffer is framed and decoded.
+    #[test]
+    fn test_large_frame() {
+        let reason: String = (0..10000).map(|n| (b'a' + (n % 26) as u8) as char).collect();
+        let data = frame(&die_message(&reason), 1 << 20).unwrap();
+        let reader = read_frame(&mut &data[..], 1 << 20).unwrap();
+        assert_eq!(reason, die_reason(&reader));
+    }
+
+    /// Tests that oversized frames are rejected on both ends.
+    #[test]
+    fn test_oversized_frame() {
+        let message = die_message("too large");
+        match frame(&message, 4) {
+            Err(Error::Raft(ErrorKind::MessageTooLarge(..))) => (),
+            _ => panic!("An oversized message was framed."),
+        }
+
+        let data = frame(&message, 1024).unwrap();
+        let mut decoder = FrameDecoder::new(4);
+        decoder.extend(&data);
+        match decoder.next_message() {
+            Err(Error::Raft(ErrorKind::MessageTooLarge(..))) => (),
+            _ => panic!("An oversized frame was decoded."),
+        }
+    }
+
+    /// Tests that the decoder asks for the rest of the frame being read, and refuses an oversized
+    /// frame once its header is read, before any of its message.
+    #[test]
+    fn test_remaining() {
+        let data = frame(&die_message("reason"), 1024).unwrap();
+        let mut decoder = FrameDecoder::new(1024);
+        assert_eq!(4, decoder.remaining().unwrap());
+        decoder.extend(&data[..2]);
+        assert_eq!(2, decoder.remaining().unwrap());
+        decoder.extend(&data[2..5]);
+        assert_eq!(data.len() - 5, decoder.remaining().unwrap());
+        decoder.extend(&data[5..]);
+        assert!(decoder.next_message().unwrap().is_some());
+        assert_eq!(4, decoder.remaining().unwrap());
+
+        let mut decoder = FrameDecoder::new(1024);
+        decoder.extend(&[0xff, 0xff, 0xff, 0xff]);
+        match decoder.remaining() {
+            Err(Error::Raft(ErrorKind::MessageTooLarge(..))) => (),
+            _ => panic!("An oversized frame was read."),
+        }
+    }
+
+    /// Tests that a malformed frame is an error.
+    #[test]
+    fn test_malformed_frame() {
+        let mut decoder = FrameDecoder::new(1024);
+        decoder.extend(&[0, 0, 0, 4, 0xff, 0xff, 0xff, 0xff]);
+        assert!(decoder.next_message().is_err());
+    }
+}
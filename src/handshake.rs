@@ -0,0 +1,167 @@
+//! The handshake which opens every connection.
+//!
+//! Before any other message, each end of a new connection sends a `Handshake` holding the
+//! protocol version it speaks, the oldest version it can speak with, the ID of its cluster, its
+//! `NodeId`, and a nonce which the other end seals its messages with (see `auth`). A connection
+//! is refused if the ends can not speak a common version, or if they belong to different
+//! clusters, so that two unrelated clusters never exchange votes.
+//!
+//! If the node has cluster keys, its handshake carries an HMAC of its cluster ID, `NodeId` and
+//! nonce, and the handshakes of the other ends must carry a valid one. The `NodeId` announced in a
+//! handshake is only authenticated this way: TLS alone proves that the other end belongs to the
+//! cluster, not which member it is.
+//!
+//! A node speaking a newer version keeps accepting the versions down to its `MIN_VERSION`, which
+//! allows a cluster to be upgraded one node at a time.
+
+use capnp::{MessageBuilder, MessageReader, MallocMessageBuilder, OwnedSpaceMessageReader};
+
+use auth;
+use frame::pack;
+use messages_capnp::handshake;
+use {Error, ErrorKind, NodeId, Result};
+
+/// The protocol version spoken by this node.
+pub const VERSION: u32 = 3;
+
+/// The oldest protocol version this node can speak with. Version 1 sealed messages without a
+/// nonce, and version 2 did not authenticate the handshake.
+pub const MIN_VERSION: u32 = 3;
+
+/// Returns the packed handshake of the node, announcing the nonce of the connection, and
+/// authenticated with the first of the cluster keys, if there are any.
+pub fn handshake(cluster_id: &str, id: NodeId, nonce: &[u8], keys: &[Vec<u8>]) -> Vec<u8> {
+    let mut builder = MallocMessageBuilder::new_default();
+    {
+        let mut handshake = builder.init_root::<handshake::Builder>();
+        handshake.set_version(VERSION);
+        handshake.set_min_version(MIN_VERSION);
+        handshake.set_cluster_id(cluster_id);
+        handshake.set_sender(id.as_bytes());
+        handshake.set_nonce(nonce);
+        if let Some(key) = keys.first() {
+            handshake.set_mac(&auth::handshake_mac(key, cluster_id, id.as_bytes(), nonce));
+        }
+    }
+    pack(&mut builder)
+}
+
+/// Checks the handshake received from the other end of a connection, and returns its `NodeId`
+/// along with the nonce it announced. If there are cluster keys, the handshake must be
+/// authenticated by one of them.
+pub fn verify(cluster_id: &str, keys: &[Vec<u8>], reader: &OwnedSpaceMessageReader)
+              -> Result<(NodeId, Vec<u8>)> {
+    let handshake = try!(reader.get_root::<handshake::Reader>());
+    let version = handshake.get_version();
+    if version < MIN_VERSION || handshake.get_min_version() > VERSION {
+        return Err(Error::Raft(ErrorKind::IncompatibleVersion(version)));
+    }
+    let their_cluster_id = try!(handshake.get_cluster_id());
+    if their_cluster_id != cluster_id {
+        return Err(Error::Raft(ErrorKind::ForeignCluster(their_cluster_id.to_string())));
+    }
+    let sender = try!(handshake.get_sender());
+    let nonce = try!(handshake.get_nonce());
+    try!(auth::verify_handshake(keys, cluster_id, sender, nonce, try!(handshake.get_mac())));
+    let id = try!(NodeId::from_bytes(sender).ok_or(Error::Raft(ErrorKind::BadResponse)));
+    Ok((id, nonce.to_vec()))
+}
+
+#[cfg(test)]
+mod test {
+
+    use capnp::serialize_packed;
+    use capnp::{MessageBuilder, MessageReader, MallocMessageBuilder, OwnedSpaceMessageReader,
+                ReaderOptions};
+
+    use frame::pack;
+    use handshake::{handshake, verify, MIN_VERSION, VERSION};
+    use messages_capnp;
+    use {Error, ErrorKind, NodeId};
+
+    fn read(message: &[u8]) -> OwnedSpaceMessageReader {
+        serialize_packed::read_message(&mut &message[..], ReaderOptions::new()).unwrap()
+    }
+
+    fn versioned(version: u32, min_version: u32) -> Vec<u8> {
+        let mut builder = MallocMessageBuilder::new_default();
+        {
+            let mut handshake = builder.init_root::<messages_capnp::handshake::Builder>();
+            handshake.set_version(version);
+            handshake.set_min_version(min_version);
+            handshake.set_cluster_id("cluster");
+            handshake.set_sender(NodeId::new().as_bytes());
+        }
+        pack(&mut builder)
+    }
+
+    #[test]
+    fn test_handshake() {
+        let id = NodeId::new();
+        let message = handshake("cluster", id, b"nonce", &[]);
+        let (peer, nonce) = verify("cluster", &[], &read(&message)).unwrap();
+        assert_eq!(id, peer);
+        assert_eq!(b"nonce".to_vec(), nonce);
+    }
+
+    #[test]
+    fn test_foreign_cluster() {
+        let message = handshake("another cluster", NodeId::new(), b"nonce", &[]);
+        match verify("cluster", &[], &read(&message)) {
+            Err(Error::Raft(ErrorKind::ForeignCluster(ref id))) if id == "another cluster" => (),
+            _ => panic!("A foreign cluster was accepted."),
+        }
+    }
+
+    /// Tests that nodes accept each other as long as they have a version in common.
+    #[test]
+    fn test_versions() {
+        assert!(verify("cluster", &[], &read(&versioned(VERSION + 1, MIN_VERSION))).is_ok());
+        match verify("cluster", &[], &read(&versioned(VERSION + 1, VERSION + 1))) {
+            Err(Error::Raft(ErrorKind::IncompatibleVersion(..))) => (),
+            _ => panic!("An incompatible version was accepted."),
+        }
+        match verify("cluster", &[], &read(&versioned(MIN_VERSION - 1, MIN_VERSION - 1))) {
+            Err(Error::Raft(ErrorKind::IncompatibleVersion(..))) => (),
+            _ => panic!("An incompatible version was accepted."),
+        }
+    }
+
+    /// Tests that with cluster keys, only a handshake authenticated by one of them is accepted,
+    /// and that the authenticated node ID can not be swapped for another.
+    #[test]
+    fn test_authenticated() {
+        let keys = vec![b"the cluster key!".to_vec()];
+        let id = NodeId::new();
+        let signed = handshake("cluster", id, b"nonce", &keys);
+        let (peer, _) = verify("cluster", &keys, &read(&signed)).unwrap();
+        assert_eq!(id, peer);
+
+        let unsigned = handshake("cluster", id, b"nonce", &[]);
+        let foreign = handshake("cluster", id, b"nonce", &[b"another key".to_vec()]);
+        for message in &[unsigned, foreign] {
+            match verify("cluster", &keys, &read(message)) {
+                Err(Error::Raft(ErrorKind::Unauthenticated)) => (),
+                _ => panic!("An unauthenticated handshake was accepted."),
+            }
+        }
+
+        // A handshake which claims another ID with the MAC of a genuine one.
+        let mut builder = MallocMessageBuilder::new_default();
+        {
+            let genuine = read(&signed);
+            let genuine = genuine.get_root::<messages_capnp::handshake::Reader>().unwrap();
+            let mut spoofed = builder.init_root::<messages_capnp::handshake::Builder>();
+            spoofed.set_version(VERSION);
+            spoofed.set_min_version(MIN_VERSION);
+            spoofed.set_cluster_id("cluster");
+            spoofed.set_sender(NodeId::new().as_bytes());
+            spoofed.set_nonce(b"nonce");
+            spoofed.set_mac(genuine.get_mac().unwrap());
+        }
+        match verify("cluster", &keys, &read(&pack(&mut builder))) {
+            Err(Error::Raft(ErrorKind::Unauthenticated)) => (),
+            _ => panic!("A spoofed handshake was accepted."),
+        }
+    }
+}
@@ -43,57 +43,85 @@
 //!
 //!     // TODO
 //!
-#![feature(buf_stream)]
-
 extern crate capnp;
 extern crate mio;
+extern crate openssl;
 extern crate rand;
 extern crate rustc_serialize;
+extern crate time;
 extern crate uuid;
 #[macro_use] extern crate log;
 
 pub mod state_machine;
 pub mod store;
 
+mod address_book;
+mod auth;
+mod client;
+mod config;
+mod frame;
+mod handshake;
+mod status;
+mod tls;
+pub mod transport;
+
 mod server;
 mod replica;
+mod scheduler;
 mod state;
+#[cfg(test)] mod simulation;
+
+pub use client::{Proposal, RaftClient};
+pub use config::Config;
+pub use status::{Event, PeerStatus, Role, Status};
+pub use tls::TlsConfig;
 
 mod messages_capnp {
     #![allow(dead_code)]
     include!(concat!(env!("OUT_DIR"), "/messages_capnp.rs"));
 }
 
-use std::{io, ops};
-use std::collections::HashSet;
+use std::{error, fmt, io, ops, result};
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
-use std::net::TcpStream;
 use std::str::FromStr;
-use std::io::BufStream;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::thread::JoinHandle;
 
+use mio::EventLoopSender;
+use openssl::ssl::error::SslError;
 use rustc_serialize::Encodable;
+use uuid::Uuid;
 // Data structures.
 use store::Store;
+use scheduler::EventLoopScheduler;
 use server::Server;
 use state_machine::StateMachine;
+use transport::{Notification, TcpTransport, Transport};
 
 // Cap'n Proto
 use capnp::serialize_packed;
 use capnp::{MessageBuilder, MessageReader, ReaderOptions, MallocMessageBuilder};
-use messages_capnp::{
-    client_request,
-    client_response,
-};
+use messages_capnp::configuration;
 
 /// This is the primary interface with a `Server` in the cluster.
 ///
 /// Note: Creating a new `Raft` client will, for now, automatically spawn a `Server` with the
 /// relevant parameters. This may be changed in the future. This is based on the assumption that
 /// any consuming application interacting with a Raft cluster will also be a participant.
+/// Applications which only interact with a cluster, without being a participant, use a
+/// `RaftClient` instead.
 pub struct Raft {
-    current_leader: Option<SocketAddr>,
-    related_server: SocketAddr, // Not Server because we move that to another thread.
-    cluster_members: HashSet<SocketAddr>,
+    /// The client which sends the requests, asking the related `Server` for the leader first.
+    client: RaftClient,
+    /// The channel to the event loop of the related `Server`.
+    notifier: EventLoopSender<Notification>,
+    /// The thread of the related `Server`, until it is shut down.
+    thread: Option<JoinHandle<()>>,
+    /// Set while the thread of the related `Server` runs.
+    running: Arc<AtomicBool>,
 }
 
 impl Raft {
@@ -101,142 +129,345 @@ impl Raft {
     /// `Raft` may not necessarily interact with the cooreponding `Server`, it will interact with
     /// the `Leader` of a cluster in almost all cases.
     /// *Note:* All requests are blocking, by design from the Raft paper.
-    pub fn new<S, M>(addr: SocketAddr,
-                     cluster_members: HashSet<SocketAddr>,
+    ///
+    /// Requests to the related `Server` are delivered through its event loop, without a TCP
+    /// connection.
+    ///
+    /// Returns once the related `Server` is listening on `addr`. Fails if the `Config` is not
+    /// valid, if its TLS certificates can not be loaded, or if the `Server` can not be started,
+    /// for instance because `addr` is already in use.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The persistent ID of the new node.
+    /// * `addr` - The address on which the new node accepts connections.
+    /// * `peers` - The ID and address of every other node in the cluster.
+    /// * `store` - The persistent log store.
+    /// * `state_machine` - The client state machine to which client commands will be applied.
+    /// * `config` - The timing and resource limits of the node.
+    pub fn new<S, M>(id: NodeId,
+                     addr: SocketAddr,
+                     peers: HashMap<NodeId, SocketAddr>,
                      store: S,
-                     state_machine: M)
-                     -> Raft
+                     state_machine: M,
+                     config: Config)
+                     -> Result<Raft>
     where S: Store, M: StateMachine {
-        debug!("Starting Raft on {}", addr);
-        let mut peers = cluster_members.clone();
-        peers.remove(&addr);
-        Server::<S, M>::spawn(addr, peers, store, state_machine);
+        try!(config.validate());
+        let transport = try!(TcpTransport::new(id, addr, &config));
+        Raft::with_transport(id, addr, peers, store, state_machine, transport, config)
+    }
+
+    /// Like `new()`, but the related `Server` exchanges its messages over the provided transport
+    /// rather than over TCP.
+    ///
+    /// The requests of the `Raft` to the other nodes, such as an append redirected to the leader,
+    /// are still sent over TCP, so a node on a `ChannelTransport` only answers the requests which
+    /// its related `Server` handles itself.
+    pub fn with_transport<S, M, T>(id: NodeId,
+                                   addr: SocketAddr,
+                                   peers: HashMap<NodeId, SocketAddr>,
+                                   store: S,
+                                   state_machine: M,
+                                   transport: T,
+                                   config: Config)
+                                   -> Result<Raft>
+    where S: Store, M: StateMachine, T: Transport {
+        debug!("Starting Raft {} on {}", id, addr);
+        try!(config.validate());
+        // The related `Server` is asked for the leader first.
+        let mut cluster_members = vec![addr];
+        cluster_members.extend(peers.values().cloned());
+        let mut client = try!(client::new(id, cluster_members, config.clone()));
+        let (notifier, thread, running) =
+            try!(Server::<S, M, T, EventLoopScheduler>::spawn(id, addr, peers, store,
+                                                              state_machine, transport, config));
+        client::attach(&mut client, notifier.clone());
         // Store relevant information.
-        Raft {
-            current_leader: None,
-            related_server: addr,
-            cluster_members: cluster_members,
-        }
+        Ok(Raft {
+            client: client,
+            notifier: notifier,
+            thread: Some(thread),
+            running: running,
+        })
     }
 
-    /// Appends an entry to the replicated log. This will only return once it's properly replicated
-    /// to a majority of nodes.
-    pub fn append(&mut self, entry: &[u8]) -> Result<()> {
-        let mut message = MallocMessageBuilder::new_default();
-        if self.current_leader.is_none() { try!(self.refresh_leader()); }
-        {
-            let mut client_req = message.init_root::<client_request::Builder>();
-            client_req.set_append(entry)
-        }
-        // We know current leader `is_some()` because `refresh_leader()` didn't fail.
-        let mut unbuffered_socket = try!(TcpStream::connect(self.current_leader.unwrap())); // TODO: Handle Leader
-        let mut socket = BufStream::new(unbuffered_socket);
-        try!(serialize_packed::write_message(&mut socket, &mut message));
-
-        // Wait for a response.
-
-        let response = try!(serialize_packed::read_message(&mut socket, ReaderOptions::new()));
-        let client_res = try!(response.get_root::<client_response::Reader>());
-        // Set the current leader.
-        match try!(client_res.which()) {
-            client_response::Which::Success(()) => {
-                // It worked!
-                Ok(())
-            },
-            client_response::Which::NotLeader(Ok(leader_bytes)) => {
-                self.current_leader = match SocketAddr::from_str(leader_bytes) {
-                    Ok(socket) => Some(socket),
-                    Err(_) => return Err(Error::Raft(ErrorKind::BadResponse))
-                };
-                // Try again.
-                self.append(entry)
+    /// Shuts down the related `Server` and waits for it to stop. The `Server` flushes its pending
+    /// responses, closes its connections and syncs its `Store` before stopping. Shutting down an
+    /// already stopped `Server`, for instance after a `die()`, only waits for its thread.
+    ///
+    /// This is also done when the `Raft` is dropped, ignoring any error.
+    pub fn shutdown(&mut self) -> Result<()> {
+        match self.thread.take() {
+            Some(thread) => {
+                // The event loop is already gone if the `Server` has stopped by itself.
+                let _ = self.notifier.send(Notification::Shutdown);
+                thread.join().map_err(|_| Error::Raft(ErrorKind::RelatedNodeDown))
             },
-            _ => unimplemented!(),
+            None => Ok(()),
         }
     }
 
+    /// Replaces the cluster keys of this `Raft` and its related `Server`, without restarting it.
+    /// See `Config::cluster_keys` for how to rotate the keys of a cluster.
+    pub fn set_cluster_keys(&mut self, keys: Vec<Vec<u8>>) -> Result<()> {
+        try!(self.check_running());
+        try!(self.client.set_cluster_keys(keys.clone()));
+        self.notifier.send(Notification::ClusterKeys(keys))
+            .map_err(|_| Error::Raft(ErrorKind::RelatedNodeDown))
+    }
+
+    /// Subscribes to the changes to the role, term and known leader of the related `Server`. The
+    /// current role, term and leader are delivered first, followed by each change to them, until
+    /// the `Server` stops or halts, or the receiver is dropped. The receiver fails once the
+    /// `Server` has stopped or halted.
+    pub fn subscribe(&mut self) -> Result<Receiver<Event>> {
+        try!(self.check_running());
+        let (sender, receiver) = mpsc::channel();
+        try!(self.notifier.send(Notification::Subscribe(sender))
+                 .map_err(|_| Error::Raft(ErrorKind::RelatedNodeDown)));
+        Ok(receiver)
+    }
+
+    /// Appends an entry to the replicated log. See `RaftClient::append()`.
+    pub fn append(&mut self, entry: &[u8]) -> Result<()> {
+        try!(self.check_running());
+        self.client.append(entry)
+    }
+
+    /// Appends the entries to the replicated log together, and returns the log index of each. See
+    /// `RaftClient::append_batch()`.
+    pub fn append_batch(&mut self, entries: &[&[u8]]) -> Result<Vec<LogIndex>> {
+        try!(self.check_running());
+        self.client.append_batch(entries)
+    }
+
+    /// Sends an entry to the leader to be appended to the replicated log, without waiting for it
+    /// to be replicated. See `RaftClient::propose()`.
+    pub fn propose(&mut self, entry: &[u8]) -> Result<Proposal> {
+        try!(self.check_running());
+        self.client.propose(entry)
+    }
+
     /// Kills the node. Should only really be used for testing purposes.
     /// Accepts a `SocketAddr` because if you're going to kill a node you should be able to pick
     /// your victim.
     pub fn die(&mut self, target: SocketAddr, reason: String) -> Result<()> {
-        if !self.cluster_members.contains(&target) {
-            return Err(Error::Raft(ErrorKind::NotInCluster))
-        }
-        let mut message = MallocMessageBuilder::new_default();
-        {
-            let mut client_req = message.init_root::<client_request::Builder>();
-            client_req.set_die(&reason)
-        }
-        // We know current leader `is_some()` because `refresh_leader()` didn't fail.
-        let mut unbuffered_socket = try!(TcpStream::connect(self.current_leader.unwrap())); // TODO: Handle Leader
-        let mut socket = BufStream::new(unbuffered_socket);
-        try!(serialize_packed::write_message(&mut socket, &mut message));
-
-        // Wait for a response.
-        let response = try!(serialize_packed::read_message(&mut socket, ReaderOptions::new()));
-        let client_res = try!(response.get_root::<client_response::Reader>());
-        // Set the current leader.
-        match try!(client_res.which()) {
-            client_response::Which::Success(()) => Ok(()),
-            _ => unimplemented!(),
-        }
+        try!(self.check_running());
+        self.client.die(target, reason)
+    }
+
+    /// Returns a snapshot of the state of the node at the provided address. See
+    /// `RaftClient::status()`.
+    pub fn status(&mut self, target: SocketAddr) -> Result<Status> {
+        try!(self.check_running());
+        self.client.status(target)
     }
 
     /// This function will force the `Raft` interface to refresh it's knowledge of the leader from
-    /// The cooresponding `Server` running alongside it.
+    /// The cooresponding `Server` running alongside it, or from the other members if it does not
+    /// know the leader.
     pub fn refresh_leader(&mut self) -> Result<()> {
-        let mut message = MallocMessageBuilder::new_default();
-        {
-            let mut client_req = message.init_root::<client_request::Builder>();
-            client_req.set_leader_refresh(());
+        try!(self.check_running());
+        self.client.refresh_leader()
+    }
+
+    /// Fails with `ErrorKind::Shutdown` once the related `Server` has been shut down, or with
+    /// `ErrorKind::RelatedNodeDown` once it has stopped by itself, for instance after a `die()`
+    /// or a panic.
+    fn check_running(&self) -> Result<()> {
+        if self.thread.is_none() {
+            Err(Error::Raft(ErrorKind::Shutdown))
+        } else if !self.running.load(Ordering::SeqCst) {
+            Err(Error::Raft(ErrorKind::RelatedNodeDown))
+        } else {
+            Ok(())
         }
-        let mut unbuffered_socket = try!(TcpStream::connect(self.related_server));
-        let mut socket = BufStream::new(unbuffered_socket);
-        try!(serialize_packed::write_message(&mut socket, &mut message));
-
-        // Wait for a response.
-        let response = try!(serialize_packed::read_message(&mut socket, ReaderOptions::new()));
-        let client_res = try!(response.get_root::<client_response::Reader>());
-        // Set the current leader.
-        self.current_leader = match try!(client_res.which()) {
-            client_response::Which::NotLeader(Ok(leader_bytes)) => {
-                match SocketAddr::from_str(leader_bytes) {
-                    Ok(socket) => Some(socket),
-                    Err(_) => return Err(Error::Raft(ErrorKind::BadResponse))
-                }
-            },
-            _ => unimplemented!(),
-        };
-        Ok(())
+    }
+}
+
+impl Drop for Raft {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
     }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 /// raft::Errors are the composed variety of errors that can originate from the various libraries.
-/// With the exception of the `Raft` variant these are generated from `try!()` macros invoking
-/// on `io::Error` or `capnp::Error` by using
+/// With the exception of the `Raft`, `Store` and `StateMachine` variants these are generated from
+/// `try!()` macros invoking on `io::Error`, `capnp::Error` or `SslError` by using
 /// [`FromError`](https://doc.rust-lang.org/std/error/#the-fromerror-trait).
+///
+/// The `Store` and `StateMachine` variants wrap the errors returned by the consuming application's
+/// implementations of those traits.
 #[derive(Debug)]
 pub enum Error {
     CapnProto(capnp::Error),
     SchemaError(capnp::NotInSchema),
     Io(io::Error),
+    Ssl(SslError),
     Raft(ErrorKind),
+    Store(Box<error::Error + Send>),
+    StateMachine(Box<error::Error + Send>),
+}
+
+impl Error {
+    /// Wraps an error returned by a `Store`.
+    fn store<E>(error: E) -> Error where E: error::Error + Send + 'static {
+        Error::Store(Box::new(error))
+    }
+
+    /// Wraps an error returned by a `StateMachine`.
+    fn state_machine<E>(error: E) -> Error where E: error::Error + Send + 'static {
+        Error::StateMachine(Box::new(error))
+    }
 }
 
-/// Currently, this can only be:
+/// The errors raised by Raft itself:
 ///
-/// * `RelatedNodeDown` - When the related Server is known to be down.
-/// * `CannotProceed` - When the related Server cannot proceed due to more than a majority of
-///                     nodes being unavailable.
-/// TODO: Hook these up.
+/// * `RelatedNodeDown` - When the related Server has stopped by itself, for instance after it
+///                       panicked or was killed with `die()`.
+/// * `CannotProceed` - When the cluster cannot proceed due to more than a majority of nodes
+///                     being unavailable, so that no leader can be found before
+///                     `Config::request_timeout` has passed.
+/// * `NotLeader` - When a node which is not the leader receives an append. The address of the
+///                 leader is included.
+/// * `TooManyRedirects` - When a request is redirected to another leader more than
+///                        `Config::max_redirects` times.
+/// * `Timeout` - When a request is not answered before `Config::request_timeout` has passed.
+/// * `OutcomeUnknown` - When the connection over which a request was sent fails before the request
+///                      is answered. The request may or may not have taken effect.
+/// * `Shutdown` - When a `Raft` is used after its related Server has been shut down.
+/// * `NotInCluster` - When a request targets an address which is not a member of the cluster.
+/// * `BadResponse` - When a node answers a request with an unexpected or malformed response.
+/// * `BadRequest` - When a node can not decode a request, or does not know it. The reason it
+///                  reported is included.
+/// * `Halted` - When the Server handling the request has halted after a `Store` or
+///              `StateMachine` error. The reason it reported is included.
+/// * `BadConfiguration` - When a configuration log entry can not be decoded.
+/// * `StateMachineAhead` - When the `StateMachine` has applied entries beyond the end of the log
+///                         in the `Store` at startup. The index applied by the state machine and
+///                         the latest index of the log are included.
+/// * `InvalidConfig` - When a `Config` is rejected by `Config::validate()`. The reason is included.
+/// * `MessageTooLarge` - When a message exceeds the maximum message size. Its size is included.
+/// * `ConnectionLimit` - When a connection can not be opened because the maximum number of
+///                       connections is reached.
+/// * `Unauthenticated` - When a message is not authenticated by any of the cluster keys.
+/// * `IncompatibleVersion` - When the other end of a connection speaks an incompatible protocol
+///                           version. Its version is included.
+/// * `ForeignCluster` - When the other end of a connection belongs to another cluster. Its
+///                      cluster ID is included.
 #[derive(Debug)]
 pub enum ErrorKind {
     RelatedNodeDown,
     CannotProceed,
+    NotLeader(SocketAddr),
+    TooManyRedirects,
+    Timeout,
+    OutcomeUnknown,
+    Shutdown,
     NotInCluster,
     BadResponse,
+    BadRequest(String),
+    Halted(String),
+    BadConfiguration,
+    StateMachineAhead(LogIndex, LogIndex),
+    InvalidConfig(String),
+    MessageTooLarge(usize),
+    ConnectionLimit,
+    Unauthenticated,
+    IncompatibleVersion(u32),
+    ForeignCluster(String),
+}
+
+impl ErrorKind {
+
+    fn description(&self) -> &str {
+        match *self {
+            ErrorKind::RelatedNodeDown => "the related server is down",
+            ErrorKind::CannotProceed => "the cluster cannot proceed",
+            ErrorKind::NotLeader(_) => "the node is not the leader",
+            ErrorKind::TooManyRedirects => "the request was redirected too many times",
+            ErrorKind::Timeout => "the request timed out",
+            ErrorKind::OutcomeUnknown => "the request was sent, but its outcome is unknown",
+            ErrorKind::Shutdown => "the related server has been shut down",
+            ErrorKind::NotInCluster => "the address is not a member of the cluster",
+            ErrorKind::BadResponse => "the node sent a bad response",
+            ErrorKind::BadRequest(_) => "the node rejected the request",
+            ErrorKind::Halted(_) => "the node has halted",
+            ErrorKind::BadConfiguration => "a configuration entry can not be decoded",
+            ErrorKind::StateMachineAhead(..) => "the state machine is ahead of the log",
+            ErrorKind::InvalidConfig(_) => "the config is invalid",
+            ErrorKind::MessageTooLarge(_) => "the message is too large",
+            ErrorKind::ConnectionLimit => "the connection limit is reached",
+            ErrorKind::Unauthenticated => "the message is not authenticated",
+            ErrorKind::IncompatibleVersion(_) => "the node speaks an incompatible protocol version",
+            ErrorKind::ForeignCluster(_) => "the node belongs to another cluster",
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorKind::NotLeader(leader) => write!(fmt, "{} (the leader is {})", self.description(), leader),
+            ErrorKind::BadRequest(ref reason) => write!(fmt, "{}: {}", self.description(), reason),
+            ErrorKind::Halted(ref reason) => write!(fmt, "{}: {}", self.description(), reason),
+            ErrorKind::InvalidConfig(ref reason) => write!(fmt, "{}: {}", self.description(), reason),
+            ErrorKind::StateMachineAhead(applied, latest) => {
+                write!(fmt, "{} (applied {:?}, log ends at {:?})",
+                       self.description(), applied, latest)
+            },
+            ErrorKind::MessageTooLarge(size) => write!(fmt, "{} ({} bytes)", self.description(), size),
+            ErrorKind::IncompatibleVersion(version) => {
+                write!(fmt, "{} (version {})", self.description(), version)
+            },
+            ErrorKind::ForeignCluster(ref cluster_id) => {
+                write!(fmt, "{} (cluster {})", self.description(), cluster_id)
+            },
+            _ => fmt.write_str(self.description()),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::CapnProto(ref error) => write!(fmt, "Cap'n Proto error: {}", error),
+            Error::SchemaError(ref error) => write!(fmt, "Cap'n Proto schema error: {:?}", error),
+            Error::Io(ref error) => write!(fmt, "IO error: {}", error),
+            Error::Ssl(ref error) => write!(fmt, "TLS error: {}", error),
+            Error::Raft(ref kind) => write!(fmt, "Raft error: {}", kind),
+            Error::Store(ref error) => write!(fmt, "store error: {}", error),
+            Error::StateMachine(ref error) => write!(fmt, "state machine error: {}", error),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::CapnProto(ref error) => error.description(),
+            Error::SchemaError(_) => "the message does not match the schema",
+            Error::Io(ref error) => error.description(),
+            Error::Ssl(ref error) => error.description(),
+            Error::Raft(ref kind) => kind.description(),
+            Error::Store(ref error) => error.description(),
+            Error::StateMachine(ref error) => error.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::CapnProto(ref error) => Some(error),
+            Error::Io(ref error) => Some(error),
+            Error::Ssl(ref error) => Some(error),
+            Error::Store(ref error) => Some(&**error),
+            Error::StateMachine(ref error) => Some(&**error),
+            Error::SchemaError(_) | Error::Raft(_) => None,
+        }
+    }
 }
 
 impl From<io::Error> for Error {
@@ -245,6 +476,12 @@ impl From<io::Error> for Error {
     }
 }
 
+impl From<SslError> for Error {
+    fn from(err: SslError) -> Error {
+        Error::Ssl(err)
+    }
+}
+
 impl From<capnp::Error> for Error {
     fn from(err: capnp::Error) -> Error {
         Error::CapnProto(err)
@@ -283,6 +520,112 @@ impl ops::Sub<u64> for Term {
     }
 }
 
+/// The kind of a log entry. Only `Application` entries are applied to the `StateMachine`; the
+/// other kinds carry Raft's own metadata. More kinds may be added in the future.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, RustcEncodable, RustcDecodable)]
+pub enum EntryKind {
+    /// A command appended by a client, which is applied to the `StateMachine` once committed.
+    Application,
+    /// An empty entry appended by a newly elected leader.
+    Noop,
+    /// A change to the cluster configuration.
+    Configuration,
+}
+
+/// The membership of a cluster.
+///
+/// A configuration is replicated through the log in `Configuration` entries, and the latest one
+/// is persisted by the `Store` so that it survives restarts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Configuration {
+    /// The IDs of every member of the cluster.
+    pub members: HashSet<NodeId>,
+}
+
+impl Configuration {
+
+    /// Creates a new `Configuration` with the provided members.
+    pub fn new(members: HashSet<NodeId>) -> Configuration {
+        Configuration { members: members }
+    }
+
+    /// Encodes the configuration as the payload of a `Configuration` log entry.
+    fn to_entry(&self) -> Vec<u8> {
+        let mut message = MallocMessageBuilder::new_default();
+        {
+            let configuration = message.init_root::<configuration::Builder>();
+            let mut members = configuration.init_members(self.members.len() as u32);
+            for (n, member) in self.members.iter().enumerate() {
+                members.set(n as u32, member.as_bytes());
+            }
+        }
+        let mut entry = Vec::new();
+        serialize_packed::write_message(&mut entry, &mut message)
+            .ok().expect("writing to a Vec can not fail");
+        entry
+    }
+
+    /// Decodes the configuration from the payload of a `Configuration` log entry.
+    fn from_entry(mut entry: &[u8]) -> Result<Configuration> {
+        let message = try!(serialize_packed::read_message(&mut entry, ReaderOptions::new()));
+        let members = try!(try!(message.get_root::<configuration::Reader>()).get_members());
+        let mut configuration = Configuration::new(HashSet::new());
+        for n in 0..members.len() {
+            match NodeId::from_bytes(try!(members.get(n))) {
+                Some(member) => configuration.members.insert(member),
+                None => return Err(Error::Raft(ErrorKind::BadConfiguration)),
+            };
+        }
+        Ok(configuration)
+    }
+}
+
+/// The persistent identity of a node.
+///
+/// A node is known to the rest of the cluster by its ID rather than by its address, so that it
+/// keeps its identity when its address changes. Generate the ID with `NodeId::new()` when the node
+/// first joins a cluster, and keep it alongside the node's `Store`.
+#[derive(Copy, Clone, Hash, PartialEq, Eq)]
+pub struct NodeId(Uuid);
+
+impl NodeId {
+
+    /// Creates a new, random `NodeId`.
+    pub fn new() -> NodeId {
+        NodeId(Uuid::new_v4())
+    }
+
+    /// Returns the 16 byte representation of the ID.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+
+    /// Creates an ID from its 16 byte representation, or returns `None` if the bytes are not
+    /// exactly 16 bytes long.
+    pub fn from_bytes(bytes: &[u8]) -> Option<NodeId> {
+        Uuid::from_bytes(bytes).map(NodeId)
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.0.to_hyphenated_string())
+    }
+}
+
+impl fmt::Debug for NodeId {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "NodeId({})", self.0.to_hyphenated_string())
+    }
+}
+
+impl FromStr for NodeId {
+    type Err = uuid::ParseError;
+    fn from_str(s: &str) -> result::Result<NodeId, uuid::ParseError> {
+        Uuid::parse_str(s).map(NodeId)
+    }
+}
+
 /// The index of a log entry.
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, RustcEncodable, RustcDecodable)]
 pub struct LogIndex(u64);
@@ -1,8 +1,10 @@
 use std::collections::HashSet;
-use std::{cmp, fmt};
+use std::{cmp, fmt, mem};
 use std::net::SocketAddr;
 
-use {LogIndex, Term};
+use {Config, Configuration, EntryKind, Error, ErrorKind, Event, LogIndex, NodeId, Result, Role, Term};
+use address_book::AddressBook;
+use messages_capnp::EntryKind as WireEntryKind;
 use messages_capnp::{
     append_entries_request,
     append_entries_response,
@@ -10,7 +12,9 @@ use messages_capnp::{
     client_response,
     request_vote_request,
     request_vote_response,
+    status,
 };
+use messages_capnp::Role as WireRole;
 use state::{ReplicaState, LeaderState, CandidateState, FollowerState};
 use state_machine::StateMachine;
 use store::Store;
@@ -21,13 +25,30 @@ pub struct Broadcast;
 /// Should respond to the sender.
 pub struct Emit;
 
+/// The room left in a message for everything but the entries of an AppendEntries request: the
+/// header of the request, the sender, the seal and the framing.
+const MESSAGE_OVERHEAD: usize = 1024;
+
+/// The room taken in a message by an entry besides its data.
+const ENTRY_OVERHEAD: usize = 32;
+
+/// The outcome of a client append.
+pub enum Append {
+    /// The entries were appended to the log in the term, the last of them at the index. The
+    /// AppendEntries request, which holds the first of them, should be sent to every peer, and the
+    /// client answered once the entries are applied.
+    Proposed(LogIndex, Term),
+    /// The replica is not the leader. The client response should be sent back.
+    Redirect,
+}
+
 /// A replica of a Raft distributed state machine. A Raft replica controls a client state machine,
 /// to which it applies commands in a globally consistent order.
 pub struct Replica<S, M> {
-    /// The network address of this `Replica`.
-    addr: SocketAddr,
-    /// The network addresses of the other `Replica`s in the Raft cluster.
-    peers: HashSet<SocketAddr>,
+    /// The ID of this `Replica`.
+    id: NodeId,
+    /// The IDs of the other `Replica`s in the Raft cluster.
+    peers: HashSet<NodeId>,
 
     /// The persistent log store.
     store: S,
@@ -40,6 +61,10 @@ pub struct Replica<S, M> {
     last_applied: LogIndex,
     /// Whether this replica should campaign after the next election timeout.
     should_campaign: bool,
+    /// The maximum number of entries to send in a single AppendEntries request.
+    max_append_entries: u64,
+    /// The maximum size of a message, which an AppendEntries request must fit in.
+    max_message_size: usize,
 
     /// The current state of the `Replica` (`Leader`, `Candidate`, or `Follower`).
     state: ReplicaState,
@@ -49,66 +74,114 @@ pub struct Replica<S, M> {
     candidate_state: CandidateState,
     /// State necessary while a `Follower`. Should not be used otherwise.
     follower_state: FollowerState,
+
+    /// The reason this replica halted, if it has. A replica halts after a `Store` or
+    /// `StateMachine` error, after which it no longer participates in the cluster.
+    halted: Option<String>,
+
+    /// The role, term and leader as of the latest recorded change.
+    reported: (Role, Term, Option<NodeId>),
+    /// The changes to the role, term and leader recorded since the last call to `changes()`.
+    events: Vec<Event>,
 }
 
 impl <S, M> Replica<S, M> where S: Store, M: StateMachine {
 
-    pub fn new(addr: SocketAddr,
-               peers: HashSet<SocketAddr>,
-               store: S,
-               state_machine: M)
-               -> Replica<S, M> {
-        let leader_state = LeaderState::new(store.latest_log_index().unwrap(), &peers);
-        Replica {
-            addr: addr,
+    /// Creates a new `Replica`.
+    ///
+    /// The provided `peers` are only used to bootstrap a new cluster. If the `Store` holds a
+    /// cluster configuration, for instance because the replica is restarting, the peers are taken
+    /// from it instead.
+    pub fn new(id: NodeId,
+               peers: HashSet<NodeId>,
+               mut store: S,
+               state_machine: M,
+               config: &Config)
+               -> Result<Replica<S, M>> {
+        let uncommitted = try!(store.uncommitted_configuration().map_err(Error::store));
+        let committed = try!(store.committed_configuration().map_err(Error::store));
+        let peers = match uncommitted.or(committed) {
+            // The latest configuration in the log is in effect, whether or not it is committed.
+            Some((index, configuration)) => {
+                info!("Replica({}): Using the stored configuration from index {:?}.", id, index);
+                configuration.members.into_iter().filter(|&member| member != id).collect()
+            },
+            None => {
+                let mut members = peers.clone();
+                members.insert(id);
+                try!(store.set_committed_configuration(LogIndex::from(0), Configuration::new(members))
+                          .map_err(Error::store));
+                peers
+            },
+        };
+
+        let latest_log_index = try!(store.latest_log_index().map_err(Error::store));
+        let current_term = try!(store.current_term().map_err(Error::store));
+        let leader_state = LeaderState::new(latest_log_index, &peers);
+        // Entries applied to the state machine before a restart are known to be committed, so
+        // resume from there instead of applying them a second time.
+        let last_applied = try!(state_machine.last_applied().map_err(Error::state_machine));
+        if last_applied > latest_log_index {
+            return Err(Error::Raft(ErrorKind::StateMachineAhead(last_applied, latest_log_index)));
+        }
+        Ok(Replica {
+            id: id,
             peers: peers,
             store: store,
             state_machine: state_machine,
-            commit_index: LogIndex(0),
-            last_applied: LogIndex(0),
+            commit_index: last_applied,
+            last_applied: last_applied,
             should_campaign: true,
+            max_append_entries: config.max_append_entries,
+            max_message_size: config.max_message_size,
             state: ReplicaState::Follower,
             leader_state: leader_state,
             candidate_state: CandidateState::new(),
             follower_state: FollowerState::new(),
-        }
+            halted: None,
+            reported: (Role::Follower, current_term, None),
+            events: Vec::new(),
+        })
     }
 
     /// Apply an append entries request to the Raft replica.
     pub fn append_entries_request(&mut self,
-                                  from: SocketAddr,
+                                  from: NodeId,
                                   request: append_entries_request::Reader,
-                                  mut response: append_entries_response::Builder) -> Option<Emit> {
+                                  mut response: append_entries_response::Builder) -> Result<Option<Emit>> {
         assert!(self.peers.contains(&from), "Received append entries request from unknown node {}.", from);
         debug!("{:?}: AppendEntriesRequest from Replica({})", self, from);
 
         let leader_term = Term(request.get_term());
-        let current_term = self.store.current_term().unwrap();
+        let current_term = try!(self.store.current_term().map_err(Error::store));
 
         if leader_term < current_term {
             response.set_term(current_term.into());
             response.set_stale_term(());
-            return None;
+            return Ok(None);
         }
 
+        // A newly elected (or restarted) leader may not yet know about commits that this replica
+        // has already applied, so the commit index never moves backwards.
         let leader_commit_index = LogIndex::from(request.get_leader_commit());
-        assert!(self.commit_index <= leader_commit_index);
-        self.commit_index = leader_commit_index;
+        self.commit_index = cmp::max(self.commit_index, leader_commit_index);
 
         match self.state {
             ReplicaState::Follower => {
                 if current_term < leader_term {
-                    self.store.set_current_term(leader_term).unwrap();
+                    try!(self.set_current_term(leader_term));
                     response.set_term(leader_term.into());
-                    self.follower_state.set_leader(from);
                 } else {
                     response.set_term(current_term.into());
                 }
+                self.set_leader(from);
+                // The leader is alive, so there is no need to campaign.
+                self.should_campaign = false;
 
                 let leader_prev_log_index = LogIndex(request.get_prev_log_index());
                 let leader_prev_log_term = Term(request.get_prev_log_term());
 
-                let latest_log_index = self.store.latest_log_index().unwrap();
+                let latest_log_index = try!(self.store.latest_log_index().map_err(Error::store));
                 if latest_log_index < leader_prev_log_index {
                     response.set_inconsistent_prev_entry(());
                 } else {
@@ -116,32 +189,51 @@ impl <S, M> Replica<S, M> where S: Store, M: StateMachine {
                     let existing_term = if leader_prev_log_index == LogIndex::from(0) {
                         Term::from(0)
                     } else {
-                        self.store.entry(leader_prev_log_index).unwrap().0
+                        try!(self.store.entry(leader_prev_log_index).map_err(Error::store)).0
                     };
 
                     if existing_term != leader_prev_log_term {
                         response.set_inconsistent_prev_entry(());
                     } else {
-                        let entries = request.get_entries().unwrap();
+                        let entries = try!(request.get_entries());
                         let num_entries = entries.len();
                         if num_entries > 0 {
                             let mut entries_vec = Vec::with_capacity(num_entries as usize);
                             for i in 0..num_entries {
-                                entries_vec.push((leader_term, entries.get(i).unwrap()));
+                                let entry = entries.get(i);
+                                entries_vec.push((Term::from(entry.get_term()),
+                                                  decode_entry_kind(try!(entry.get_kind())),
+                                                  try!(entry.get_data())));
+                            }
+                            // A duplicated or reordered request may hold entries which the log
+                            // already has, followed by entries of later requests. The log is only
+                            // truncated from the first entry whose term conflicts with the leader's.
+                            let mut first = 0;
+                            while first < entries_vec.len() {
+                                let index = leader_prev_log_index + (1 + first as u64);
+                                if index > latest_log_index
+                                        || try!(self.store.entry(index).map_err(Error::store)).0 != entries_vec[first].0 {
+                                    break;
+                                }
+                                first += 1;
+                            }
+                            if first < entries_vec.len() {
+                                let from = leader_prev_log_index + (1 + first as u64);
+                                try!(self.store.append_entries(from, &entries_vec[first..]).map_err(Error::store));
+                                try!(self.append_configuration(from, &entries_vec[first..]));
                             }
-                            self.store.append_entries(leader_prev_log_index + 1, &entries_vec).unwrap();
                         }
                         let latest_log_index = leader_prev_log_index + num_entries as u64;
                         // We are matching the leaders log up to and including `latest_log_index`.
-                        self.apply_commits_until(latest_log_index);
+                        try!(self.apply_commits_until(latest_log_index));
                         response.set_success(latest_log_index.into());
                     }
                 }
-                return Some(Emit) // Need to respond to the leader.
+                return Ok(Some(Emit)) // Need to respond to the leader.
             },
             ReplicaState::Candidate => {
                 // recognize the new leader, return to follower state, and apply the entries
-                self.transition_to_follower(leader_term, from.clone());
+                try!(self.transition_to_follower(leader_term, from.clone()));
                 return self.append_entries_request(from, request, response)
             },
             ReplicaState::Leader => {
@@ -149,11 +241,11 @@ impl <S, M> Replica<S, M> where S: Store, M: StateMachine {
                     // The single leader-per-term invariant is broken; there is a bug in the Raft
                     // implementation.
                     panic!("ID {}: peer leader {} with matching term {:?} detected.",
-                           self.addr, from, current_term);
+                           self.id, from, current_term);
                 }
 
                 // recognize the new leader, return to follower state, and apply the entries
-                self.transition_to_follower(leader_term, from.clone());
+                try!(self.transition_to_follower(leader_term, from.clone()));
                 return self.append_entries_request(from, request, response)
             },
         }
@@ -165,15 +257,15 @@ impl <S, M> Replica<S, M> where S: Store, M: StateMachine {
     /// the follower in the case that the follower's log is behind.
     #[must_use]
     pub fn append_entries_response(&mut self,
-                                   from: SocketAddr,
+                                   from: NodeId,
                                    response: append_entries_response::Reader,
-                                   mut message: append_entries_request::Builder) -> Option<Emit> {
+                                   mut message: append_entries_request::Builder) -> Result<Option<Emit>> {
         assert!(self.peers.contains(&from), "{:?} received AppendEntries response from unknown peer {}.", self, from);
         debug!("{:?}: AppendEntriesResponse from Replica({})", self, from);
 
-        let local_term = self.store.current_term().unwrap();
+        let local_term = try!(self.store.current_term().map_err(Error::store));
         let responder_term = Term::from(response.get_term());
-        let local_latest_log_index = self.store.latest_log_index().unwrap();
+        let local_latest_log_index = try!(self.store.latest_log_index().map_err(Error::store));
 
         if local_term < responder_term {
             // Responder has a higher term number. Relinquish leader position (if it is held), and
@@ -181,12 +273,12 @@ impl <S, M> Replica<S, M> where S: Store, M: StateMachine {
 
             // The responder is not necessarily the leader, but it is somewhat likely, so we will
             // use it as the leader hint.
-            self.transition_to_follower(responder_term, from);
-            return None
+            try!(self.transition_to_follower(responder_term, from));
+            return Ok(None)
         } else if local_term > responder_term {
             // Responder is responding to an AppendEntries request from a different term. Ignore
             // the response.
-            return None
+            return Ok(None)
         }
 
         let mut send_message = false;
@@ -195,13 +287,21 @@ impl <S, M> Replica<S, M> where S: Store, M: StateMachine {
                 Ok(append_entries_response::Which::Success(follower_latest_log_index)) => {
                     let follower_latest_log_index = LogIndex::from(follower_latest_log_index);
                     assert!(follower_latest_log_index <= local_latest_log_index);
-                    self.leader_state.set_next_index(from.clone(), follower_latest_log_index + 1);
-                    self.leader_state.set_match_index(from.clone(), follower_latest_log_index);
-                    self.advance_commit_index();
-                    send_message = local_latest_log_index > follower_latest_log_index;
+                    // A response to a duplicated or reordered request may report less than the
+                    // follower is already known to hold.
+                    if follower_latest_log_index >= self.leader_state.match_index(&from) {
+                        self.leader_state.set_next_index(from.clone(), follower_latest_log_index + 1);
+                        self.leader_state.set_match_index(from.clone(), follower_latest_log_index);
+                        try!(self.advance_commit_index());
+                        send_message = local_latest_log_index > follower_latest_log_index;
+                    } else {
+                        debug!("{:?}: ignoring stale AppendEntries response from Replica({})", self, from);
+                    }
                 }
                 Ok(append_entries_response::Which::InconsistentPrevEntry(..)) => {
-                    let next_index = self.leader_state.next_index(&from) - 1;
+                    // The entries the follower is known to hold are never sent again.
+                    let next_index = cmp::max(self.leader_state.next_index(&from) - 1,
+                                              self.leader_state.match_index(&from) + 1);
                     self.leader_state.set_next_index(from, next_index);
                     send_message = true;
                 }
@@ -229,7 +329,7 @@ impl <S, M> Replica<S, M> where S: Store, M: StateMachine {
             // from Leader to Candidate or Follower states without increasing the term, and
             // we have already checked that local_term == responder_term).
             unreachable!("{:?}: received AppendEntries response from Replica({}) while in state {:?}.",
-                          self.addr, from, self.state);
+                          self.id, from, self.state);
         }
 
         if send_message {
@@ -237,56 +337,61 @@ impl <S, M> Replica<S, M> where S: Store, M: StateMachine {
 
             if next_index <= local_latest_log_index {
                 let prev_log_index = next_index - 1;
-                let (prev_log_term, _) = self.store.entry(prev_log_index).unwrap();
+                let prev_log_term = if prev_log_index == LogIndex::from(0) {
+                    Term::from(0)
+                } else {
+                    try!(self.store.entry(prev_log_index).map_err(Error::store)).0
+                };
 
                 message.set_term(local_term.into());
                 message.set_prev_log_index(prev_log_index.into());
                 message.set_prev_log_term(prev_log_term.into());
                 message.set_leader_commit(self.commit_index.into());
-
-                let from_index = Into::<u64>::into(next_index);
-                let until_index = Into::<u64>::into(local_latest_log_index) + 1;
-                let mut entries = message.init_entries((until_index - from_index) as u32);
-                for (n, index) in (from_index..until_index).enumerate() {
-                    entries.set(n as u32, self.store.entry(LogIndex::from(index)).unwrap().1);
-                }
-                Some(Emit)
+                try!(self.set_entries(next_index, message));
+                Ok(Some(Emit))
             } else {
-                None
+                Ok(None)
             }
         } else {
-            None
+            Ok(None)
         }
     }
 
     /// Apply a request vote request to the Raft replica.
     pub fn request_vote_request(&mut self,
-                                candidate: SocketAddr,
+                                candidate: NodeId,
                                 request: request_vote_request::Reader,
-                                mut response: request_vote_response::Builder) -> Option<Emit> {
+                                mut response: request_vote_response::Builder) -> Result<Option<Emit>> {
         assert!(self.peers.contains(&candidate), "Received request vote request from unknown node {}.", candidate);
         debug!("{:?}: RequestVoteRequest from Replica({})", self, candidate);
 
         let candidate_term = Term(request.get_term());
         let candidate_index = LogIndex(request.get_last_log_index());
-        let local_term = self.store.current_term().unwrap();
-        let local_index = self.store.latest_log_index().unwrap();
+        let candidate_log_term = Term(request.get_last_log_term());
+        let local_term = try!(self.store.current_term().map_err(Error::store));
+        let local_index = try!(self.store.latest_log_index().map_err(Error::store));
+        let local_log_term = try!(self.store.latest_log_term().map_err(Error::store));
 
         if candidate_term > local_term {
-            self.store.set_current_term(candidate_term).unwrap();
+            // A later election has started, which this replica follows, whatever its role.
+            try!(self.step_down(candidate_term));
             response.set_term(candidate_term.into());
         } else {
             response.set_term(local_term.into());
         }
 
+        // The candidate's log must be at least as up-to-date as this replica's: its last entry is
+        // of a later term, or of the same term and at least as far.
+        let up_to_date = candidate_log_term > local_log_term
+                         || (candidate_log_term == local_log_term && candidate_index >= local_index);
         if candidate_term < local_term {
             response.set_stale_term(());
-        } else if candidate_index < local_index {
+        } else if !up_to_date {
             response.set_inconsistent_log(());
         } else {
-            match self.store.voted_for().unwrap() {
+            match try!(self.store.voted_for().map_err(Error::store)) {
                 None => {
-                    self.store.set_voted_for(candidate).unwrap();
+                    try!(self.store.set_voted_for(candidate).map_err(Error::store));
                     response.set_granted(());
                     self.should_campaign = false;
                 },
@@ -299,7 +404,7 @@ impl <S, M> Replica<S, M> where S: Store, M: StateMachine {
                 },
             }
         }
-        Some(Emit) // Always need to send.
+        Ok(Some(Emit)) // Always need to send.
     }
 
     /// Apply a request vote response to the Raft replica.
@@ -308,13 +413,13 @@ impl <S, M> Replica<S, M> where S: Store, M: StateMachine {
     ///
     /// Returns `Some(())` if the provided AppendEntriesRequest should be sent to every peer cluster
     /// member.
-    pub fn request_vote_response(&mut self, from: SocketAddr,
+    pub fn request_vote_response(&mut self, from: NodeId,
                                  response: request_vote_response::Reader,
-                                 message: append_entries_request::Builder) -> Option<Broadcast> {
+                                 message: append_entries_request::Builder) -> Result<Option<Broadcast>> {
         assert!(self.peers.contains(&from), "Received request vote response from unknown node {}.", from);
         debug!("{:?}: RequestVoteResponse from Replica({})", self, from);
 
-        let local_term = self.store.current_term().unwrap();
+        let local_term = try!(self.store.current_term().map_err(Error::store));
         let voter_term = Term::from(response.get_term());
 
         let majority = self.majority();
@@ -327,7 +432,7 @@ impl <S, M> Replica<S, M> where S: Store, M: StateMachine {
 
             // The responder is not necessarily teh leader, but it is somewhat likely, so we will
             // use it as the leader hint.
-            self.transition_to_follower(voter_term, from);
+            try!(self.transition_to_follower(voter_term, from));
         } else if local_term > voter_term {
             // Ignore this message; it came from a previous election cycle.
         } else if self.is_candidate() {
@@ -343,70 +448,131 @@ impl <S, M> Replica<S, M> where S: Store, M: StateMachine {
 
         if transition_to_leader {
             // Need to transition and broadcast the first heartbeat.
-            self.transition_to_leader(message);
-            Some(Broadcast)
+            try!(self.transition_to_leader(message));
+            Ok(Some(Broadcast))
         } else {
-            None
+            Ok(None)
         }
     }
 
     /// Apply a client append request to the Raft replica.
-    pub fn client_append(&mut self, from: SocketAddr, entry: &[u8],
-                         message: client_response::Builder) -> Option<Broadcast> {
-        debug!("{:?}: Append from Client({})", self, from);
-        unimplemented!();
-        Some(Broadcast)
+    ///
+    /// If the replica is the leader, the entries are appended to its log together, and the
+    /// provided AppendEntriesRequest builder is initialized with a message replicating them to
+    /// each cluster peer. A batch too large for a single message is replicated in chunks, like
+    /// the log of a lagging follower. Otherwise the client response is initialized with the leader
+    /// address.
+    pub fn client_append(&mut self, from: SocketAddr, entries: &[&[u8]], addresses: &AddressBook,
+                         response: client_response::Builder,
+                         mut message: append_entries_request::Builder) -> Result<Append> {
+        debug!("{:?}: Append of {} entries from Client({})", self, entries.len(), from);
+        if !self.is_leader() {
+            self.redirect(addresses, response);
+            return Ok(Append::Redirect);
+        }
+        let term = try!(self.store.current_term().map_err(Error::store));
+        let prev_log_index = try!(self.store.latest_log_index().map_err(Error::store));
+        let prev_log_term = try!(self.store.latest_log_term().map_err(Error::store));
+        let log_entries: Vec<(Term, EntryKind, &[u8])> =
+            entries.iter().map(|&entry| (term, EntryKind::Application, entry)).collect();
+        try!(self.store.append_entries(prev_log_index + 1, &log_entries).map_err(Error::store));
+        // A solitary leader commits the entries at once.
+        try!(self.advance_commit_index());
+
+        message.set_term(term.into());
+        message.set_prev_log_index(prev_log_index.into());
+        message.set_prev_log_term(prev_log_term.into());
+        message.set_leader_commit(self.commit_index.into());
+        try!(self.set_entries(prev_log_index + 1, message));
+        Ok(Append::Proposed(prev_log_index + entries.len() as u64, term))
     }
 
-    /// Refreshes the client with the leader address.
-    pub fn client_leader_refresh(&mut self, from: SocketAddr,
-                                 message: client_response::Builder) -> Option<Emit> {
+    /// Initializes the entries of the AppendEntries request with those of the log from the index
+    /// on. At most `max_append_entries` entries are sent, and only as many as fit in a message,
+    /// though always at least one; the rest follow as the follower acknowledges them.
+    fn set_entries(&self, from_index: LogIndex, message: append_entries_request::Builder)
+                   -> Result<()> {
+        let latest_log_index = try!(self.store.latest_log_index().map_err(Error::store));
+        let room = self.max_message_size.saturating_sub(MESSAGE_OVERHEAD);
+        let mut entries = Vec::new();
+        let mut size = 0;
+        let mut index = from_index;
+        while index <= latest_log_index && (entries.len() as u64) < self.max_append_entries {
+            let entry = try!(self.store.entry(index).map_err(Error::store));
+            size += entry.2.len() + ENTRY_OVERHEAD;
+            if !entries.is_empty() && size > room {
+                break;
+            }
+            entries.push(entry);
+            index = index + 1;
+        }
+        let mut wire_entries = message.init_entries(entries.len() as u32);
+        for (n, (term, kind, data)) in entries.into_iter().enumerate() {
+            let mut wire_entry = wire_entries.borrow().get(n as u32);
+            wire_entry.set_term(term.into());
+            wire_entry.set_kind(encode_entry_kind(kind));
+            wire_entry.set_data(data);
+        }
+        Ok(())
+    }
+
+    /// Refreshes the client with the leader address, looked up in the provided address book.
+    pub fn client_leader_refresh(&mut self, from: SocketAddr, addresses: &AddressBook,
+                                 message: client_response::Builder) -> Result<Option<Emit>> {
         debug!("{:?}: LeaderRefresh from Client({})", self, from);
-        unimplemented!();
-        Some(Emit)
+        self.redirect(addresses, message);
+        Ok(Some(Emit))
+    }
+
+    /// Initializes the client response with the leader address, if it is known.
+    pub fn redirect(&self, addresses: &AddressBook, mut message: client_response::Builder) {
+        match self.leader().and_then(|leader| addresses.get(&leader)) {
+            Some(addr) => message.set_not_leader(&addr.to_string()),
+            None => message.set_unknown_leader(()),
+        }
     }
 
     /// Trigger a heartbeat timeout on the Raft replica.
     ///
     /// The provided AppendEntriesRequest builder may be initialized with a message to send to each
     /// cluster peer.
-    pub fn heartbeat_timeout(&mut self, mut message: append_entries_request::Builder) -> Option<Broadcast> {
+    pub fn heartbeat_timeout(&mut self, mut message: append_entries_request::Builder) -> Result<Option<Broadcast>> {
         debug!("{:?}: HeartbeatTimeout", self);
         if self.is_leader() {
             // Send a heartbeat
-            message.set_term(self.store.current_term().unwrap().into());
-            message.set_prev_log_index(self.store.latest_log_index().unwrap().into());
-            message.set_prev_log_term(self.store.latest_log_term().unwrap().into());
+            message.set_term(try!(self.store.current_term().map_err(Error::store)).into());
+            message.set_prev_log_index(try!(self.store.latest_log_index().map_err(Error::store)).into());
+            message.set_prev_log_term(try!(self.store.latest_log_term().map_err(Error::store)).into());
             message.set_leader_commit(self.commit_index.into());
             message.init_entries(0);
-            Some(Broadcast)
-        } else { None }
+            Ok(Some(Broadcast))
+        } else { Ok(None) }
     }
 
     /// Trigger an election timeout on the Raft replica.
     ///
     /// The provided RequestVoteRequest builder may be initialized with a message to send to each
     /// cluster peer.
-    pub fn election_timeout(&mut self, message: request_vote_request::Builder) -> Option<Broadcast> {
+    pub fn election_timeout(&mut self, message: request_vote_request::Builder) -> Result<Option<Broadcast>> {
         debug!("{:?}: ElectionTimeout", self);
         if self.should_campaign && !self.is_leader() {
             if self.peers.is_empty() {
                 // Solitary replica special case; jump straight to leader status
                 assert!(self.is_follower());
-                assert!(self.store.voted_for().unwrap().is_none());
-                self.store.inc_current_term().unwrap();
-                self.store.set_voted_for(self.addr).unwrap();
-                let latest_log_index = self.store.latest_log_index().unwrap();
-                self.state = ReplicaState::Leader;
+                assert!(try!(self.store.voted_for().map_err(Error::store)).is_none());
+                try!(self.inc_current_term());
+                try!(self.store.set_voted_for(self.id).map_err(Error::store));
+                let latest_log_index = try!(self.store.latest_log_index().map_err(Error::store));
+                self.set_state(ReplicaState::Leader);
                 self.leader_state.reinitialize(latest_log_index);
-                None
+                Ok(None)
             } else {
-                self.transition_to_candidate(message);
-                Some(Broadcast)
+                try!(self.transition_to_candidate(message));
+                Ok(Some(Broadcast))
             }
         } else {
             self.should_campaign = true;
-            None
+            Ok(None)
         }
     }
 
@@ -414,81 +580,201 @@ impl <S, M> Replica<S, M> where S: Store, M: StateMachine {
     ///
     /// The provided AppendEntriesRequest builder will be initialized with a message to send to each
     /// cluster peer.
-    fn transition_to_leader(&mut self, mut message: append_entries_request::Builder) -> Option<Broadcast> {
+    fn transition_to_leader(&mut self, mut message: append_entries_request::Builder) -> Result<Option<Broadcast>> {
         info!("{:?}: Transition to Leader", self);
-        let current_term = self.store.current_term().unwrap();
-        let latest_log_index = self.store.latest_log_index().unwrap();
-        let latest_log_term = self.store.latest_log_term().unwrap();
-        self.state = ReplicaState::Leader;
+        let current_term = try!(self.store.current_term().map_err(Error::store));
+        let latest_log_index = try!(self.store.latest_log_index().map_err(Error::store));
+        let latest_log_term = try!(self.store.latest_log_term().map_err(Error::store));
+        self.set_state(ReplicaState::Leader);
         self.leader_state.reinitialize(latest_log_index);
 
         message.set_term(current_term.into());
         message.set_prev_log_index(latest_log_index.into());
         message.set_prev_log_term(latest_log_term.into());
         message.set_leader_commit(self.commit_index.into());
-        Some(Broadcast)
+        Ok(Some(Broadcast))
     }
 
     /// Transition this Replica to Candidate state.
     ///
     /// The provided RequestVoteRequest message will be initialized with a message to send to each
     /// cluster peer.
-    fn transition_to_candidate(&mut self, mut message: request_vote_request::Builder) -> Option<Broadcast> {
+    fn transition_to_candidate(&mut self, mut message: request_vote_request::Builder) -> Result<Option<Broadcast>> {
         info!("{:?}: Transition to Candidate", self);
-        self.store.inc_current_term().unwrap();
-        self.store.set_voted_for(self.addr).unwrap();
-        self.state = ReplicaState::Candidate;
+        try!(self.inc_current_term());
+        try!(self.store.set_voted_for(self.id).map_err(Error::store));
+        self.set_state(ReplicaState::Candidate);
         self.candidate_state.clear();
-        self.candidate_state.record_vote(self.addr.clone());
+        self.candidate_state.record_vote(self.id);
 
-        let current_term = self.store.current_term().unwrap();
-        let latest_index = self.store.latest_log_index().unwrap();
-        let latest_term = self.store.latest_log_term().unwrap();
+        let current_term = try!(self.store.current_term().map_err(Error::store));
+        let latest_index = try!(self.store.latest_log_index().map_err(Error::store));
+        let latest_term = try!(self.store.latest_log_term().map_err(Error::store));
 
         message.set_term(current_term.into());
         message.set_last_log_index(latest_index.into());
         message.set_last_log_term(latest_term.into());
-        Some(Broadcast)
+        Ok(Some(Broadcast))
     }
 
     /// Advance the commit index and apply committed entries to the state machine, if possible.
-    fn advance_commit_index(&mut self) {
+    fn advance_commit_index(&mut self) -> Result<()> {
         assert!(self.is_leader());
         let majority = self.majority();
-        while self.leader_state.count_match_indexes(self.commit_index + 1) >= majority {
+        let latest_log_index = try!(self.store.latest_log_index().map_err(Error::store));
+        // The leader holds every entry in its log, in addition to the followers which match it.
+        while self.commit_index < latest_log_index
+                && self.leader_state.count_match_indexes(self.commit_index + 1) + 1 >= majority {
             self.commit_index = self.commit_index + 1;
         }
 
         // Apply all committed but unapplied entries
-        while self.last_applied < self.commit_index {
-            let (_, entry) = self.store.entry(self.last_applied + 1).unwrap();
-            self.state_machine.apply(entry).unwrap();
-            self.last_applied = self.last_applied + 1;
-        }
+        let commit_index = self.commit_index;
+        self.apply_commits_until(commit_index)
     }
 
     /// Apply all committed but unapplied log entries up to and including the provided index.
-    fn apply_commits_until(&mut self, until: LogIndex) {
+    fn apply_commits_until(&mut self, until: LogIndex) -> Result<()> {
         let index = cmp::min(self.commit_index, until);
-        if index == LogIndex::from(0) { return };
-        while self.last_applied <= index {
-            let (_, entry) = self.store.entry(self.last_applied + 1).unwrap();
-            self.state_machine.apply(entry).unwrap();
-            self.last_applied = self.last_applied + 1;
+        while self.last_applied < index {
+            let next = self.last_applied + 1;
+            let configuration = {
+                let (_, kind, entry) = try!(self.store.entry(next).map_err(Error::store));
+                // Entries of other kinds carry Raft's own metadata, not client commands.
+                match kind {
+                    EntryKind::Application => {
+                        try!(self.state_machine.apply(next, entry).map_err(Error::state_machine));
+                        None
+                    },
+                    EntryKind::Configuration => Some(try!(Configuration::from_entry(entry))),
+                    EntryKind::Noop => None,
+                }
+            };
+            if let Some(configuration) = configuration {
+                try!(self.commit_configuration(next, configuration));
+            }
+            self.last_applied = next;
+        }
+        Ok(())
+    }
+
+    /// Records the latest configuration among the entries which were just appended to the log
+    /// starting at index `from`. Appending the entries truncated any uncommitted configuration at
+    /// or after `from`, in which case the replica falls back to the committed configuration.
+    fn append_configuration(&mut self, from: LogIndex, entries: &[(Term, EntryKind, &[u8])]) -> Result<()> {
+        let mut latest = None;
+        for (n, &(_, kind, entry)) in entries.iter().enumerate() {
+            if kind == EntryKind::Configuration {
+                latest = Some((from + n as u64, try!(Configuration::from_entry(entry))));
+            }
+        }
+
+        let truncated = match try!(self.store.uncommitted_configuration().map_err(Error::store)) {
+            Some((index, _)) => index >= from,
+            None => false,
+        };
+        match latest {
+            Some((index, configuration)) => {
+                try!(self.set_peers(&configuration));
+                try!(self.store.set_uncommitted_configuration(Some((index, configuration)))
+                               .map_err(Error::store));
+            },
+            None if truncated => {
+                try!(self.store.set_uncommitted_configuration(None).map_err(Error::store));
+                if let Some((_, configuration)) = try!(self.store.committed_configuration().map_err(Error::store)) {
+                    try!(self.set_peers(&configuration));
+                }
+            },
+            None => (),
         }
+        Ok(())
+    }
+
+    /// Records a configuration which has been committed at the provided index. The configuration
+    /// is already in effect, since it took effect when it was appended to the log.
+    fn commit_configuration(&mut self, index: LogIndex, configuration: Configuration) -> Result<()> {
+        info!("{:?}: Committed configuration {:?} at index {:?}", self, configuration, index);
+        if let Some((uncommitted_index, _)) = try!(self.store.uncommitted_configuration().map_err(Error::store)) {
+            if uncommitted_index <= index {
+                try!(self.store.set_uncommitted_configuration(None).map_err(Error::store));
+            }
+        }
+        self.store.set_committed_configuration(index, configuration).map_err(Error::store)
+    }
+
+    /// Replaces the set of peers with the members of the provided configuration.
+    fn set_peers(&mut self, configuration: &Configuration) -> Result<()> {
+        let id = self.id;
+        self.peers = configuration.members.iter().cloned().filter(|&member| member != id).collect();
+        let latest_log_index = try!(self.store.latest_log_index().map_err(Error::store));
+        self.leader_state.set_peers(latest_log_index, &self.peers);
+        Ok(())
     }
 
     /// Transition to follower state with the provided term. The `voted_for` field will be reset.
     /// The provided leader hint will replace the last known leader.
-    fn transition_to_follower(&mut self, term: Term, leader: SocketAddr) {
+    fn transition_to_follower(&mut self, term: Term, leader: NodeId) -> Result<()> {
         info!("{:?}: Transition to Follower", self);
-        self.store.set_current_term(term).unwrap();
-        self.state = ReplicaState::Follower;
+        try!(self.set_current_term(term));
         self.follower_state.set_leader(leader);
+        self.set_state(ReplicaState::Follower);
+        Ok(())
+    }
+
+    /// Moves to the provided later term as a follower which knows no leader yet.
+    fn step_down(&mut self, term: Term) -> Result<()> {
+        info!("{:?}: Stepping down in term {:?}", self, term);
+        try!(self.set_current_term(term));
+        self.follower_state = FollowerState::new();
+        self.set_state(ReplicaState::Follower);
+        Ok(())
+    }
+
+    /// Moves to the provided term, and records the change.
+    fn set_current_term(&mut self, term: Term) -> Result<()> {
+        try!(self.store.set_current_term(term).map_err(Error::store));
+        self.record(Event::TermChanged(term));
+        Ok(())
+    }
+
+    /// Moves to the next term, and records the change.
+    fn inc_current_term(&mut self) -> Result<()> {
+        try!(self.store.inc_current_term().map_err(Error::store));
+        let term = try!(self.store.current_term().map_err(Error::store));
+        self.record(Event::TermChanged(term));
+        Ok(())
+    }
+
+    /// Takes on the provided state, and records the change to the role and, through it, to the
+    /// leader.
+    fn set_state(&mut self, state: ReplicaState) {
+        self.state = state;
+        let (role, leader) = (self.role(), self.leader());
+        self.record(Event::RoleChanged(role));
+        self.record(Event::LeaderChanged(leader));
+    }
+
+    /// Recognizes the provided leader, and records the change.
+    fn set_leader(&mut self, leader: NodeId) {
+        self.follower_state.set_leader(leader);
+        let leader = self.leader();
+        self.record(Event::LeaderChanged(leader));
+    }
+
+    /// Records the change for `changes()`, unless it repeats the latest recorded value.
+    fn record(&mut self, event: Event) {
+        let changed = match event {
+            Event::RoleChanged(role) => mem::replace(&mut self.reported.0, role) != role,
+            Event::TermChanged(term) => mem::replace(&mut self.reported.1, term) != term,
+            Event::LeaderChanged(leader) => mem::replace(&mut self.reported.2, leader) != leader,
+        };
+        if changed {
+            self.events.push(event);
+        }
     }
 
     /// Returns `true` if the replica is in the Leader state.
-    fn is_leader(&self) -> bool {
+    pub fn is_leader(&self) -> bool {
         self.state == ReplicaState::Leader
     }
 
@@ -502,14 +788,112 @@ impl <S, M> Replica<S, M> where S: Store, M: StateMachine {
         self.state == ReplicaState::Candidate
     }
 
-    /// Returns the address of the replica.
-    fn addr(&self) -> &SocketAddr {
-        &self.addr
+    /// Halts the replica following an unrecoverable `Store` or `StateMachine` error, and returns
+    /// the reason to report to peers and clients. Once halted, the replica no longer takes part
+    /// in elections or replication.
+    pub fn halt(&mut self, error: Error) -> String {
+        let reason = format!("{:?}", error);
+        error!("{:?}: Halting: {}", self, reason);
+        self.halted = Some(reason.clone());
+        reason
+    }
+
+    /// Flushes the persistent state of the replica to durable storage.
+    pub fn sync(&mut self) -> Result<()> {
+        self.store.sync().map_err(Error::store)
+    }
+
+    /// Returns the reason the replica halted, or `None` if it is still running.
+    pub fn halted(&self) -> Option<&str> {
+        self.halted.as_ref().map(|reason| &reason[..])
+    }
+
+    /// Returns the ID of the replica.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Returns the IDs of the other `Replica`s in the cluster.
+    pub fn peers(&self) -> &HashSet<NodeId> {
+        &self.peers
+    }
+
+    /// Returns the ID of the leader, if it is known.
+    pub fn leader(&self) -> Option<NodeId> {
+        match self.state {
+            ReplicaState::Leader => Some(self.id),
+            ReplicaState::Follower => self.follower_state.leader(),
+            ReplicaState::Candidate => None,
+        }
+    }
+
+    /// Returns the index of the latest entry applied to the state machine.
+    pub fn last_applied(&self) -> LogIndex {
+        self.last_applied
     }
 
     /// Returns the current term of the replica.
-    fn current_term(&self) -> Term {
-        self.store.current_term().unwrap()
+    pub fn current_term(&self) -> Result<Term> {
+        self.store.current_term().map_err(Error::store)
+    }
+
+    /// Returns the term of the entry at the index of the log.
+    pub fn entry_term(&self, index: LogIndex) -> Result<Term> {
+        self.store.entry(index).map(|(term, _, _)| term).map_err(Error::store)
+    }
+
+    /// Returns the `Store` of the replica, for inspecting its log.
+    #[cfg(test)]
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Returns the role of the replica.
+    pub fn role(&self) -> Role {
+        match self.state {
+            ReplicaState::Follower => Role::Follower,
+            ReplicaState::Candidate => Role::Candidate,
+            ReplicaState::Leader => Role::Leader,
+        }
+    }
+
+    /// Returns the changes to the term, role and leader of the replica since the previous call, in
+    /// the order they happened.
+    pub fn changes(&mut self) -> Vec<Event> {
+        mem::replace(&mut self.events, Vec::new())
+    }
+
+    /// Returns the term, role and leader of the replica as of the latest change, so that a new
+    /// subscriber can learn them once it has been sent the pending `changes()`.
+    pub fn reported(&self) -> Vec<Event> {
+        let (role, term, leader) = self.reported;
+        vec![Event::TermChanged(term), Event::RoleChanged(role), Event::LeaderChanged(leader)]
+    }
+
+    /// Initializes the provided Status builder with a snapshot of the state of the replica.
+    pub fn status(&self, mut message: status::Builder) -> Result<()> {
+        message.set_id(self.id.as_bytes());
+        message.set_role(match self.role() {
+            Role::Follower => WireRole::Follower,
+            Role::Candidate => WireRole::Candidate,
+            Role::Leader => WireRole::Leader,
+        });
+        message.set_term(try!(self.current_term()).into());
+        message.set_commit_index(self.commit_index.into());
+        message.set_last_applied(self.last_applied.into());
+        if let Some(leader) = self.leader() {
+            message.set_leader(leader.as_bytes());
+        }
+        if self.is_leader() {
+            let mut peers = message.init_peers(self.peers.len() as u32);
+            for (n, peer) in self.peers.iter().enumerate() {
+                let mut peer_status = peers.borrow().get(n as u32);
+                peer_status.set_id(peer.as_bytes());
+                peer_status.set_next_index(self.leader_state.next_index(peer).into());
+                peer_status.set_match_index(self.leader_state.match_index(peer).into());
+            }
+        }
+        Ok(())
     }
 
     /// Get the cluster quorum majority size.
@@ -520,46 +904,65 @@ impl <S, M> Replica<S, M> where S: Store, M: StateMachine {
     }
 }
 
+/// Converts a log entry kind to its Cap'n Proto representation.
+fn encode_entry_kind(kind: EntryKind) -> WireEntryKind {
+    match kind {
+        EntryKind::Application => WireEntryKind::Application,
+        EntryKind::Noop => WireEntryKind::Noop,
+        EntryKind::Configuration => WireEntryKind::Configuration,
+    }
+}
+
+/// Converts a log entry kind from its Cap'n Proto representation.
+fn decode_entry_kind(kind: WireEntryKind) -> EntryKind {
+    match kind {
+        WireEntryKind::Application => EntryKind::Application,
+        WireEntryKind::Noop => EntryKind::Noop,
+        WireEntryKind::Configuration => EntryKind::Configuration,
+    }
+}
+
 impl <S, M> fmt::Debug for Replica<S, M> {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        write!(fmt, "Replica({})", self.addr)
+        write!(fmt, "Replica({})", self.id)
     }
 }
 
 #[cfg(test)]
 mod test {
 
-    use std::collections::HashSet;
-    use std::net::{SocketAddr};
-    use std::sync::mpsc;
+    use std::collections::{HashMap, HashSet};
+    use std::net::SocketAddr;
     use std::str::FromStr;
+    use std::sync::mpsc;
 
     use capnp::{MallocMessageBuilder, MessageBuilder};
 
+    use address_book::AddressBook;
     use messages_capnp::{
         append_entries_request,
         append_entries_response,
+        client_response,
         request_vote_request,
         request_vote_response,
     };
-    use replica::Replica;
-    use state_machine::ChannelStateMachine;
-    use store::MemStore;
-    use Term;
+    use messages_capnp::EntryKind as WireEntryKind;
+    use replica::{Append, Replica};
+    use state_machine::{ChannelStateMachine, StateMachine};
+    use store::{MemStore, Store};
+    use {Config, Configuration, EntryKind, Error, ErrorKind, Event, LogIndex, NodeId, Role, Term};
 
     type TestReplica = Replica<MemStore, ChannelStateMachine>;
 
     fn new_cluster(size: u16) -> Vec<(TestReplica, mpsc::Receiver<Vec<u8>>)> {
-        // the actual port does not matter here since they won't be bound
-        let addrs: HashSet<SocketAddr> =
-            (0..size).map(|port| FromStr::from_str(&format!("127.0.0.1:{}", port)).unwrap()).collect();
+        let ids: HashSet<NodeId> = (0..size).map(|_| NodeId::new()).collect();
 
-        addrs.iter().map(|addr| {
-            let mut peers = addrs.clone();
-            peers.remove(addr);
+        ids.iter().map(|id| {
+            let mut peers = ids.clone();
+            peers.remove(id);
             let store = MemStore::new();
             let (state_machine, recv) = ChannelStateMachine::new();
-            (Replica::new(addr.clone(), peers, store, state_machine), recv)
+            (Replica::new(*id, peers, store, state_machine, &Config::default()).unwrap(), recv)
         }).collect()
     }
 
@@ -571,26 +974,26 @@ mod test {
         let mut append_entries_request = MallocMessageBuilder::new_default();
         let mut response = MallocMessageBuilder::new_default();
 
-        let respond = leader.election_timeout(request_vote_request.init_root::<request_vote_request::Builder>());
+        let respond = leader.election_timeout(request_vote_request.init_root::<request_vote_request::Builder>()).unwrap();
         if respond.is_none() {
             // The leader could have had an AppendEntries request since the last timeout, so it may
             // take two timeouts.
-            let respond = leader.election_timeout(request_vote_request.init_root::<request_vote_request::Builder>());
+            let respond = leader.election_timeout(request_vote_request.init_root::<request_vote_request::Builder>()).unwrap();
             assert!(respond.is_some());
         }
 
         for &mut (ref mut follower, _) in followers.iter_mut() {
-            follower.request_vote_request(leader.addr().clone(),
+            follower.request_vote_request(leader.id(),
                                           request_vote_request.get_root::<request_vote_request::Builder>().unwrap().as_reader(),
-                                          response.init_root::<request_vote_response::Builder>());
+                                          response.init_root::<request_vote_response::Builder>()).unwrap();
 
             let resp = response.get_root::<request_vote_response::Builder>().unwrap().as_reader();
             assert!(if let request_vote_response::Which::Granted(_) = resp.which().unwrap() { true } else { false });
 
             // Return success vote to candidate, and make sure it transitions to leader
-            let respond = leader.request_vote_response(follower.addr().clone(),
+            let respond = leader.request_vote_response(follower.id(),
                                                             resp,
-                                                            append_entries_request.init_root::<append_entries_request::Builder>());
+                                                            append_entries_request.init_root::<append_entries_request::Builder>()).unwrap();
             assert!(respond.is_some());
             assert!(follower.is_follower());
         }
@@ -608,7 +1011,7 @@ mod test {
         let mut message = MallocMessageBuilder::new_default();
         let request = message.init_root::<request_vote_request::Builder>();
 
-        let respond = replica.election_timeout(request);
+        let respond = replica.election_timeout(request).unwrap();
         assert!(respond.is_none());
         assert!(replica.is_leader());
     }
@@ -625,31 +1028,31 @@ mod test {
 
         // Trigger replica1's timeout, and make sure it transitions to candidate
 
-        let respond = replica1.election_timeout(request.init_root::<request_vote_request::Builder>());
+        let respond = replica1.election_timeout(request.init_root::<request_vote_request::Builder>()).unwrap();
         assert!(respond.is_some());
         assert!(replica1.is_candidate());
 
         // Send replica1's RequestVoteRequest to replica2
 
-        replica2.request_vote_request(replica1.addr().clone(),
+        replica2.request_vote_request(replica1.id(),
                                       request.get_root::<request_vote_request::Builder>().unwrap().as_reader(),
-                                      response.init_root::<request_vote_response::Builder>());
+                                      response.init_root::<request_vote_response::Builder>()).unwrap();
 
         let resp = response.get_root::<request_vote_response::Builder>().unwrap().as_reader();
         assert!(if let request_vote_response::Which::Granted(_) = resp.which().unwrap() { true } else { false });
 
         // Trigger replica2's timeout, and make sure it does *not* transitition to candidate, since
         // it has already voted in an election during this timeout period.
-        let respond = replica2.election_timeout(request.init_root::<request_vote_request::Builder>());
+        let respond = replica2.election_timeout(request.init_root::<request_vote_request::Builder>()).unwrap();
         assert!(respond.is_none());
 
         // Return success vote to candidate, and make sure it transitions to leader
-        let respond = replica1.request_vote_response(replica2.addr().clone(),
+        let respond = replica1.request_vote_response(replica2.id(),
                                                           resp,
-                                                          request.init_root::<append_entries_request::Builder>());
+                                                          request.init_root::<append_entries_request::Builder>()).unwrap();
         assert!(respond.is_some());
         assert!(replica1.is_leader());
-        assert!(replica1.current_term() == Term::from(1));
+        assert!(replica1.current_term().unwrap() == Term::from(1));
     }
 
     /// Tests a two node cluster with leader and follower.  The leader sends a heartbeat to the
@@ -667,21 +1070,484 @@ mod test {
         let (mut follower, _) = replicas.pop().unwrap();
 
         let respond =
-            leader.heartbeat_timeout(request.init_root::<append_entries_request::Builder>());
+            leader.heartbeat_timeout(request.init_root::<append_entries_request::Builder>()).unwrap();
         assert!(respond.is_some());
 
-        follower.append_entries_request(leader.addr().clone(),
+        follower.append_entries_request(leader.id(),
                                         request.get_root::<append_entries_request::Builder>().unwrap().as_reader(),
-                                        response.init_root::<append_entries_response::Builder>());
+                                        response.init_root::<append_entries_response::Builder>()).unwrap();
 
         let resp = response.get_root::<append_entries_response::Builder>().unwrap().as_reader();
         assert!(if let append_entries_response::Which::Success(0) = resp.which().unwrap() { true } else { false });
 
-        let respond = follower.election_timeout(request.init_root::<request_vote_request::Builder>());
+        let respond = follower.election_timeout(request.init_root::<request_vote_request::Builder>()).unwrap();
         assert!(respond.is_none());
         assert!(follower.is_follower());
-        let respond = follower.election_timeout(request.init_root::<request_vote_request::Builder>());
+        let respond = follower.election_timeout(request.init_root::<request_vote_request::Builder>()).unwrap();
         assert!(respond.is_some());
         assert!(follower.is_candidate());
     }
+
+    /// Tests that a restarted replica resumes applying entries after the index reported by its
+    /// state machine, instead of applying the whole log a second time.
+    #[test]
+    fn test_restart_resumes_from_last_applied() {
+        let id = NodeId::new();
+        let leader = NodeId::new();
+        let mut peers = HashSet::new();
+        peers.insert(leader);
+
+        // The state of the replica before the restart.
+        let mut store = MemStore::new();
+        store.set_current_term(Term::from(1)).unwrap();
+        store.append_entries(LogIndex::from(1), &[(Term::from(1), EntryKind::Application, &[1]),
+                                                  (Term::from(1), EntryKind::Application, &[2])]).unwrap();
+        let (mut state_machine, recv) = ChannelStateMachine::new();
+        state_machine.apply(LogIndex::from(1), &[1]).unwrap();
+        state_machine.apply(LogIndex::from(2), &[2]).unwrap();
+        assert_eq!(vec![1u8], recv.recv().unwrap());
+        assert_eq!(vec![2u8], recv.recv().unwrap());
+
+        let mut follower = Replica::new(id, peers, store, state_machine, &Config::default()).unwrap();
+        assert_eq!(LogIndex::from(2), follower.last_applied);
+        assert_eq!(LogIndex::from(2), follower.commit_index);
+
+        let mut request = MallocMessageBuilder::new_default();
+        let mut response = MallocMessageBuilder::new_default();
+        {
+            let mut append_entries = request.init_root::<append_entries_request::Builder>();
+            append_entries.set_term(1);
+            append_entries.set_prev_log_index(2);
+            append_entries.set_prev_log_term(1);
+            append_entries.set_leader_commit(3);
+            let mut entry = append_entries.init_entries(1).get(0);
+            entry.set_term(1);
+            entry.set_kind(WireEntryKind::Application);
+            entry.set_data(&[3]);
+        }
+        follower.append_entries_request(leader,
+                                        request.get_root::<append_entries_request::Builder>().unwrap().as_reader(),
+                                        response.init_root::<append_entries_response::Builder>()).unwrap();
+
+        let resp = response.get_root::<append_entries_response::Builder>().unwrap().as_reader();
+        assert!(if let append_entries_response::Which::Success(3) = resp.which().unwrap() { true } else { false });
+
+        // Only the new entry is applied.
+        assert_eq!(vec![3u8], recv.recv().unwrap());
+        assert!(recv.try_recv().is_err());
+        assert_eq!(LogIndex::from(3), follower.last_applied);
+    }
+
+    /// Tests that a replica refuses to start when its state machine has applied entries which are
+    /// not in its log.
+    #[test]
+    fn test_state_machine_ahead_of_log() {
+        let mut peers = HashSet::new();
+        peers.insert(NodeId::new());
+        let (mut state_machine, _recv) = ChannelStateMachine::new();
+        state_machine.apply(LogIndex::from(1), &[1]).unwrap();
+        state_machine.apply(LogIndex::from(2), &[2]).unwrap();
+        match Replica::new(NodeId::new(), peers, MemStore::new(), state_machine, &Config::default()) {
+            Err(Error::Raft(ErrorKind::StateMachineAhead(applied, latest))) => {
+                assert_eq!(LogIndex::from(2), applied);
+                assert_eq!(LogIndex::from(0), latest);
+            },
+            _ => panic!("The replica started with a state machine ahead of its log."),
+        }
+    }
+
+    /// Tests that a failure to apply a committed entry is returned to the caller instead of
+    /// panicking, and that the replica can then be halted.
+    #[test]
+    fn test_state_machine_error() {
+        let mut replicas = new_cluster(2);
+        let (mut leader, _) = replicas.pop().unwrap();
+        elect_leader(&mut leader, &mut replicas[..]);
+        // Dropping the receiver makes every `apply` on the follower's state machine fail.
+        let (mut follower, _) = replicas.pop().unwrap();
+
+        let mut request = MallocMessageBuilder::new_default();
+        let mut response = MallocMessageBuilder::new_default();
+        {
+            let mut append_entries = request.init_root::<append_entries_request::Builder>();
+            append_entries.set_term(leader.current_term().unwrap().into());
+            append_entries.set_prev_log_index(0);
+            append_entries.set_prev_log_term(0);
+            append_entries.set_leader_commit(1);
+            let mut entry = append_entries.init_entries(1).get(0);
+            entry.set_term(leader.current_term().unwrap().into());
+            entry.set_kind(WireEntryKind::Application);
+            entry.set_data(&[1]);
+        }
+        let result = follower.append_entries_request(leader.id(),
+                                                     request.get_root::<append_entries_request::Builder>().unwrap().as_reader(),
+                                                     response.init_root::<append_entries_response::Builder>());
+        match result {
+            Err(Error::StateMachine(_)) => (),
+            _ => panic!("expected a state machine error"),
+        }
+
+        assert!(follower.halted().is_none());
+        follower.halt(result.err().unwrap());
+        assert!(follower.halted().is_some());
+    }
+
+    /// Tests that the peers given to a new replica are only used to bootstrap the cluster
+    /// configuration, and that a stored configuration takes precedence over them.
+    #[test]
+    fn test_stored_configuration() {
+        let id = NodeId::new();
+        let stored_peer = NodeId::new();
+        let bootstrap_peer = NodeId::new();
+        let mut peers = HashSet::new();
+        peers.insert(bootstrap_peer);
+
+        // A fresh store is bootstrapped with the provided peers.
+        let (state_machine, _) = ChannelStateMachine::new();
+        let replica = Replica::new(id, peers.clone(), MemStore::new(), state_machine, &Config::default()).unwrap();
+        assert_eq!(peers, replica.peers);
+        let mut members = peers.clone();
+        members.insert(id);
+        assert_eq!(Some((LogIndex::from(0), Configuration::new(members))),
+                   replica.store.committed_configuration().unwrap());
+
+        // A stored configuration overrides the provided peers.
+        let mut store = MemStore::new();
+        let mut members = HashSet::new();
+        members.insert(id);
+        members.insert(stored_peer);
+        store.set_committed_configuration(LogIndex::from(0), Configuration::new(members)).unwrap();
+        let (state_machine, _) = ChannelStateMachine::new();
+        let replica = Replica::new(id, peers, store, state_machine, &Config::default()).unwrap();
+        let mut expected = HashSet::new();
+        expected.insert(stored_peer);
+        assert_eq!(expected, replica.peers);
+    }
+
+    /// Tests that a configuration change keeps the replication progress of the peers which remain,
+    /// and drops that of the peers which were removed.
+    #[test]
+    fn test_set_peers_keeps_progress() {
+        let id = NodeId::new();
+        let (staying, leaving, joining) = (NodeId::new(), NodeId::new(), NodeId::new());
+        let mut peers = HashSet::new();
+        peers.insert(staying);
+        peers.insert(leaving);
+        let (state_machine, _) = ChannelStateMachine::new();
+        let mut replica = Replica::new(id, peers, MemStore::new(), state_machine, &Config::default()).unwrap();
+        replica.leader_state.set_next_index(staying, LogIndex::from(4));
+        replica.leader_state.set_match_index(staying, LogIndex::from(3));
+
+        let mut members = HashSet::new();
+        members.insert(id);
+        members.insert(staying);
+        members.insert(joining);
+        replica.set_peers(&Configuration::new(members)).unwrap();
+
+        assert_eq!(LogIndex::from(4), replica.leader_state.next_index(&staying));
+        assert_eq!(LogIndex::from(3), replica.leader_state.match_index(&staying));
+        assert_eq!(LogIndex::from(1), replica.leader_state.next_index(&joining));
+        assert_eq!(LogIndex::from(0), replica.leader_state.match_index(&joining));
+        assert_eq!(1, replica.leader_state.count_match_indexes(LogIndex::from(1)));
+    }
+
+    /// Initializes an AppendEntries request of term 1 holding the entries which follow the index.
+    fn init_append_entries(request: &mut MallocMessageBuilder, prev_log_index: u64, entries: &[&[u8]]) {
+        let mut append_entries = request.init_root::<append_entries_request::Builder>();
+        append_entries.set_term(1);
+        append_entries.set_prev_log_index(prev_log_index);
+        append_entries.set_prev_log_term(if prev_log_index == 0 { 0 } else { 1 });
+        append_entries.set_leader_commit(0);
+        let mut wire_entries = append_entries.init_entries(entries.len() as u32);
+        for (n, &entry) in entries.iter().enumerate() {
+            let mut wire_entry = wire_entries.borrow().get(n as u32);
+            wire_entry.set_term(1);
+            wire_entry.set_kind(WireEntryKind::Application);
+            wire_entry.set_data(entry);
+        }
+    }
+
+    /// Delivers the AppendEntries request to the follower, and returns the index it reports to
+    /// match the leader's log up to, if it succeeds.
+    fn deliver_append_entries(follower: &mut TestReplica, leader: NodeId,
+                              request: &mut MallocMessageBuilder) -> Option<u64> {
+        let mut response = MallocMessageBuilder::new_default();
+        follower.append_entries_request(leader,
+                                        request.get_root::<append_entries_request::Builder>().unwrap().as_reader(),
+                                        response.init_root::<append_entries_response::Builder>()).unwrap();
+        let resp = response.get_root::<append_entries_response::Builder>().unwrap().as_reader();
+        match resp.which().unwrap() {
+            append_entries_response::Which::Success(index) => Some(index),
+            _ => None,
+        }
+    }
+
+    /// Tests that a duplicated AppendEntries request arriving after a later one does not truncate
+    /// the entries the later one appended.
+    #[test]
+    fn test_duplicate_append_entries() {
+        let id = NodeId::new();
+        let leader = NodeId::new();
+        let mut peers = HashSet::new();
+        peers.insert(leader);
+        let (state_machine, _) = ChannelStateMachine::new();
+        let mut follower = Replica::new(id, peers, MemStore::new(), state_machine, &Config::default()).unwrap();
+
+        let mut first = MallocMessageBuilder::new_default();
+        init_append_entries(&mut first, 0, &[b"1", b"2"]);
+        let mut second = MallocMessageBuilder::new_default();
+        init_append_entries(&mut second, 2, &[b"3"]);
+
+        assert_eq!(Some(2), deliver_append_entries(&mut follower, leader, &mut first));
+        assert_eq!(Some(3), deliver_append_entries(&mut follower, leader, &mut second));
+        assert_eq!(Some(2), deliver_append_entries(&mut follower, leader, &mut first));
+        assert_eq!(LogIndex::from(3), follower.store.latest_log_index().unwrap());
+
+        // A conflicting entry still truncates the log from its index.
+        let mut conflicting = MallocMessageBuilder::new_default();
+        {
+            let mut append_entries = conflicting.init_root::<append_entries_request::Builder>();
+            append_entries.set_term(2);
+            append_entries.set_prev_log_index(1);
+            append_entries.set_prev_log_term(1);
+            append_entries.set_leader_commit(0);
+            let mut entry = append_entries.init_entries(1).get(0);
+            entry.set_term(2);
+            entry.set_kind(WireEntryKind::Application);
+            entry.set_data(b"4");
+        }
+        assert_eq!(Some(2), deliver_append_entries(&mut follower, leader, &mut conflicting));
+        assert_eq!(LogIndex::from(2), follower.store.latest_log_index().unwrap());
+        assert_eq!(Term::from(2), follower.store.latest_log_term().unwrap());
+    }
+
+    /// Tests that the leader ignores a response to an AppendEntries request which reports less
+    /// than the follower is known to hold.
+    #[test]
+    fn test_stale_append_entries_response() {
+        let client = SocketAddr::from_str("127.0.0.1:9000").unwrap();
+        let addresses = AddressBook::new(HashMap::new());
+        let mut replicas = new_cluster(2);
+        let (mut leader, _) = replicas.pop().unwrap();
+        elect_leader(&mut leader, &mut replicas[..]);
+        let follower = replicas[0].0.id();
+
+        let mut client_response = MallocMessageBuilder::new_default();
+        let mut request = MallocMessageBuilder::new_default();
+        let entries: Vec<&[u8]> = vec![b"1", b"2"];
+        leader.client_append(client, &entries, &addresses,
+                             client_response.init_root::<client_response::Builder>(),
+                             request.init_root::<append_entries_request::Builder>()).unwrap();
+
+        let term = leader.current_term().unwrap();
+        for &index in &[2, 1] {
+            let mut response = MallocMessageBuilder::new_default();
+            {
+                let mut append_entries = response.init_root::<append_entries_response::Builder>();
+                append_entries.set_term(term.into());
+                append_entries.set_success(index);
+            }
+            leader.append_entries_response(follower,
+                                           response.get_root::<append_entries_response::Builder>().unwrap().as_reader(),
+                                           request.init_root::<append_entries_request::Builder>()).unwrap();
+        }
+        assert_eq!(LogIndex::from(2), leader.leader_state.match_index(&follower));
+        assert_eq!(LogIndex::from(3), leader.leader_state.next_index(&follower));
+    }
+
+    /// Delivers a RequestVote request of the candidate to the voter, and returns whether the vote
+    /// was granted.
+    fn request_vote(voter: &mut TestReplica, candidate: NodeId, term: u64, last_log_index: u64,
+                    last_log_term: u64) -> bool {
+        let mut request = MallocMessageBuilder::new_default();
+        let mut response = MallocMessageBuilder::new_default();
+        {
+            let mut request_vote = request.init_root::<request_vote_request::Builder>();
+            request_vote.set_term(term);
+            request_vote.set_last_log_index(last_log_index);
+            request_vote.set_last_log_term(last_log_term);
+        }
+        voter.request_vote_request(candidate,
+                                   request.get_root::<request_vote_request::Builder>().unwrap().as_reader(),
+                                   response.init_root::<request_vote_response::Builder>()).unwrap();
+        let resp = response.get_root::<request_vote_response::Builder>().unwrap().as_reader();
+        if let request_vote_response::Which::Granted(_) = resp.which().unwrap() { true } else { false }
+    }
+
+    /// Tests that a vote is only granted to a candidate whose last log entry is of a later term,
+    /// or of the same term and at least as far, however long its log is.
+    #[test]
+    fn test_vote_requires_up_to_date_log() {
+        let id = NodeId::new();
+        let (stale, current) = (NodeId::new(), NodeId::new());
+        let mut peers = HashSet::new();
+        peers.insert(stale);
+        peers.insert(current);
+        let mut store = MemStore::new();
+        store.set_current_term(Term::from(2)).unwrap();
+        store.append_entries(LogIndex::from(1), &[(Term::from(2), EntryKind::Application, &[1])]).unwrap();
+        let (state_machine, _) = ChannelStateMachine::new();
+        let mut voter = Replica::new(id, peers, store, state_machine, &Config::default()).unwrap();
+
+        assert!(!request_vote(&mut voter, stale, 3, 5, 1), "a stale log was voted for.");
+        assert!(request_vote(&mut voter, current, 3, 1, 2), "an up-to-date log was refused.");
+    }
+
+    /// Tests that a leader which receives a RequestVote request of a later term steps down.
+    #[test]
+    fn test_leader_steps_down_on_request_vote() {
+        let mut replicas = new_cluster(3);
+        let (mut leader, _) = replicas.pop().unwrap();
+        elect_leader(&mut leader, &mut replicas[..]);
+        let candidate = replicas[0].0.id();
+
+        let term: u64 = leader.current_term().unwrap().into();
+        assert!(request_vote(&mut leader, candidate, term + 1, 0, 0));
+        assert!(leader.is_follower());
+        assert_eq!(None, leader.leader());
+        assert_eq!(Term::from(term + 1), leader.current_term().unwrap());
+    }
+
+    /// Tests that a client append is applied at once by a solitary leader, and that a follower
+    /// redirects it.
+    #[test]
+    fn test_client_append() {
+        let client = SocketAddr::from_str("127.0.0.1:9000").unwrap();
+        let addresses = AddressBook::new(HashMap::new());
+        let mut response = MallocMessageBuilder::new_default();
+        let mut request = MallocMessageBuilder::new_default();
+
+        let (mut leader, applied) = new_cluster(1).pop().unwrap();
+        while !leader.is_leader() {
+            let mut message = MallocMessageBuilder::new_default();
+            leader.election_timeout(message.init_root::<request_vote_request::Builder>()).unwrap();
+        }
+        let append = leader.client_append(client, &[b"entry"], &addresses,
+                                          response.init_root::<client_response::Builder>(),
+                                          request.init_root::<append_entries_request::Builder>()).unwrap();
+        let index = match append {
+            Append::Proposed(index, _) => index,
+            Append::Redirect => panic!("the leader redirected the append"),
+        };
+        assert_eq!(index, leader.last_applied());
+        assert_eq!(b"entry".to_vec(), applied.try_recv().unwrap());
+
+        let (mut follower, _) = new_cluster(2).pop().unwrap();
+        let append = follower.client_append(client, &[b"entry"], &addresses,
+                                            response.init_root::<client_response::Builder>(),
+                                            request.init_root::<append_entries_request::Builder>()).unwrap();
+        assert!(if let Append::Redirect = append { true } else { false });
+        let redirect = response.get_root::<client_response::Builder>().unwrap().as_reader();
+        assert!(if let client_response::Which::UnknownLeader(()) = redirect.which().unwrap() { true } else { false });
+    }
+
+    /// Tests that a client append of a large batch is replicated in chunks of at most
+    /// `max_append_entries` entries.
+    #[test]
+    fn test_client_append_chunks() {
+        let client = SocketAddr::from_str("127.0.0.1:9000").unwrap();
+        let addresses = AddressBook::new(HashMap::new());
+        let config = Config { max_append_entries: 2, ..Config::default() };
+        let (leader_id, follower_id) = (NodeId::new(), NodeId::new());
+        let mut peers = HashSet::new();
+        peers.insert(follower_id);
+        let (state_machine, _) = ChannelStateMachine::new();
+        let mut leader = Replica::new(leader_id, peers, MemStore::new(), state_machine, &config).unwrap();
+        let mut peers = HashSet::new();
+        peers.insert(leader_id);
+        let (state_machine, recv) = ChannelStateMachine::new();
+        let follower = Replica::new(follower_id, peers, MemStore::new(), state_machine, &config).unwrap();
+        let mut followers = vec![(follower, recv)];
+        elect_leader(&mut leader, &mut followers);
+        let (mut follower, _) = followers.pop().unwrap();
+
+        let mut client_response = MallocMessageBuilder::new_default();
+        let mut request = MallocMessageBuilder::new_default();
+        let mut response = MallocMessageBuilder::new_default();
+        let entries: Vec<&[u8]> = vec![b"1", b"2", b"3", b"4", b"5"];
+        let append = leader.client_append(client, &entries, &addresses,
+                                          client_response.init_root::<client_response::Builder>(),
+                                          request.init_root::<append_entries_request::Builder>()).unwrap();
+        let index = match append {
+            Append::Proposed(index, _) => index,
+            Append::Redirect => panic!("the leader redirected the append"),
+        };
+
+        // The follower is sent the rest of the batch as it acknowledges each chunk.
+        loop {
+            {
+                let sent = request.get_root::<append_entries_request::Builder>().unwrap().as_reader();
+                let chunk = sent.get_entries().unwrap().len();
+                assert!(chunk > 0 && chunk <= 2, "a chunk of {} entries was sent", chunk);
+                follower.append_entries_request(leader_id, sent,
+                                                response.init_root::<append_entries_response::Builder>())
+                        .unwrap();
+            }
+            let acknowledged = response.get_root::<append_entries_response::Builder>().unwrap().as_reader();
+            let respond = leader.append_entries_response(follower_id, acknowledged,
+                                                         request.init_root::<append_entries_request::Builder>())
+                                .unwrap();
+            if respond.is_none() {
+                break;
+            }
+        }
+        assert_eq!(index, follower.store.latest_log_index().unwrap());
+    }
+
+    /// Tests that the changes to the term, role and leader of a replica are each reported once.
+    #[test]
+    fn test_changes() {
+        let (mut replica, _) = new_cluster(1).pop().unwrap();
+        assert!(replica.changes().is_empty());
+
+        while !replica.is_leader() {
+            let mut message = MallocMessageBuilder::new_default();
+            replica.election_timeout(message.init_root::<request_vote_request::Builder>()).unwrap();
+        }
+        let id = replica.id();
+        assert_eq!(vec![Event::TermChanged(Term::from(1)),
+                        Event::RoleChanged(Role::Leader),
+                        Event::LeaderChanged(Some(id))],
+                   replica.changes());
+        assert!(replica.changes().is_empty());
+    }
+
+    /// Tests that a candidate which steps down before its changes are taken reports each of its
+    /// transitions.
+    #[test]
+    fn test_changes_within_a_turn() {
+        let id = NodeId::new();
+        let leader = NodeId::new();
+        let mut peers = HashSet::new();
+        peers.insert(leader);
+        let (state_machine, _) = ChannelStateMachine::new();
+        let mut replica = Replica::new(id, peers, MemStore::new(), state_machine, &Config::default()).unwrap();
+
+        let mut message = MallocMessageBuilder::new_default();
+        replica.election_timeout(message.init_root::<request_vote_request::Builder>()).unwrap();
+        let mut request = MallocMessageBuilder::new_default();
+        let mut response = MallocMessageBuilder::new_default();
+        {
+            let mut append_entries = request.init_root::<append_entries_request::Builder>();
+            append_entries.set_term(2);
+            append_entries.set_prev_log_index(0);
+            append_entries.set_prev_log_term(0);
+            append_entries.set_leader_commit(0);
+            append_entries.init_entries(0);
+        }
+        replica.append_entries_request(leader,
+                                       request.get_root::<append_entries_request::Builder>().unwrap().as_reader(),
+                                       response.init_root::<append_entries_response::Builder>()).unwrap();
+
+        assert_eq!(vec![Event::TermChanged(Term::from(1)),
+                        Event::RoleChanged(Role::Candidate),
+                        Event::TermChanged(Term::from(2)),
+                        Event::RoleChanged(Role::Follower),
+                        Event::LeaderChanged(Some(leader))],
+                   replica.changes());
+        assert_eq!(vec![Event::TermChanged(Term::from(2)),
+                        Event::RoleChanged(Role::Follower),
+                        Event::LeaderChanged(Some(leader))],
+                   replica.reported());
+    }
 }
@@ -0,0 +1,45 @@
+//! The timers of a `Server`, and the randomness of its election timeouts.
+//!
+//! A `Server` arms its timers and picks its election timeouts through a `Scheduler` instead of
+//! going to its event loop and the thread's random number generator directly. A running `Server`
+//! uses an `EventLoopScheduler`, while the simulation replaces it with one driven by a virtual
+//! clock and a seeded generator, so that whole runs of real `Server`s can be reproduced.
+
+use std::io;
+
+use mio::{EventLoop, Handler, Token};
+use rand;
+
+use {Config, Error, Result};
+
+/// The source of the timers of a `Server`, and of the randomness of its election timeouts.
+pub trait Scheduler: Send + 'static {
+
+    /// Picks a random election timeout between the bounds set by the `Config`, in milliseconds.
+    fn election_timeout(&mut self, config: &Config) -> u64;
+
+    /// Arms the timer identified by the token, to fire once after `delay` milliseconds.
+    fn schedule<H>(&mut self, event_loop: &mut EventLoop<H>, token: Token, delay: u64) -> Result<()>
+    where H: Handler<Timeout=Token>;
+}
+
+/// The `Scheduler` of a running `Server`, which arms the timers of its event loop and draws from
+/// the random number generator of its thread.
+pub struct EventLoopScheduler;
+
+impl Scheduler for EventLoopScheduler {
+
+    fn election_timeout(&mut self, config: &Config) -> u64 {
+        config.election_timeout(&mut rand::thread_rng())
+    }
+
+    fn schedule<H>(&mut self, event_loop: &mut EventLoop<H>, token: Token, delay: u64) -> Result<()>
+    where H: Handler<Timeout=Token> {
+        event_loop.timeout_ms(token, delay)
+                  .map(|_| ())
+                  .map_err(|error| {
+                      Error::Io(io::Error::new(io::ErrorKind::Other,
+                                               format!("unable to schedule a timeout: {:?}", error)))
+                  })
+    }
+}
@@ -1,24 +1,24 @@
-use std::thread;
-use std::collections::HashSet;
+use std::thread::{self, JoinHandle};
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
-use std::collections::VecDeque;
-use std::io::BufReader;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
 
 // MIO
-use mio::tcp::{listen, TcpListener, TcpStream};
-use mio::util::Slab;
-use mio::Socket;
-use mio::buf::{RingBuf};
-use mio::{Interest, PollOpt, NonBlock, Token, EventLoop, Handler, ReadHint};
-use mio::buf::Buf;
-use mio::{TryRead, TryWrite};
-
-use rand::{self, Rng};
+use mio::{Token, EventLoop, EventLoopSender, Handler, ReadHint};
 
 // Data structures.
+use {Config, Error, ErrorKind, Event, LogIndex, NodeId, Result, Term};
+use address_book::AddressBook;
+use client::Reply;
 use store::Store;
-use replica::{Replica, Emit, Broadcast};
+use replica::{Append, Replica, Emit, Broadcast};
+use scheduler::{EventLoopScheduler, Scheduler};
 use state_machine::StateMachine;
+use transport::{Notification, Transport};
+use frame::pack;
 
 // Cap'n Proto
 use capnp::serialize_packed;
@@ -30,25 +30,18 @@ use capnp::{
     OwnedSpaceMessageReader,
 };
 use messages_capnp::{
+    message,
     rpc_request,
     rpc_response,
     client_request,
     client_response,
-    request_vote_response,
-    append_entries_response,
-    append_entries_request,
 };
-use super::{Error, Result};
 
 // MIO Tokens
 const ELECTION_TIMEOUT: Token = Token(0);
 const HEARTBEAT_TIMEOUT: Token = Token(1);
-const LISTENER:  Token = Token(2);
+const IDLE_TIMEOUT: Token = Token(2);
 
-const ELECTION_MIN: u64 = 150;
-const ELECTION_MAX: u64 = 300;
-const HEARTBEAT_DURATION: u64 = 50;
-const RINGBUF_SIZE: usize = 4096;
 
 /// The Raft Distributed Consensus Algorithm requires two RPC calls to be available:
 ///
@@ -60,94 +53,695 @@ const RINGBUF_SIZE: usize = 4096;
 /// own status. It will maintain both volatile state (which can be safely lost) and persistent
 /// state (which must be carefully stored and kept safe).
 ///
+/// Messages are exchanged with peers and clients through a `Transport`. Peers are identified by
+/// their `NodeId`, and reached at the latest address they announced in their messages. Messages
+/// whose sender is not the node the transport received them from are ignored.
+///
 /// Currently, the `Server` API is not well defined. **We are looking for feedback and suggestions.**
-pub struct Server<S, M> where S: Store, M: StateMachine {
+pub struct Server<S, M, T, C> where S: Store, M: StateMachine, T: Transport, C: Scheduler {
     replica: Replica<S, M>,
-    // Channels and Sockets
-    listener: NonBlock<TcpListener>,
-    connections: Slab<Connection>,
+    /// The address on which this node accepts connections, announced to peers.
+    addr: SocketAddr,
+    /// The latest known address of each cluster member, including this node.
+    addresses: AddressBook,
+    transport: T,
+    /// The source of the timers and of the election timeouts.
+    scheduler: C,
+    config: Config,
+    /// The entries appended by clients which have not been applied yet, in log order.
+    proposals: VecDeque<Proposal>,
+    /// The channels to which changes to the role, term and leader of the replica are sent.
+    subscribers: Vec<Sender<Event>>,
+    /// The replies to the pending requests of the related `Raft`, by request ID. Dropped without
+    /// an answer when the `Server` stops.
+    local_requests: HashMap<u64, Reply>,
+}
+
+/// Clears the flag of a running `Server` once its thread stops, even by panicking.
+struct Running(Arc<AtomicBool>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Entries appended by a client, which is answered once the entries are applied.
+struct Proposal {
+    /// The index of the last of the entries.
+    index: LogIndex,
+    /// The term of the leader which appended the entries. Entries found at `index` with another
+    /// term have replaced them, so they were never committed.
+    term: Term,
+    /// The number of entries of an `appendBatch` request, which is answered with their indexes,
+    /// or `None` for an `append` request.
+    batch: Option<u64>,
+    client: SocketAddr,
+    request_id: u64,
 }
 
 /// The implementation of the Server. In most use cases, creating a `Server` should just be
 /// done via `::new()`.
-impl<S, M> Server<S, M> where S: Store, M: StateMachine {
+impl<S, M, T, C> Server<S, M, T, C> where S: Store, M: StateMachine, T: Transport, C: Scheduler {
 
-    /// Creates a new Raft node with the cluster members specified.
+    /// Creates a new Raft node with the cluster members specified. The node does nothing until it
+    /// is started on an event loop with `start()`.
     ///
     /// # Arguments
     ///
-    /// * `addr` - The address of the new node.
-    /// * `peers` - The address of all peers in the Raft cluster.
+    /// * `id` - The persistent ID of the new node.
+    /// * `addr` - The address on which the new node accepts connections.
+    /// * `peers` - The ID and address of all peers in the Raft cluster.
     /// * `store` - The persitent log store.
     /// * `state_machine` - The client state machine to which client commands will be applied.
-    pub fn spawn(addr: SocketAddr,
-                 peers: HashSet<SocketAddr>,
+    /// * `transport` - The transport of messages to peers and clients.
+    /// * `scheduler` - The source of the timers and of the election timeouts of the node.
+    /// * `config` - The timing and resource limits of the node. It must be valid.
+    pub fn new(id: NodeId,
+               addr: SocketAddr,
+               peers: HashMap<NodeId, SocketAddr>,
+               store: S,
+               state_machine: M,
+               transport: T,
+               scheduler: C,
+               config: Config)
+               -> Result<Server<S, M, T, C>> {
+        let replica = try!(Replica::new(id, peers.keys().cloned().collect(), store, state_machine, &config));
+        let mut addresses = AddressBook::new(peers);
+        addresses.insert(id, addr);
+        Ok(Server {
+            replica: replica,
+            addr: addr,
+            addresses: addresses,
+            transport: transport,
+            scheduler: scheduler,
+            config: config,
+            proposals: VecDeque::new(),
+            subscribers: Vec::new(),
+            local_requests: HashMap::new(),
+        })
+    }
+
+    /// Registers the transport of the node with the event loop, and arms its timers.
+    pub fn start(&mut self, event_loop: &mut EventLoop<Server<S, M, T, C>>) -> Result<()> {
+        try!(self.transport.register(event_loop));
+        let timeout = self.scheduler.election_timeout(&self.config);
+        try!(self.scheduler.schedule(event_loop, ELECTION_TIMEOUT, timeout));
+        let interval = self.config.heartbeat_interval;
+        try!(self.scheduler.schedule(event_loop, HEARTBEAT_TIMEOUT, interval));
+        let timeout = self.config.connection_idle_timeout;
+        try!(self.scheduler.schedule(event_loop, IDLE_TIMEOUT, timeout));
+        Ok(())
+    }
+
+    /// Arms the timer identified by the token again, after it fired. A node whose timers can not
+    /// be armed can no longer take part in the cluster, so it halts.
+    fn reschedule(&mut self, event_loop: &mut EventLoop<Server<S, M, T, C>>,
+                  token: Token, delay: u64) {
+        if let Err(error) = self.scheduler.schedule(event_loop, token, delay) {
+            self.replica.halt(error);
+        }
+    }
+
+    /// Returns the replica of the node, for inspecting its state.
+    #[cfg(test)]
+    pub fn replica(&self) -> &Replica<S, M> {
+        &self.replica
+    }
+
+    /// Stops the event loop once the current iteration is done. The `Store` is synced, and the
+    /// pending messages are flushed before the connections are closed.
+    fn shutdown(&mut self, event_loop: &mut EventLoop<Server<S, M, T, C>>) {
+        info!("{:?}: Shutting down", self.replica);
+        // Ends the subscriptions.
+        self.subscribers.clear();
+        if let Err(error) = self.replica.sync() {
+            error!("{:?}: unable to sync the store: {:?}", self.replica, error);
+        }
+        if let Err(error) = self.transport.shutdown(event_loop) {
+            warn!("{:?}: unable to shut down the transport: {:?}", self.replica, error);
+        }
+        event_loop.shutdown();
+    }
+
+    /// Handles a message received from the provided address. `peer` is the ID the sender presented
+    /// in the handshake of its connection, which is authenticated only if the cluster has keys.
+    fn handle(&mut self, event_loop: &mut EventLoop<Server<S, M, T, C>>,
+              from: SocketAddr, peer: NodeId, reader: OwnedSpaceMessageReader) {
+        let message = match reader.get_root::<message::Reader>() {
+            Ok(message) => message,
+            Err(error) => {
+                warn!("{:?}: unable to decode message from {}: {:?}", self.replica, from, error);
+                return;
+            },
+        };
+        match message.which() {
+            Ok(message::Which::RpcRequest(Ok(request))) => {
+                if let Some(sender) = self.sender(from, peer, message) {
+                    self.handle_rpc_request(event_loop, from, sender, request)
+                }
+            },
+            Ok(message::Which::RpcResponse(Ok(response))) => {
+                if let Some(sender) = self.sender(from, peer, message) {
+                    self.handle_rpc_response(event_loop, from, sender, response)
+                }
+            },
+            Ok(message::Which::ClientRequest(Ok(request))) => {
+                self.handle_client_request(event_loop, from, request)
+            },
+            _ => {
+                warn!("{:?}: ignoring unknown message from {}: incompatible protocol version.",
+                      self.replica, from);
+            },
+        }
+    }
+
+    /// Handles a `ClientRequest` from the related `Raft`, which is answered through the provided
+    /// `Reply`. The request is handled as if it came from the address of this node, which no client
+    /// connection can share.
+    fn handle_local_request(&mut self, event_loop: &mut EventLoop<Server<S, M, T, C>>,
+                            reader: OwnedSpaceMessageReader, reply: Reply) {
+        let message = match reader.get_root::<message::Reader>() {
+            Ok(message) => message,
+            Err(error) => {
+                warn!("{:?}: unable to decode a local request: {:?}", self.replica, error);
+                return;
+            },
+        };
+        match message.which() {
+            Ok(message::Which::ClientRequest(Ok(request))) => {
+                self.local_requests.insert(request.get_request_id(), reply);
+                let addr = self.addr;
+                self.handle_client_request(event_loop, addr, request)
+            },
+            _ => {
+                warn!("{:?}: ignoring unknown local request: incompatible protocol version.",
+                      self.replica);
+            },
+        }
+    }
+
+    /// Identifies the peer which sent a message, and records the address it announced. Returns
+    /// `None` if the sender is not a member of the cluster, or is not the node which the message
+    /// was received from.
+    fn sender(&mut self, from: SocketAddr, peer: NodeId, message: message::Reader) -> Option<NodeId> {
+        let id = match message.get_sender().ok().and_then(NodeId::from_bytes) {
+            Some(id) => id,
+            None => {
+                warn!("{:?}: ignoring message without a sender ID from {}.", self.replica, from);
+                return None;
+            },
+        };
+        if id != peer {
+            warn!("{:?}: ignoring message from {} claiming to be sent by {} rather than {}.",
+                  self.replica, from, id, peer);
+            return None;
+        }
+        if !self.replica.peers().contains(&id) {
+            warn!("{:?}: ignoring message from unknown node {} at {}.", self.replica, id, from);
+            return None;
+        }
+        match message.get_sender_address().ok().and_then(|addr| SocketAddr::from_str(addr).ok()) {
+            Some(addr) => {
+                if let Some(previous) = self.addresses.insert(id, addr) {
+                    info!("{:?}: Replica({}) moved from {} to {}.", self.replica, id, previous, addr);
+                }
+            },
+            None => warn!("{:?}: Replica({}) sent an invalid address.", self.replica, id),
+        }
+        Some(id)
+    }
+
+    /// Handles an `RpcRequest` from a peer. A response is always sent.
+    fn handle_rpc_request(&mut self, event_loop: &mut EventLoop<Server<S, M, T, C>>,
+                          from: SocketAddr, sender: NodeId, request: rpc_request::Reader) {
+        if let Some(reason) = self.replica.halted().map(|reason| reason.to_string()) {
+            // A halted replica answers every request with the reason it halted.
+            self.emit_internal_error(event_loop, from, request, &reason);
+            return;
+        }
+        let mut builder_message = MallocMessageBuilder::new_default();
+        let result = {
+            let response = builder_message.init_root::<message::Builder>().init_rpc_response();
+            match request.which() {
+                Ok(rpc_request::Which::AppendEntries(Ok(call))) => {
+                    self.replica.append_entries_request(sender, call, response.init_append_entries())
+                },
+                Ok(rpc_request::Which::RequestVote(Ok(call))) => {
+                    self.replica.request_vote_request(sender, call, response.init_request_vote())
+                },
+                _ => {
+                    warn!("{:?}: ignoring unknown RpcRequest from {}: incompatible protocol version.",
+                          self.replica, from);
+                    return;
+                },
+            }
+        };
+        match result {
+            Ok(Some(Emit)) => self.emit(event_loop, from, &mut builder_message),
+            Ok(None) => (),
+            Err(error) => {
+                let reason = self.replica.halt(error);
+                self.emit_internal_error(event_loop, from, request, &reason);
+            },
+        }
+    }
+
+    /// Handles an `RpcResponse` from a peer.
+    fn handle_rpc_response(&mut self, event_loop: &mut EventLoop<Server<S, M, T, C>>,
+                           from: SocketAddr, sender: NodeId, response: rpc_response::Reader) {
+        if self.replica.halted().is_some() {
+            // A halted replica has no use for responses.
+            return;
+        }
+        let mut builder_message = MallocMessageBuilder::new_default();
+        match response.which() {
+            Ok(rpc_response::Which::AppendEntries(Ok(call))) => {
+                let respond = {
+                    let request = builder_message.init_root::<message::Builder>().init_rpc_request();
+                    self.replica.append_entries_response(sender, call, request.init_append_entries())
+                };
+                match respond {
+                    Ok(Some(Emit)) => self.emit(event_loop, from, &mut builder_message),
+                    Ok(None) => (),
+                    Err(error) => { self.replica.halt(error); },
+                }
+            },
+            Ok(rpc_response::Which::RequestVote(Ok(call))) => {
+                let respond = {
+                    let request = builder_message.init_root::<message::Builder>().init_rpc_request();
+                    self.replica.request_vote_response(sender, call, request.init_append_entries())
+                };
+                match respond {
+                    Ok(Some(Broadcast)) => {
+                        // Won an election!
+                        self.broadcast(event_loop, &mut builder_message);
+                    },
+                    Ok(None) => (),
+                    Err(error) => { self.replica.halt(error); },
+                }
+            },
+            _ => {
+                warn!("{:?}: ignoring unknown RpcResponse from {}: incompatible protocol version.",
+                      self.replica, from);
+            },
+        }
+    }
+
+    /// Handles a `ClientRequest`.
+    fn handle_client_request(&mut self, event_loop: &mut EventLoop<Server<S, M, T, C>>,
+                             from: SocketAddr, request: client_request::Reader) {
+        let request_id = request.get_request_id();
+        if let Some(reason) = self.replica.halted().map(|reason| reason.to_string()) {
+            // A halted replica answers every client request with the reason it halted.
+            self.emit_client_internal_error(event_loop, from, request_id, &reason);
+            return;
+        }
+        let mut builder_message = MallocMessageBuilder::new_default();
+        let mut should_die = false;
+        // We will be responding.
+        match request.which() {
+            Ok(client_request::Which::Append(Ok(call))) => {
+                self.client_append(event_loop, from, request_id, &[call], None);
+            },
+            Ok(client_request::Which::AppendBatch(Ok(call))) => {
+                match (0..call.len()).map(|n| call.get(n)).collect::<::std::result::Result<Vec<_>, _>>() {
+                    Ok(entries) => {
+                        let batch = Some(entries.len() as u64);
+                        self.client_append(event_loop, from, request_id, &entries, batch);
+                    },
+                    Err(error) => {
+                        warn!("{:?}: unable to decode the batch from Client({}): {:?}", self.replica, from, error);
+                        let reason = format!("unable to decode the batch: {}", error);
+                        self.emit_client_bad_request(event_loop, from, request_id, &reason);
+                    },
+                }
+            },
+            Ok(client_request::Which::Die(Ok(call))) => {
+                should_die = true;
+                {
+                    let mut response = builder_message.init_root::<message::Builder>().init_client_response();
+                    response.set_request_id(request_id);
+                    response.set_success(());
+                }
+                self.emit(event_loop, from, &mut builder_message);
+                debug!("Got a Die request from Client({}). Reason: {}", from, call);
+            },
+            Ok(client_request::Which::LeaderRefresh(())) => {
+                let respond = {
+                    let mut response = builder_message.init_root::<message::Builder>().init_client_response();
+                    response.set_request_id(request_id);
+                    self.replica.client_leader_refresh(from, &self.addresses, response)
+                };
+                match respond {
+                    Ok(Some(Emit)) => self.emit(event_loop, from, &mut builder_message),
+                    Ok(None) => (),
+                    Err(error) => {
+                        let reason = self.replica.halt(error);
+                        self.emit_client_internal_error(event_loop, from, request_id, &reason);
+                    },
+                }
+            },
+            Ok(client_request::Which::Status(())) => {
+                let respond = {
+                    let mut response = builder_message.init_root::<message::Builder>().init_client_response();
+                    response.set_request_id(request_id);
+                    let mut status = response.init_status();
+                    status.set_rejected_messages(self.transport.rejected_messages());
+                    self.replica.status(status)
+                };
+                match respond {
+                    Ok(()) => self.emit(event_loop, from, &mut builder_message),
+                    Err(error) => {
+                        let reason = self.replica.halt(error);
+                        self.emit_client_internal_error(event_loop, from, request_id, &reason);
+                    },
+                }
+            },
+            _ => {
+                warn!("{:?}: ignoring unknown ClientRequest from {}: incompatible protocol version.",
+                      self.replica, from);
+                self.emit_client_bad_request(event_loop, from, request_id, "unknown request");
+            },
+        };
+
+        // Do this here so that we can send the response.
+        if should_die {
+            self.shutdown(event_loop);
+        }
+    }
+
+    /// Queues an `internalError` response to a peer's request, on behalf of a halted replica.
+    fn emit_internal_error(&mut self, event_loop: &mut EventLoop<Server<S, M, T, C>>,
+                           to: SocketAddr, request: rpc_request::Reader, reason: &str) {
+        let mut message = MallocMessageBuilder::new_default();
+        {
+            let response = message.init_root::<message::Builder>().init_rpc_response();
+            match request.which() {
+                Ok(rpc_request::Which::AppendEntries(_)) => {
+                    response.init_append_entries().set_internal_error(reason);
+                },
+                Ok(rpc_request::Which::RequestVote(_)) => {
+                    response.init_request_vote().set_internal_error(reason);
+                },
+                Err(_) => return,
+            }
+        }
+        self.emit(event_loop, to, &mut message);
+    }
+
+    /// Appends the entries of a client request, which is answered once they are applied, unless
+    /// it is redirected to the leader.
+    fn client_append(&mut self, event_loop: &mut EventLoop<Server<S, M, T, C>>, from: SocketAddr,
+                     request_id: u64, entries: &[&[u8]], batch: Option<u64>) {
+        let mut response_message = MallocMessageBuilder::new_default();
+        let mut request_message = MallocMessageBuilder::new_default();
+        let respond = {
+            let mut response = response_message.init_root::<message::Builder>().init_client_response();
+            response.set_request_id(request_id);
+            let request = request_message.init_root::<message::Builder>().init_rpc_request();
+            self.replica.client_append(from, entries, &self.addresses, response, request.init_append_entries())
+        };
+        match respond {
+            Ok(Append::Proposed(index, term)) => {
+                self.proposals.push_back(Proposal {
+                    index: index,
+                    term: term,
+                    batch: batch,
+                    client: from,
+                    request_id: request_id,
+                });
+                self.broadcast(event_loop, &mut request_message);
+            },
+            Ok(Append::Redirect) => self.emit(event_loop, from, &mut response_message),
+            Err(error) => {
+                let reason = self.replica.halt(error);
+                self.emit_client_internal_error(event_loop, from, request_id, &reason);
+            },
+        }
+    }
+
+    /// Queues an `internalError` response to a client request, on behalf of a halted replica.
+    fn emit_client_internal_error(&mut self, event_loop: &mut EventLoop<Server<S, M, T, C>>,
+                                  to: SocketAddr, request_id: u64, reason: &str) {
+        let mut message = MallocMessageBuilder::new_default();
+        {
+            let mut response = message.init_root::<message::Builder>().init_client_response();
+            response.set_request_id(request_id);
+            response.set_internal_error(reason);
+        }
+        self.emit(event_loop, to, &mut message);
+    }
+
+    /// Queues a `badRequest` response to a client request which can not be handled, so that the
+    /// client does not wait for it in vain.
+    fn emit_client_bad_request(&mut self, event_loop: &mut EventLoop<Server<S, M, T, C>>,
+                               to: SocketAddr, request_id: u64, reason: &str) {
+        let mut message = MallocMessageBuilder::new_default();
+        {
+            let mut response = message.init_root::<message::Builder>().init_client_response();
+            response.set_request_id(request_id);
+            response.set_bad_request(reason);
+        }
+        self.emit(event_loop, to, &mut message);
+    }
+
+    /// Sends the changes to the role, term and leader of the replica to the subscribers, dropping
+    /// those which are gone. A halted replica changes no further, so its subscribers are dropped
+    /// once they have been sent the changes up to the halt, which ends their subscription.
+    fn publish_changes(&mut self) {
+        for event in self.replica.changes() {
+            self.subscribers.retain(|subscriber| subscriber.send(event).is_ok());
+        }
+        if self.replica.halted().is_some() {
+            self.subscribers.clear();
+        }
+    }
+
+    /// Answers the clients whose entries have been applied. A deposed leader may apply the
+    /// entries of a new leader in place of those it proposed, so a proposal is only acknowledged
+    /// if the applied entry at its index is from its term; otherwise the client is redirected.
+    /// The entries of the remaining proposals may never be committed once the replica is no longer
+    /// the leader, so their clients are redirected to the new leader, or told the reason the
+    /// replica halted.
+    fn answer_proposals(&mut self, event_loop: &mut EventLoop<Server<S, M, T, C>>) {
+        let last_applied = self.replica.last_applied();
+        while self.proposals.front().map(|proposal| proposal.index <= last_applied).unwrap_or(false) {
+            let proposal = self.proposals.pop_front().unwrap();
+            let committed = match self.replica.entry_term(proposal.index) {
+                Ok(term) => term == proposal.term,
+                Err(error) => {
+                    let reason = self.replica.halt(error);
+                    self.emit_client_internal_error(event_loop, proposal.client,
+                                                    proposal.request_id, &reason);
+                    continue;
+                },
+            };
+            let mut message = MallocMessageBuilder::new_default();
+            {
+                let mut response = message.init_root::<message::Builder>().init_client_response();
+                response.set_request_id(proposal.request_id);
+                if !committed {
+                    warn!("{:?}: entries of request {} from {} were replaced before commit",
+                          self.replica, proposal.request_id, proposal.client);
+                    self.replica.redirect(&self.addresses, response);
+                } else {
+                    match proposal.batch {
+                        Some(count) => {
+                            let last: u64 = proposal.index.into();
+                            let mut indexes = response.init_appended(count as u32);
+                            for n in 0..count {
+                                indexes.set(n as u32, last - count + 1 + n);
+                            }
+                        },
+                        None => response.set_success(()),
+                    }
+                }
+            }
+            self.emit(event_loop, proposal.client, &mut message);
+        }
+
+        if let Some(reason) = self.replica.halted().map(|reason| reason.to_string()) {
+            while let Some(proposal) = self.proposals.pop_front() {
+                self.emit_client_internal_error(event_loop, proposal.client, proposal.request_id, &reason);
+            }
+        } else if !self.replica.is_leader() {
+            while let Some(proposal) = self.proposals.pop_front() {
+                let mut message = MallocMessageBuilder::new_default();
+                {
+                    let mut response = message.init_root::<message::Builder>().init_client_response();
+                    response.set_request_id(proposal.request_id);
+                    self.replica.redirect(&self.addresses, response);
+                }
+                self.emit(event_loop, proposal.client, &mut message);
+            }
+        }
+    }
+
+    /// Stamps the message with the ID and address of this node, and packs it. The transport seals
+    /// it with the cluster key.
+    fn stamp(&self, builder: &mut MallocMessageBuilder) -> Vec<u8> {
+        {
+            let mut message = builder.get_root::<message::Builder>().unwrap();
+            message.set_sender(self.replica.id().as_bytes());
+            message.set_sender_address(&self.addr.to_string());
+        }
+        pack(builder)
+    }
+
+    /// Sends the message to the provided address.
+    fn emit(&mut self, event_loop: &mut EventLoop<Server<S, M, T, C>>,
+            to: SocketAddr, builder: &mut MallocMessageBuilder) {
+        if to == self.addr {
+            // A response to the related `Raft`.
+            self.reply_locally(builder);
+            return;
+        }
+        let message = self.stamp(builder);
+        if let Err(error) = self.transport.send(event_loop, to, &message) {
+            warn!("{:?}: unable to send message to {}: {:?}", self.replica, to, error);
+        }
+    }
+
+    /// Sends a `ClientResponse` to the related `Raft`, through the reply of the request it answers.
+    fn reply_locally(&mut self, builder: &mut MallocMessageBuilder) {
+        let message = pack(builder);
+        match response_request_id(&message) {
+            Ok(request_id) => match self.local_requests.remove(&request_id) {
+                Some(reply) => reply.send(&message),
+                None => warn!("{:?}: response to an unknown local request {}", self.replica, request_id),
+            },
+            Err(error) => warn!("{:?}: unable to answer a local request: {:?}", self.replica, error),
+        }
+    }
+
+    /// Sends the message to every peer.
+    fn broadcast(&mut self, event_loop: &mut EventLoop<Server<S, M, T, C>>,
+                 builder: &mut MallocMessageBuilder) {
+        let message = self.stamp(builder);
+        let peers: Vec<NodeId> = self.replica.peers().iter().cloned().collect();
+        for peer in peers {
+            let addr = match self.addresses.get(&peer) {
+                Some(addr) => addr,
+                None => {
+                    // The peer will be reached once it has announced its address.
+                    debug!("{:?}: the address of Replica({}) is unknown.", self.replica, peer);
+                    continue;
+                },
+            };
+            if let Err(error) = self.transport.send(event_loop, addr, &message) {
+                warn!("{:?}: unable to send message to {}: {:?}", self.replica, addr, error);
+            }
+        }
+    }
+}
+
+impl<S, M, T> Server<S, M, T, EventLoopScheduler> where S: Store, M: StateMachine, T: Transport {
+
+    /// Creates a new Raft node as described by `new()`, starts it on an event loop of its own, and
+    /// runs the event loop on a new thread.
+    ///
+    /// Returns a channel to the event loop of the node, on which `Notification::Shutdown` stops
+    /// it, along with the handle of the node's thread, and a flag which is set while the thread
+    /// runs. The node is listening once this returns; failing to create the event loop, to bind
+    /// the address, to open the `Store` or to start the thread is reported here.
+    pub fn spawn(id: NodeId,
+                 addr: SocketAddr,
+                 peers: HashMap<NodeId, SocketAddr>,
                  store: S,
-                 state_machine: M) {
+                 state_machine: M,
+                 transport: T,
+                 config: Config)
+                 -> Result<(EventLoopSender<Notification>, JoinHandle<()>, Arc<AtomicBool>)> {
         debug!("Spawning Server");
         // Create an event loop
-        let mut event_loop = EventLoop::<Server<S, M>>::new().unwrap();
-        // Setup the socket, make it not block.
-        let listener = listen(&addr).unwrap();
-        listener.set_reuseaddr(true).unwrap();
-        event_loop.register(&listener, LISTENER).unwrap();
-        let timeout = rand::thread_rng().gen_range::<u64>(ELECTION_MIN, ELECTION_MAX);
-        event_loop.timeout_ms(ELECTION_TIMEOUT, timeout).unwrap();
-        event_loop.timeout_ms(HEARTBEAT_TIMEOUT, HEARTBEAT_DURATION).unwrap();
-        let replica = Replica::new(addr, peers, store, state_machine);
+        let mut event_loop = try!(EventLoop::<Server<S, M, T, EventLoopScheduler>>::new());
+        let mut raft_node = try!(Server::new(id, addr, peers, store, state_machine, transport,
+                                             EventLoopScheduler, config));
+        try!(raft_node.start(&mut event_loop));
+        let sender = event_loop.channel();
+        let running = Arc::new(AtomicBool::new(true));
+        let running_flag = Running(running.clone());
         // Fire up the thread.
-        thread::Builder::new().name(format!("Server {}", addr)).spawn(move || {
-            let mut raft_node = Server {
-                listener: listener,
-                replica: replica,
-                connections: Slab::new_starting_at(Token(2), 128),
-            };
-            event_loop.run(&mut raft_node).unwrap();
-        }).unwrap();
+        let handle = try!(thread::Builder::new().name(format!("Server {}", id)).spawn(move || {
+            let _running = running_flag;
+            if let Err(error) = event_loop.run(&mut raft_node) {
+                error!("{:?}: the event loop failed: {:?}", raft_node.replica, error);
+            }
+        }));
+        Ok((sender, handle, running))
     }
 }
 
-impl<S, M> Handler for Server<S, M> where S: Store, M: StateMachine {
+impl<S, M, T, C> Handler for Server<S, M, T, C>
+where S: Store, M: StateMachine, T: Transport, C: Scheduler {
 
-    type Message = RingBuf;
+    type Message = Notification;
     type Timeout = Token;
 
     /// A registered IoHandle has available writing space.
-    fn writable(&mut self, reactor: &mut EventLoop<Server<S, M>>, token: Token) {
+    fn writable(&mut self, reactor: &mut EventLoop<Server<S, M, T, C>>, token: Token) {
         debug!("Writeable");
-        match token {
-            ELECTION_TIMEOUT => unreachable!(),
-            HEARTBEAT_TIMEOUT => unreachable!(),
-            LISTENER => unreachable!(),
-            tok => {
-                self.connections[tok].writable(reactor, &mut self.replica).unwrap();
-            }
+        if let Err(error) = self.transport.writable(reactor, token) {
+            warn!("{:?}: transport error while writing: {:?}", self.replica, error);
         }
     }
 
     /// A registered IoHandle has available data to read
-    fn readable(&mut self, reactor: &mut EventLoop<Server<S, M>>, token: Token, _hint: ReadHint) {
+    fn readable(&mut self, reactor: &mut EventLoop<Server<S, M, T, C>>, token: Token, hint: ReadHint) {
         debug!("Readable");
-        match token {
-            ELECTION_TIMEOUT => unreachable!(),
-            HEARTBEAT_TIMEOUT => unreachable!(),
-            LISTENER => {
-                let stream = match self.listener.accept().unwrap() {
-                    Some(s) => s,
-                    None => return, // Socket isn't quite ready.
-                }; // Result<Option<_>,_>
-                let conn = Connection::new(stream);
-                let tok = self.connections.insert(conn)
-                    .ok().expect("Could not add connection to slab.");
-
-                // Register the connection
-                self.connections[tok].token = tok;
-                reactor.register_opt(&self.connections[tok].stream, tok, Interest::readable(), PollOpt::edge() | PollOpt::oneshot())
-                    .ok().expect("Could not register socket with event loop.");
-            },
-            tok => {
-                self.connections[tok].readable(reactor, &mut self.replica).unwrap();
-            }
+        let messages = match self.transport.readable(reactor, token, hint) {
+            Ok(messages) => messages,
+            Err(error) => {
+                warn!("{:?}: transport error while reading: {:?}", self.replica, error);
+                return;
+            },
+        };
+        for (from, peer, reader) in messages {
+            self.handle(reactor, from, peer, reader);
+        }
+        self.answer_proposals(reactor);
+        self.publish_changes();
+    }
+
+    /// A notification has arrived through the event loop channel.
+    fn notify(&mut self, reactor: &mut EventLoop<Server<S, M, T, C>>, notification: Notification) {
+        match notification {
+            Notification::Received(from, peer, message) => {
+                match serialize_packed::read_message(&mut &message[..], ReaderOptions::new()) {
+                    Ok(reader) => self.handle(reactor, from, peer, reader),
+                    Err(error) => {
+                        warn!("{:?}: unable to decode message from {}: {:?}", self.replica, from, error);
+                    },
+                }
+                self.answer_proposals(reactor);
+                self.publish_changes();
+            },
+            Notification::Shutdown => self.shutdown(reactor),
+            Notification::ClusterKeys(keys) => {
+                info!("{:?}: Using {} new cluster keys", self.replica, keys.len());
+                self.config.cluster_keys = keys.clone();
+                self.transport.set_cluster_keys(keys);
+            },
+            Notification::Request(message, reply) => {
+                match serialize_packed::read_message(&mut &message[..], ReaderOptions::new()) {
+                    Ok(reader) => self.handle_local_request(reactor, reader, reply),
+                    Err(error) => {
+                        warn!("{:?}: unable to decode a local request: {:?}", self.replica, error);
+                    },
+                }
+                self.answer_proposals(reactor);
+                self.publish_changes();
+            },
+            Notification::Subscribe(subscriber) => {
+                // The subscriber first learns the current state, then each change to it, unless
+                // the replica has halted.
+                self.publish_changes();
+                if self.replica.reported().into_iter().all(|event| subscriber.send(event).is_ok())
+                        && self.replica.halted().is_none() {
+                    self.subscribers.push(subscriber);
+                }
+            },
         }
     }
 
@@ -157,266 +751,300 @@ impl<S, M> Handler for Server<S, M> where S: Store, M: StateMachine {
     /// to become a `Candidate`.
     /// * A heartbeat timeout, when the `Leader` node needs to refresh it's authority over the
     /// followers. Initializes and sends an `AppendEntries` request to all followers.
-    fn timeout(&mut self, reactor: &mut EventLoop<Server<S, M>>, token: Token) {
+    /// * An idle timeout, when the transport should close the connections which have been idle
+    /// since the previous one.
+    fn timeout(&mut self, reactor: &mut EventLoop<Server<S, M, T, C>>, token: Token) {
         debug!("Timeout");
         let mut message = MallocMessageBuilder::new_default();
-        let mut send_message = None;
-        match token {
+        let send_message = match token {
             ELECTION_TIMEOUT => {
-                let request = message.init_root::<rpc_request::Builder>();
-                send_message = self.replica.election_timeout(request.init_request_vote());
                 // Set timeout.
-                let timeout = rand::thread_rng().gen_range::<u64>(ELECTION_MIN, ELECTION_MAX);
-                reactor.timeout_ms(ELECTION_TIMEOUT, timeout).unwrap();
-
+                let timeout = self.scheduler.election_timeout(&self.config);
+                self.reschedule(reactor, ELECTION_TIMEOUT, timeout);
+                if self.replica.halted().is_some() { return; }
+                let request = message.init_root::<message::Builder>().init_rpc_request();
+                self.replica.election_timeout(request.init_request_vote())
             },
             HEARTBEAT_TIMEOUT => {
-                let request = message.init_root::<rpc_request::Builder>();
-                send_message = self.replica.heartbeat_timeout(request.init_append_entries());
                 // Set Timeout
-                reactor.timeout_ms(HEARTBEAT_TIMEOUT, HEARTBEAT_DURATION).unwrap();
+                let interval = self.config.heartbeat_interval;
+                self.reschedule(reactor, HEARTBEAT_TIMEOUT, interval);
+                if self.replica.halted().is_some() { return; }
+                let request = message.init_root::<message::Builder>().init_rpc_request();
+                self.replica.heartbeat_timeout(request.init_append_entries())
+            },
+            IDLE_TIMEOUT => {
+                let timeout = self.config.connection_idle_timeout;
+                self.reschedule(reactor, IDLE_TIMEOUT, timeout);
+                if let Err(error) = self.transport.close_idle(reactor) {
+                    warn!("{:?}: unable to close idle connections: {:?}", self.replica, error);
+                }
+                return;
             },
             _ => unreachable!(),
-        }
+        };
         // Send if necessary.
         match send_message {
-            Some(Broadcast) => {
-                let mut buf = RingBuf::new(RINGBUF_SIZE);
-                serialize_packed::write_message(
-                    &mut buf,
-                    &mut message
-                ).unwrap();
-                for connection in self.connections.iter_mut() {
-                    connection.add_write(buf.clone());
-                }
-            },
-            None => (),
+            Ok(Some(Broadcast)) => self.broadcast(reactor, &mut message),
+            Ok(None) => (),
+            Err(error) => { self.replica.halt(error); },
         }
+        self.answer_proposals(reactor);
+        self.publish_changes();
     }
 }
 
-struct Connection {
-    stream: NonBlock<TcpStream>,
-    token: Token,
-    interest: Interest,
-    current_read: BufReader<RingBuf>,
-    current_write: BufReader<RingBuf>,
-    next_write: VecDeque<RingBuf>,
+/// Reads the request ID out of a packed `ClientResponse`.
+fn response_request_id(message: &[u8]) -> Result<u64> {
+    let reader = try!(serialize_packed::read_message(&mut &message[..], ReaderOptions::new()));
+    let request_id = match try!(try!(reader.get_root::<message::Reader>()).which()) {
+        message::Which::ClientResponse(Ok(response)) => response.get_request_id(),
+        _ => return Err(Error::Raft(ErrorKind::BadResponse)),
+    };
+    Ok(request_id)
 }
 
-impl Connection {
-    /// Note: The caller must manually assign `token` to what is desired.
-    fn new(sock: NonBlock<TcpStream>) -> Connection {
-        Connection {
-            stream: sock,
-            token: Token(0), // Effectively a `null`. This needs to be assigned by the caller.
-            interest: Interest::hup(),
-            current_read: BufReader::new(RingBuf::new(4096)),
-            current_write: BufReader::new(RingBuf::new(4096)),
-            next_write: VecDeque::with_capacity(10),
+#[cfg(test)]
+mod test {
+
+    use std::collections::{HashMap, HashSet};
+    use std::io::Write;
+    use std::net::{SocketAddr, TcpStream};
+    use std::str::FromStr;
+
+    use capnp::serialize_packed;
+    use capnp::{MessageBuilder, MessageReader, MallocMessageBuilder, ReaderOptions};
+
+    use messages_capnp::{client_response, message};
+    use auth::{self, Opener, Sealer};
+    use client;
+    use frame::{self, pack};
+    use handshake;
+    use server::Server;
+    use {Config, Error, ErrorKind, NodeId};
+    use state_machine::ChannelStateMachine;
+    use store::MemStore;
+    use transport::{ChannelNetwork, Notification, TcpTransport};
+
+    /// Tests that a cluster of `Server`s communicating over a `ChannelNetwork` elects a leader
+    /// which all of them agree on.
+    #[test]
+    fn test_channel_transport_election() {
+        let network = ChannelNetwork::new();
+        let nodes: HashMap<NodeId, SocketAddr> = (0..3).map(|port| {
+            (NodeId::new(), SocketAddr::from_str(&format!("127.0.0.1:{}", port)).unwrap())
+        }).collect();
+        let addrs: Vec<SocketAddr> = nodes.values().cloned().collect();
+        let servers: Vec<_> = nodes.iter().map(|(id, addr)| {
+            let peers = nodes.iter()
+                             .filter(|&(peer, _)| peer != id)
+                             .map(|(peer, addr)| (*peer, *addr))
+                             .collect();
+            let (state_machine, _) = ChannelStateMachine::new();
+            Server::spawn(*id, *addr, peers, MemStore::new(), state_machine, network.transport(*id, *addr),
+                          Config::default()).unwrap()
+        }).collect();
+
+        let client = network.endpoint(SocketAddr::from_str("127.0.0.1:100").unwrap());
+        let mut request = MallocMessageBuilder::new_default();
+        request.init_root::<message::Builder>().init_client_request().set_leader_refresh(());
+        let request = pack(&mut request);
+        loop {
+            let mut leaders = HashSet::new();
+            for addr in addrs.iter() {
+                client.send(*addr, &request);
+                let (_, response) = client.recv().unwrap();
+                let reader = serialize_packed::read_message(&mut &response[..], ReaderOptions::new()).unwrap();
+                let message = reader.get_root::<message::Reader>().unwrap();
+                if let Ok(message::Which::ClientResponse(Ok(response))) = message.which() {
+                    if let Ok(client_response::Which::NotLeader(Ok(leader))) = response.which() {
+                        leaders.insert(leader.to_string());
+                        continue;
+                    }
+                }
+                // This node does not know the leader yet.
+                leaders.insert(String::new());
+            }
+            if leaders.len() == 1 && !leaders.contains("") {
+                break;
+            }
+        }
+
+        for (sender, handle, _) in servers {
+            sender.send(Notification::Shutdown).ok().expect("Server stopped early.");
+            handle.join().ok().expect("Server panicked.");
         }
     }
 
-    /// A registered IoHandle has available writing space.
-    fn writable<S, M>(&mut self, event_loop: &mut EventLoop<Server<S, M>>, replica: &mut Replica<S,M>)
-                      -> Result<()>
-    where S: Store, M: StateMachine {
-        // Attempt to write data.
-        // The `current_write` buffer will be advanced based on how much we wrote.
-        match self.stream.write(self.current_write.get_mut()) {
-            Ok(None) => {
-                // This is a buffer flush. WOULDBLOCK
-                self.interest.insert(Interest::writable());
-            },
-            Ok(Some(r)) => {
-                // We managed to write data!
-                match (self.current_write.get_ref().has_remaining(), self.next_write.is_empty()) {
-                    // Need to write more of what we have.
-                    (true, _) => (),
-                    // Need to roll over.
-                    (false, false) => self.current_write = BufReader::new(self.next_write.pop_front().unwrap()),
-                    // We're done writing for now.
-                    (false, true) => self.interest.remove(Interest::writable()),
+    /// Tests that a `Die` request is answered, and stops the `Server` without a panic.
+    #[test]
+    fn test_die() {
+        let network = ChannelNetwork::new();
+        let addr = SocketAddr::from_str("127.0.0.1:0").unwrap();
+        let (state_machine, _) = ChannelStateMachine::new();
+        let id = NodeId::new();
+        let (_, handle, _) = Server::spawn(id, addr, HashMap::new(), MemStore::new(), state_machine,
+                                        network.transport(id, addr), Config::default()).unwrap();
 
+        let client = network.endpoint(SocketAddr::from_str("127.0.0.1:100").unwrap());
+        let mut request = MallocMessageBuilder::new_default();
+        request.init_root::<message::Builder>().init_client_request().set_die("Testing.");
+        client.send(addr, &pack(&mut request));
+        let (_, response) = client.recv().unwrap();
+        let reader = serialize_packed::read_message(&mut &response[..], ReaderOptions::new()).unwrap();
+        let message = reader.get_root::<message::Reader>().unwrap();
+        match message.which() {
+            Ok(message::Which::ClientResponse(Ok(response))) => {
+                match response.which() {
+                    Ok(client_response::Which::Success(())) => (),
+                    _ => panic!("Unexpected response to a Die request."),
                 }
             },
-            Err(e) => return Err(Error::from(e)),
+            _ => panic!("Unexpected response to a Die request."),
+        }
+        handle.join().ok().expect("Server panicked.");
+    }
+
+    /// Tests that a `Server` ignores a message whose sender is not the node it was received from,
+    /// even if the claimed sender is a peer.
+    #[test]
+    fn test_spoofed_sender() {
+        let network = ChannelNetwork::new();
+        let addr = SocketAddr::from_str("127.0.0.1:0").unwrap();
+        let peer = NodeId::new();
+        let mut peers = HashMap::new();
+        peers.insert(peer, SocketAddr::from_str("127.0.0.1:1").unwrap());
+        let (state_machine, _) = ChannelStateMachine::new();
+        let id = NodeId::new();
+        let (sender, handle, _) = Server::spawn(id, addr, peers, MemStore::new(), state_machine,
+                                             network.transport(id, addr), Config::default()).unwrap();
+
+        let client = network.endpoint(SocketAddr::from_str("127.0.0.1:100").unwrap());
+        let mut request = MallocMessageBuilder::new_default();
+        {
+            let mut message = request.init_root::<message::Builder>();
+            message.set_sender(peer.as_bytes());
+            message.set_sender_address("127.0.0.1:100");
+            message.init_rpc_request().init_request_vote().set_term(1);
         }
+        client.send(addr, &pack(&mut request));
+        client.send(addr, &leader_refresh(1));
 
-        match event_loop.reregister(&self.stream, self.token, self.interest, PollOpt::edge() | PollOpt::oneshot()) {
-            Ok(()) => Ok(()),
-            Err(e) => Err(Error::from(e)),
+        // The vote request is not answered.
+        let (_, response) = client.recv().unwrap();
+        let reader = serialize_packed::read_message(&mut &response[..], ReaderOptions::new()).unwrap();
+        let message = reader.get_root::<message::Reader>().unwrap();
+        match message.which() {
+            Ok(message::Which::ClientResponse(Ok(response))) => assert_eq!(1, response.get_request_id()),
+            _ => panic!("The spoofed vote request was answered."),
         }
+
+        sender.send(Notification::Shutdown).ok().expect("Server stopped early.");
+        handle.join().ok().expect("Server panicked.");
     }
 
-    /// A registered IoHandle has available data to read.
-    /// This does not necessarily mean that there is an entire packed item on the stream. We could
-    /// get some, all of it, or none. We'll use the buffer to read in until we can find one.
-    fn readable<S, M>(&mut self, event_loop: &mut EventLoop<Server<S, M>>, replica: &mut Replica<S,M>)
-                      -> Result<()>
-    where S: Store, M: StateMachine {
-        let mut read = 0;
-        match self.stream.read(self.current_read.get_mut()) {
-            Ok(Some(r)) => {
-                // Just read `r` bytes.
-                read = r;
-            },
-            Ok(None) => panic!("We just got readable, but were unable to read from the socket?"),
-            Err(e) => return Err(Error::from(e)),
-        };
-        if read > 0 {
-            match serialize_packed::read_message(&mut self.current_read, ReaderOptions::new()) {
-                // We have something reasonably interesting in the buffer!
-                Ok(reader) => {
-                    self.handle_reader(reader, event_loop, replica);
-                },
-                // It's not read entirely yet.
-                // Should roll back, pending changes to bytes upstream.
-                // TODO: This was fixed.
-                Err(_) => unimplemented!(),
-            }
-        }
-        match event_loop.reregister(&self.stream, self.token, self.interest, PollOpt::edge()) {
-            Ok(()) => Ok(()),
-            Err(e) => Err(Error::from(e)),
+    /// Packs a `LeaderRefresh` request.
+    fn leader_refresh(request_id: u64) -> Vec<u8> {
+        let mut request = MallocMessageBuilder::new_default();
+        {
+            let mut client_req = request.init_root::<message::Builder>().init_client_request();
+            client_req.set_request_id(request_id);
+            client_req.set_leader_refresh(());
         }
+        pack(&mut request)
     }
-    
-    /// This is called when there is a full reader available in the buffer.
-    /// It handles what to do with the data.
-    fn handle_reader<S, M>(&mut self, reader: OwnedSpaceMessageReader,
-                           event_loop: &mut EventLoop<Server<S, M>>, replica: &mut Replica<S,M>)
-    where S: Store, M: StateMachine {
-        let mut builder_message = MallocMessageBuilder::new_default();
-        let from = self.stream.peer_addr().unwrap();
-        if let Ok(request) = reader.get_root::<rpc_request::Reader>() {
-            match request.which().unwrap() {
-                // TODO: Move these into replica?
-                rpc_request::Which::AppendEntries(Ok(call)) => {
-                    let builder = builder_message.init_root::<append_entries_response::Builder>();
-                    match replica.append_entries_request(from, call, builder) {
-                        Some(Emit) => {
-                            // Special Cirsumstance Detection
-                            unimplemented!();
-                        },
-                        None => (),
-                    }
-                },
-                rpc_request::Which::RequestVote(Ok(call)) => {
-                    let respond = {
-                        let builder = builder_message.init_root::<request_vote_response::Builder>();
-                        replica.request_vote_request(from, call, builder)
-                    };
-                    match respond {
-                        Some(Emit) => {
-                            // TODO
-                            self.emit(builder_message);
-                        },
-                        None            => (),
-                    }
-                },
-                _ => unimplemented!(),
-            };
-        } else if let Ok(response) = reader.get_root::<rpc_response::Reader>() {
-            // We won't be responding. This is already a response.
-            match response.which().unwrap() {
-                rpc_response::Which::AppendEntries(Ok(call)) => {
-                    let respond = {
-                        let builder = builder_message.init_root::<append_entries_request::Builder>();
-                        replica.append_entries_response(from, call, builder)
-                    };
-                    match respond {
-                        Some(Emit) => {
-                            // TODO
-                            self.emit(builder_message);
-                        },
-                        None => (),
-                    }
-                },
-                rpc_response::Which::RequestVote(Ok(call)) => {
-                    let respond = {
-                        let builder = builder_message.init_root::<append_entries_request::Builder>();
-                        replica.request_vote_response(from, call, builder)
-                    };
-                    match respond {
-                        Some(Broadcast) => {
-                            // Won an election!
-                            self.broadcast(builder_message);
-                        },
-                        None => (),
-                    }
 
-                },
-                _ => unimplemented!(),
-            }
-        } else if let Ok(client_req) = reader.get_root::<client_request::Reader>() {
-            let mut should_die = false;
-            // We will be responding.
-            match client_req.which().unwrap() {
-                client_request::Which::Append(Ok(call)) => {
-                    let respond = {
-                        let builder = builder_message.init_root::<client_response::Builder>();
-                        replica.client_append(from, call, builder)
-                    };
-                    match respond {
-                        Some(emit) => {
-                            self.emit(builder_message);
-                        },
-                        None => (),
-                    }
+    /// Tests that a `Server` with cluster keys drops unauthenticated and replayed messages, and
+    /// answers authenticated ones.
+    #[test]
+    fn test_unauthenticated() {
+        let keys = vec![b"the cluster key!".to_vec()];
+        let addr = SocketAddr::from_str("127.0.0.1:3100").unwrap();
+        let id = NodeId::new();
+        let (state_machine, _) = ChannelStateMachine::new();
+        let config = Config { cluster_keys: keys.clone(), ..Config::default() };
+        let transport = TcpTransport::new(id, addr, &config).unwrap();
+        let (sender, handle, _) = Server::spawn(id, addr, HashMap::new(), MemStore::new(),
+                                             state_machine, transport, config.clone()).unwrap();
 
-                },
-                client_request::Which::Die(Ok(call)) => {
-                    should_die = true;
-                    let mut builder = builder_message.init_root::<client_response::Builder>();
-                    builder.set_success(());
-                    self.interest.insert(Interest::writable());
-                    debug!("Got a Die request from Client({}). Reason: {}", from, call);
-                },
-                client_request::Which::LeaderRefresh(()) => {
-                    let respond = {
-                        let builder = builder_message.init_root::<client_response::Builder>();
-                        replica.client_leader_refresh(from, builder)
-                    };
-                    match respond {
-                        Some(Emit) => {
-                            self.emit(builder_message);
-                        },
-                        None => (),
+        let max = config.max_message_size;
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let nonce = auth::nonce();
+        let ours = handshake::handshake(&config.cluster_id, NodeId::new(), &nonce, &keys);
+        stream.write_all(&frame::frame(&ours, max).unwrap()).unwrap();
+        let theirs = frame::read_frame(&mut stream, max).unwrap();
+        let (peer, their_nonce) = handshake::verify(&config.cluster_id, &keys, &theirs).unwrap();
+        assert_eq!(id, peer);
+        let mut sealer = Sealer::new(their_nonce);
+        let mut opener = Opener::new(nonce);
+
+        let mut request = MallocMessageBuilder::new_default();
+        request.init_root::<message::Builder>().init_client_request().set_die("Testing.");
+        stream.write_all(&frame::frame(&pack(&mut request), max).unwrap()).unwrap();
+        let sealed = sealer.seal(&keys, leader_refresh(1));
+        stream.write_all(&frame::frame(&sealed, max).unwrap()).unwrap();
+        stream.write_all(&frame::frame(&sealed, max).unwrap()).unwrap();
+        let sealed = sealer.seal(&keys, leader_refresh(2));
+        stream.write_all(&frame::frame(&sealed, max).unwrap()).unwrap();
+
+        // Only the authenticated requests are answered, once each.
+        for request_id in 1..3 {
+            let reader = frame::read_frame(&mut stream, max).unwrap();
+            let reader = opener.unseal(&keys, reader).unwrap();
+            let message = reader.get_root::<message::Reader>().unwrap();
+            match message.which() {
+                Ok(message::Which::ClientResponse(Ok(response))) => {
+                    assert_eq!(request_id, response.get_request_id());
+                    match response.which() {
+                        Ok(client_response::Which::NotLeader(_)) => (),
+                        Ok(client_response::Which::UnknownLeader(())) => (),
+                        _ => panic!("Unexpected response to a LeaderRefresh request."),
                     }
                 },
-                _ => unimplemented!(),
-            };
-
-            // Do this here so that we can send the response.
-            if should_die {
-                panic!("Got a Die request.");
+                _ => panic!("Unexpected response to a LeaderRefresh request."),
             }
-
-        } else {
-            // It's something we don't understand.
-            unimplemented!();
         }
-    }
 
-    fn broadcast(&mut self, builder: MallocMessageBuilder) {
-        unimplemented!();
+        sender.send(Notification::Shutdown).ok().expect("Server stopped early.");
+        handle.join().ok().expect("Server panicked.");
     }
 
-    /// Push the new message into `self.next_write`. This does not actually send the message, it
-    /// just queues it up.
-    pub fn emit(&mut self, mut builder: MallocMessageBuilder) {
-        let mut buf = RingBuf::new(RINGBUF_SIZE);
-        serialize_packed::write_message(
-            &mut buf,
-            &mut builder
-        ).unwrap();
-        self.add_write(buf);
-    }
+    /// Tests that a request of the related `Raft` is answered on its own channel, without being
+    /// sealed, even though the `Server` has cluster keys.
+    #[test]
+    fn test_local_request() {
+        let network = ChannelNetwork::new();
+        let addr = SocketAddr::from_str("127.0.0.1:0").unwrap();
+        let (state_machine, _) = ChannelStateMachine::new();
+        let config = Config { cluster_keys: vec![b"the cluster key!".to_vec()], ..Config::default() };
+        let id = NodeId::new();
+        let (sender, handle, _) = Server::spawn(id, addr, HashMap::new(), MemStore::new(),
+                                             state_machine, network.transport(id, addr), config).unwrap();
+
+        let mut request = MallocMessageBuilder::new_default();
+        {
+            let mut client_req = request.init_root::<message::Builder>().init_client_request();
+            client_req.set_request_id(7);
+            client_req.set_leader_refresh(());
+        }
+        let (reply, proposal) = client::reply();
+        sender.send(Notification::Request(pack(&mut request), reply)).ok().expect("Server stopped early.");
+        match proposal.wait_timeout(5000) {
+            Some(Err(Error::Raft(ErrorKind::NotLeader(_)))) => (),
+            Some(Err(Error::Raft(ErrorKind::CannotProceed))) => (),
+            Some(_) => panic!("Unexpected response to a LeaderRefresh request."),
+            None => panic!("The local request was not answered."),
+        }
+
+        // A request left unanswered when the `Server` stops fails rather than waiting forever.
+        let (reply, proposal) = client::reply();
+        drop(reply);
+        match proposal.wait_timeout(5000) {
+            Some(Err(Error::Raft(ErrorKind::OutcomeUnknown))) => (),
+            _ => panic!("An unanswered local request did not fail."),
+        }
 
-    /// This queues a byte buffer into the write queue. This is used primarily when message has
-    /// already been packed.
-    pub fn add_write(&mut self, buf: RingBuf) {
-        self.next_write.push_back(buf);
+        sender.send(Notification::Shutdown).ok().expect("Server stopped early.");
+        handle.join().ok().expect("Server panicked.");
     }
 }
@@ -0,0 +1,897 @@
+//! A deterministic simulation of whole Raft clusters.
+//!
+//! A `Simulation` runs a cluster of real `Server`s in a single thread against a virtual clock.
+//! Their event loops are never run: the simulation calls their handlers directly, delivers the
+//! messages they send through a simulated network, and fires the timers they arm through a
+//! simulated `Scheduler`. Every source of nondeterminism a `Server` would see (election timeouts,
+//! and the loss, delay, duplication and reordering of messages) is drawn from a single seeded
+//! random number generator, so the same seed and the same sequence of calls always lead to the
+//! same run. A failing seed can be replayed exactly by passing it to `Simulation::new` again.
+//!
+//! After every step the simulation checks that no two replicas are leader in the same term, that
+//! the logs of the replicas match wherever they share an entry, that every leader holds the
+//! entries committed in earlier terms, and that the state machines agree on the commands they
+//! applied. It panics with the seed if they do not.
+
+use std::cmp::{self, Ordering};
+use std::collections::{BinaryHeap, HashMap};
+use std::mem;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::u64;
+
+use mio::{EventLoop, Handler, ReadHint, Token};
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+// Cap'n Proto
+use capnp::serialize_packed;
+use capnp::{MessageBuilder, MessageReader, ReaderOptions, MallocMessageBuilder,
+            OwnedSpaceMessageReader};
+use messages_capnp::{client_response, message};
+
+use frame::pack;
+use scheduler::Scheduler;
+use server::Server;
+use state_machine::ChannelStateMachine;
+use store::{MemStore, Store};
+use transport::{Notification, Transport};
+use {Config, Error, LogIndex, NodeId, Result, Term};
+
+/// The behaviour of the simulated network.
+#[derive(Clone, Copy, Debug)]
+pub struct NetworkConfig {
+    /// The probability that a message is dropped.
+    pub drop: f64,
+    /// The probability that a message is delivered twice.
+    pub duplicate: f64,
+    /// The minimum delay before a message is delivered, in milliseconds.
+    pub min_delay: u64,
+    /// The maximum delay before a message is delivered, in milliseconds. Each message is delayed
+    /// independently, so messages may be reordered when this is larger than `min_delay`.
+    pub max_delay: u64,
+}
+
+impl NetworkConfig {
+
+    /// A network which delivers every message exactly once, in order.
+    pub fn reliable() -> NetworkConfig {
+        NetworkConfig { drop: 0.0, duplicate: 0.0, min_delay: 1, max_delay: 1 }
+    }
+
+    /// A network which drops, duplicates and reorders messages.
+    pub fn lossy() -> NetworkConfig {
+        NetworkConfig { drop: 0.1, duplicate: 0.05, min_delay: 1, max_delay: 40 }
+    }
+}
+
+/// The messages sent by a simulated `Server`, with their destinations, in the order they were
+/// sent.
+type Outbox = Arc<Mutex<Vec<(SocketAddr, Vec<u8>)>>>;
+
+/// The timers armed by a simulated `Server`, with their delay, in the order they were armed.
+type Armed = Arc<Mutex<Vec<(Token, u64)>>>;
+
+/// The `Transport` of a simulated `Server`, which leaves the messages it sends in an outbox for
+/// the simulation to deliver.
+struct SimulatedTransport {
+    outbox: Outbox,
+}
+
+impl Transport for SimulatedTransport {
+
+    fn register<H>(&mut self, _event_loop: &mut EventLoop<H>) -> Result<()>
+    where H: Handler<Message=Notification> {
+        Ok(())
+    }
+
+    fn readable<H>(&mut self, _event_loop: &mut EventLoop<H>, _token: Token, _hint: ReadHint)
+                   -> Result<Vec<(SocketAddr, NodeId, OwnedSpaceMessageReader)>>
+    where H: Handler {
+        Ok(Vec::new())
+    }
+
+    fn writable<H>(&mut self, _event_loop: &mut EventLoop<H>, _token: Token) -> Result<()>
+    where H: Handler {
+        Ok(())
+    }
+
+    fn send<H>(&mut self, _event_loop: &mut EventLoop<H>, to: SocketAddr, message: &[u8])
+               -> Result<()>
+    where H: Handler {
+        self.outbox.lock().unwrap().push((to, message.to_vec()));
+        Ok(())
+    }
+
+    fn set_cluster_keys(&mut self, _keys: Vec<Vec<u8>>) {
+        // Messages never leave the simulation, so they are not sealed.
+    }
+
+    fn rejected_messages(&self) -> u64 {
+        0
+    }
+
+    fn close_idle<H>(&mut self, _event_loop: &mut EventLoop<H>) -> Result<()>
+    where H: Handler {
+        Ok(())
+    }
+
+    fn shutdown<H>(&mut self, _event_loop: &mut EventLoop<H>) -> Result<()>
+    where H: Handler {
+        Ok(())
+    }
+}
+
+/// The `Scheduler` of a simulated `Server`, which leaves the timers it arms for the simulation to
+/// fire, and draws its election timeouts from a generator seeded by the simulation.
+struct SimulatedScheduler {
+    rng: XorShiftRng,
+    armed: Armed,
+}
+
+impl Scheduler for SimulatedScheduler {
+
+    fn election_timeout(&mut self, config: &Config) -> u64 {
+        config.election_timeout(&mut self.rng)
+    }
+
+    fn schedule<H>(&mut self, _event_loop: &mut EventLoop<H>, token: Token, delay: u64)
+                   -> Result<()>
+    where H: Handler<Timeout=Token> {
+        self.armed.lock().unwrap().push((token, delay));
+        Ok(())
+    }
+}
+
+type SimulatedServer =
+    Server<MemStore, ChannelStateMachine, SimulatedTransport, SimulatedScheduler>;
+
+/// A message in flight on the simulated network.
+struct Delivery {
+    /// The virtual time at which the message arrives.
+    at: u64,
+    /// Orders messages arriving at the same time by when they were sent.
+    sequence: u64,
+    from: usize,
+    to: usize,
+    message: Vec<u8>,
+}
+
+impl PartialEq for Delivery {
+    fn eq(&self, other: &Delivery) -> bool {
+        (self.at, self.sequence) == (other.at, other.sequence)
+    }
+}
+
+impl Eq for Delivery {}
+
+impl PartialOrd for Delivery {
+    fn partial_cmp(&self, other: &Delivery) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Delivery {
+    /// Reversed, so that the earliest delivery is at the top of a `BinaryHeap`.
+    fn cmp(&self, other: &Delivery) -> Ordering {
+        (other.at, other.sequence).cmp(&(self.at, self.sequence))
+    }
+}
+
+/// A simulated node, running a real `Server`.
+struct Node {
+    id: NodeId,
+    addr: SocketAddr,
+    server: SimulatedServer,
+    /// The event loop the handlers of the `Server` are called with. It is never run.
+    event_loop: EventLoop<SimulatedServer>,
+    outbox: Outbox,
+    armed: Armed,
+    /// The armed timers of the `Server`, with the virtual time at which they fire.
+    timers: Vec<(Token, u64)>,
+    applied_commands: mpsc::Receiver<Vec<u8>>,
+    /// The commands applied by the state machine so far, in order.
+    applied: Vec<Vec<u8>>,
+    /// The side of the network partition the node is on.
+    side: usize,
+}
+
+impl Node {
+
+    /// Returns the time at which the next timer of the node fires.
+    fn next_timer(&self) -> u64 {
+        self.timers.iter().map(|&(_, at)| at).min().unwrap_or(u64::MAX)
+    }
+}
+
+/// An entry whose append was acknowledged to the client, and which must therefore never be lost.
+struct Acknowledged {
+    index: LogIndex,
+    /// The term of the entry in the log of the leader which acknowledged it.
+    term: Term,
+    data: Vec<u8>,
+}
+
+/// A deterministic simulation of a Raft cluster. See the module documentation.
+pub struct Simulation {
+    seed: u64,
+    /// The virtual time, in milliseconds.
+    now: u64,
+    rng: XorShiftRng,
+    network: NetworkConfig,
+    nodes: Vec<Node>,
+    indices: HashMap<SocketAddr, usize>,
+    in_flight: BinaryHeap<Delivery>,
+    sequence: u64,
+    /// The leader seen in each term, for checking election safety.
+    leaders: HashMap<Term, NodeId>,
+    /// The address of the simulated client, which sends its requests to the leader directly.
+    client: SocketAddr,
+    client_id: NodeId,
+    next_request_id: u64,
+    /// The data of each append awaiting a response, by request ID.
+    pending: HashMap<u64, Vec<u8>>,
+    acknowledged: Vec<Acknowledged>,
+}
+
+impl Simulation {
+
+    /// Creates a simulation of a new cluster of `size` replicas with the default `Config`.
+    pub fn new(seed: u64, size: u16, network: NetworkConfig) -> Simulation {
+        Simulation::with_config(seed, size, network, Config::default())
+    }
+
+    /// Creates a simulation of a new cluster of `size` replicas with the provided `Config`.
+    pub fn with_config(seed: u64, size: u16, network: NetworkConfig, config: Config) -> Simulation {
+        // The generator must not be seeded with all zeros.
+        let mut rng = XorShiftRng::from_seed([0x193a6754, seed as u32, (seed >> 32) as u32, 0xdeadbeef]);
+        // The IDs are drawn from the generator too, so that they are the same in every run.
+        let members: Vec<(NodeId, SocketAddr)> = (0..size).map(|n| {
+            let mut bytes = [0u8; 16];
+            rng.fill_bytes(&mut bytes);
+            let addr = SocketAddr::from_str(&format!("127.0.0.1:{}", 1000 + n)).unwrap();
+            (NodeId::from_bytes(&bytes).unwrap(), addr)
+        }).collect();
+        let mut bytes = [0u8; 16];
+        rng.fill_bytes(&mut bytes);
+        let client_id = NodeId::from_bytes(&bytes).unwrap();
+        let mut simulation = Simulation {
+            seed: seed,
+            now: 0,
+            rng: rng,
+            network: network,
+            nodes: Vec::with_capacity(members.len()),
+            indices: HashMap::new(),
+            in_flight: BinaryHeap::new(),
+            sequence: 0,
+            leaders: HashMap::new(),
+            client: SocketAddr::from_str("127.0.0.1:100").unwrap(),
+            client_id: client_id,
+            next_request_id: 0,
+            pending: HashMap::new(),
+            acknowledged: Vec::new(),
+        };
+        for (index, &(id, addr)) in members.iter().enumerate() {
+            let peers = members.iter().cloned().filter(|&(peer, _)| peer != id).collect();
+            let (state_machine, applied_commands) = ChannelStateMachine::new();
+            let outbox = Arc::new(Mutex::new(Vec::new()));
+            let armed = Arc::new(Mutex::new(Vec::new()));
+            let scheduler = SimulatedScheduler {
+                // Each node draws from a generator of its own, seeded from the simulation's.
+                rng: XorShiftRng::from_seed([simulation.rng.gen::<u32>() | 1, simulation.rng.gen(),
+                                             simulation.rng.gen(), simulation.rng.gen()]),
+                armed: armed.clone(),
+            };
+            let transport = SimulatedTransport { outbox: outbox.clone() };
+            let mut server = check(seed, Server::new(id, addr, peers, MemStore::new(),
+                                                     state_machine, transport, scheduler,
+                                                     config.clone()));
+            let mut event_loop = EventLoop::new().unwrap();
+            check(seed, server.start(&mut event_loop));
+            simulation.nodes.push(Node {
+                id: id,
+                addr: addr,
+                server: server,
+                event_loop: event_loop,
+                outbox: outbox,
+                armed: armed,
+                timers: Vec::new(),
+                applied_commands: applied_commands,
+                applied: Vec::new(),
+                side: 0,
+            });
+            simulation.indices.insert(addr, index);
+            simulation.collect(index);
+        }
+        simulation
+    }
+
+    /// Returns the seed of the simulation.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Returns the current virtual time, in milliseconds.
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Returns the IDs of the replicas.
+    pub fn ids(&self) -> Vec<NodeId> {
+        self.nodes.iter().map(|node| node.id).collect()
+    }
+
+    /// Partitions the network in two: the provided replicas on one side, and the rest on the
+    /// other. Messages crossing the partition are dropped, including those already in flight.
+    pub fn partition(&mut self, ids: &[NodeId]) {
+        for node in self.nodes.iter_mut() {
+            node.side = if ids.contains(&node.id) { 1 } else { 0 };
+        }
+    }
+
+    /// Removes any network partition.
+    pub fn heal(&mut self) {
+        for node in self.nodes.iter_mut() {
+            node.side = 0;
+        }
+    }
+
+    /// Returns `true` if the replica is a leader.
+    pub fn is_leader(&self, id: NodeId) -> bool {
+        self.nodes.iter().any(|node| node.id == id && node.server.replica().is_leader())
+    }
+
+    /// Returns the leader with the highest term, if there is one. Leaders in lower terms may
+    /// remain on the other side of a partition.
+    pub fn leader(&self) -> Option<NodeId> {
+        self.leader_index().map(|index| self.nodes[index].id)
+    }
+
+    fn leader_index(&self) -> Option<usize> {
+        let seed = self.seed;
+        (0..self.nodes.len())
+            .filter(|&index| self.nodes[index].server.replica().is_leader())
+            .max_by(|&index| check(seed, self.nodes[index].server.replica().current_term()))
+    }
+
+    /// Returns the leader seen in each term so far, ordered by term.
+    pub fn leader_history(&self) -> Vec<(Term, NodeId)> {
+        let mut history: Vec<(Term, NodeId)> =
+            self.leaders.iter().map(|(&term, &id)| (term, id)).collect();
+        history.sort_by(|a, b| a.0.cmp(&b.0));
+        history
+    }
+
+    /// Sends a client append of the data to the leader with the highest term. Returns `false`,
+    /// without sending anything, if there is no leader. The append is not guaranteed to succeed.
+    pub fn append(&mut self, data: &[u8]) -> bool {
+        let index = match self.leader_index() {
+            Some(index) => index,
+            None => return false,
+        };
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+        let mut builder = MallocMessageBuilder::new_default();
+        {
+            let mut request = builder.init_root::<message::Builder>().init_client_request();
+            request.set_request_id(request_id);
+            request.init_append_batch(1).set(0, data);
+        }
+        let message = pack(&mut builder);
+        self.pending.insert(request_id, data.to_vec());
+        let (client, client_id) = (self.client, self.client_id);
+        {
+            let node = &mut self.nodes[index];
+            node.server.notify(&mut node.event_loop,
+                               Notification::Received(client, client_id, message));
+        }
+        self.collect(index);
+        self.check_invariants();
+        true
+    }
+
+    /// Returns the data of the appends acknowledged to the client so far, in log order.
+    pub fn acknowledged(&self) -> Vec<Vec<u8>> {
+        let mut acknowledged: Vec<&Acknowledged> = self.acknowledged.iter().collect();
+        acknowledged.sort_by(|a, b| a.index.cmp(&b.index));
+        acknowledged.into_iter().map(|entry| entry.data.clone()).collect()
+    }
+
+    /// Returns the number of appends the client has not had a response to.
+    pub fn unanswered(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns the commands applied by the state machine of the replica so far, in order.
+    pub fn applied(&self, id: NodeId) -> Vec<Vec<u8>> {
+        self.nodes.iter().find(|node| node.id == id).unwrap().applied.clone()
+    }
+
+    /// Runs the simulation until `duration` milliseconds of virtual time have passed.
+    pub fn run_for(&mut self, duration: u64) {
+        let until = self.now + duration;
+        while self.next_event() <= until {
+            self.step();
+        }
+        self.now = until;
+    }
+
+    /// Runs the simulation until the condition holds, for at most `timeout` milliseconds of
+    /// virtual time. Returns whether the condition holds.
+    pub fn run_until<F>(&mut self, timeout: u64, condition: F) -> bool
+    where F: Fn(&Simulation) -> bool {
+        let until = self.now + timeout;
+        while !condition(self) {
+            if self.next_event() > until {
+                self.now = until;
+                return false;
+            }
+            self.step();
+        }
+        true
+    }
+
+    /// Returns the virtual time of the next message delivery or timeout.
+    fn next_event(&self) -> u64 {
+        let timers = self.nodes.iter().map(|node| node.next_timer()).min().unwrap();
+        match self.in_flight.peek() {
+            Some(delivery) => cmp_min(delivery.at, timers),
+            None => timers,
+        }
+    }
+
+    /// Advances the clock to the next event and handles it. Messages due at the same time as a
+    /// timeout are delivered first, and simultaneous timeouts fire in replica order, then in
+    /// token order.
+    fn step(&mut self) {
+        let at = self.next_event();
+        self.now = at;
+        let due = self.in_flight.peek().map(|delivery| delivery.at == at).unwrap_or(false);
+        if due {
+            let delivery = self.in_flight.pop().unwrap();
+            self.deliver(delivery);
+        } else {
+            let index = self.nodes.iter().position(|node| node.next_timer() == at).unwrap();
+            let node = &mut self.nodes[index];
+            let position = (0..node.timers.len())
+                .filter(|&position| node.timers[position].1 == at)
+                .min_by(|&position| (node.timers[position].0).0)
+                .unwrap();
+            let (token, _) = node.timers.remove(position);
+            node.server.timeout(&mut node.event_loop, token);
+        }
+        // Only one node handled an event, but collecting from all of them is just as cheap.
+        for index in 0..self.nodes.len() {
+            self.collect(index);
+        }
+        self.check_invariants();
+    }
+
+    /// Delivers a message to its `Server`, unless a partition came up while it was in flight.
+    fn deliver(&mut self, delivery: Delivery) {
+        if self.nodes[delivery.from].side != self.nodes[delivery.to].side {
+            return;
+        }
+        let (from, id) = (self.nodes[delivery.from].addr, self.nodes[delivery.from].id);
+        let node = &mut self.nodes[delivery.to];
+        node.server.notify(&mut node.event_loop, Notification::Received(from, id, delivery.message));
+    }
+
+    /// Takes the timers armed, the messages sent and the commands applied by the `Server` of the
+    /// node since the previous call. The messages are put on the network, or handed to the client.
+    fn collect(&mut self, index: usize) {
+        let now = self.now;
+        let (armed, mut sent) = {
+            let node = &mut self.nodes[index];
+            while let Ok(command) = node.applied_commands.try_recv() {
+                node.applied.push(command);
+            }
+            let armed = mem::replace(&mut *node.armed.lock().unwrap(), Vec::new());
+            let sent = mem::replace(&mut *node.outbox.lock().unwrap(), Vec::new());
+            (armed, sent)
+        };
+        for (token, delay) in armed {
+            let timers = &mut self.nodes[index].timers;
+            timers.retain(|&(armed, _)| armed != token);
+            timers.push((token, now + delay));
+        }
+        // The `Server` sends to its peers in the order of a `HashSet`, which must not leak into
+        // the run. The sort is stable, so the messages to each destination keep their order.
+        let indices = self.indices.clone();
+        sent.sort_by(|a, b| indices.get(&a.0).cmp(&indices.get(&b.0)));
+        for (to, message) in sent {
+            match indices.get(&to) {
+                Some(&to) => self.send(index, to, message),
+                None if to == self.client => self.respond(index, message),
+                None => panic!("seed {}: message sent to unknown address {}", self.seed, to),
+            }
+        }
+    }
+
+    /// Puts the message on the network.
+    fn send(&mut self, from: usize, to: usize, message: Vec<u8>) {
+        if self.nodes[from].side != self.nodes[to].side
+                || self.rng.gen::<f64>() < self.network.drop {
+            return;
+        }
+        let copies = if self.rng.gen::<f64>() < self.network.duplicate { 2 } else { 1 };
+        for _ in 0..copies {
+            let delay = self.rng.gen_range(self.network.min_delay, self.network.max_delay + 1);
+            self.sequence += 1;
+            self.in_flight.push(Delivery {
+                at: self.now + delay,
+                sequence: self.sequence,
+                from: from,
+                to: to,
+                message: message.clone(),
+            });
+        }
+    }
+
+    /// Handles a response of the replica to the client. Acknowledged appends are recorded, along
+    /// with the term of their entry in the log of the replica.
+    fn respond(&mut self, from: usize, message: Vec<u8>) {
+        let seed = self.seed;
+        let reader = serialize_packed::read_message(&mut &message[..], ReaderOptions::new()).unwrap();
+        let message = reader.get_root::<message::Reader>().unwrap();
+        let response = match message.which() {
+            Ok(message::Which::ClientResponse(Ok(response))) => response,
+            _ => panic!("seed {}: unexpected message to the client", seed),
+        };
+        let data = match self.pending.remove(&response.get_request_id()) {
+            Some(data) => data,
+            None => return,
+        };
+        if let Ok(client_response::Which::Appended(Ok(indexes))) = response.which() {
+            let index = LogIndex::from(indexes.get(0));
+            let store = self.nodes[from].server.replica().store();
+            let (term, _, stored) = check(seed, store.entry(index).map_err(Error::store));
+            assert_eq!(data, stored.to_vec());
+            self.acknowledged.push(Acknowledged { index: index, term: term, data: data });
+        }
+    }
+
+    /// Panics if any of the safety properties of Raft does not hold.
+    fn check_invariants(&mut self) {
+        self.check_election_safety();
+        self.check_log_matching();
+        self.check_leader_completeness();
+        self.check_state_machine_safety();
+    }
+
+    /// Panics if two replicas have been leader in the same term.
+    fn check_election_safety(&mut self) {
+        for node in self.nodes.iter().filter(|node| node.server.replica().is_leader()) {
+            let term = check(self.seed, node.server.replica().current_term());
+            let leader = *self.leaders.entry(term).or_insert(node.id);
+            if leader != node.id {
+                panic!("seed {}: both {} and {} were leader in term {:?}.",
+                       self.seed, leader, node.id, term);
+            }
+        }
+    }
+
+    /// Panics if two logs hold an entry with the same index and term, but differ before it.
+    fn check_log_matching(&self) {
+        let seed = self.seed;
+        for (n, first) in self.nodes.iter().enumerate() {
+            for second in self.nodes[n + 1..].iter() {
+                let first_log = first.server.replica().store();
+                let second_log = second.server.replica().store();
+                let mut index = cmp::min(latest_index(seed, first_log),
+                                         latest_index(seed, second_log));
+                while index > LogIndex::from(0)
+                        && term_at(seed, first_log, index) != term_at(seed, second_log, index) {
+                    index = index - 1;
+                }
+                // The logs share the entry at `index`, so they must be identical up to it.
+                while index > LogIndex::from(0) {
+                    let first_entry = check(seed, first_log.entry(index).map_err(Error::store));
+                    let second_entry = check(seed, second_log.entry(index).map_err(Error::store));
+                    if first_entry != second_entry {
+                        panic!("seed {}: the logs of {} and {} differ at index {:?}.",
+                               seed, first.id, second.id, index);
+                    }
+                    index = index - 1;
+                }
+            }
+        }
+    }
+
+    /// Panics if a leader lacks an entry acknowledged to the client in an earlier term or its own.
+    fn check_leader_completeness(&self) {
+        let seed = self.seed;
+        for node in self.nodes.iter().filter(|node| node.server.replica().is_leader()) {
+            let term = check(seed, node.server.replica().current_term());
+            let store = node.server.replica().store();
+            let latest = latest_index(seed, store);
+            for entry in self.acknowledged.iter().filter(|entry| entry.term <= term) {
+                let holds = entry.index <= latest && {
+                    let (stored_term, _, data) = check(seed, store.entry(entry.index)
+                                                                  .map_err(Error::store));
+                    stored_term == entry.term && data == &entry.data[..]
+                };
+                if !holds {
+                    panic!("seed {}: leader {} of term {:?} lost the entry acknowledged at {:?}.",
+                           seed, node.id, term, entry.index);
+                }
+            }
+        }
+    }
+
+    /// Panics if two state machines applied different commands at the same position.
+    fn check_state_machine_safety(&self) {
+        let longest = self.nodes.iter().max_by(|node| node.applied.len()).unwrap();
+        for node in self.nodes.iter() {
+            if node.applied[..] != longest.applied[..node.applied.len()] {
+                panic!("seed {}: the state machines of {} and {} applied different commands.",
+                       self.seed, node.id, longest.id);
+            }
+        }
+    }
+}
+
+/// Returns the index of the latest entry of the log.
+fn latest_index(seed: u64, store: &MemStore) -> LogIndex {
+    check(seed, store.latest_log_index().map_err(Error::store))
+}
+
+/// Returns the term of the entry at the index of the log.
+fn term_at(seed: u64, store: &MemStore, index: LogIndex) -> Term {
+    check(seed, store.entry(index).map_err(Error::store)).0
+}
+
+/// Unwraps the result of a replica operation, reporting the seed on failure.
+fn check<T>(seed: u64, result: Result<T>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(error) => panic!("seed {}: replica failed: {:?}", seed, error),
+    }
+}
+
+fn cmp_min(a: u64, b: u64) -> u64 {
+    if a < b { a } else { b }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{NetworkConfig, Simulation};
+    use Config;
+
+    /// Tests that every seed elects a leader on a reliable network.
+    #[test]
+    fn test_reliable_election() {
+        for seed in 0..20 {
+            let mut simulation = Simulation::new(seed, 3, NetworkConfig::reliable());
+            assert!(simulation.run_until(5000, |s| s.leader().is_some()),
+                    "seed {}: no leader was elected.", seed);
+        }
+    }
+
+    /// Tests that an elected leader keeps its leadership on a reliable network.
+    #[test]
+    fn test_stable_leader() {
+        for seed in 0..20 {
+            let mut simulation = Simulation::new(seed, 5, NetworkConfig::reliable());
+            assert!(simulation.run_until(5000, |s| s.leader().is_some()),
+                    "seed {}: no leader was elected.", seed);
+            let leader = simulation.leader();
+            simulation.run_for(5000);
+            assert_eq!(leader, simulation.leader());
+        }
+    }
+
+    /// Tests that every seed elects a leader on a lossy network.
+    #[test]
+    fn test_lossy_election() {
+        for seed in 0..20 {
+            let mut simulation = Simulation::new(seed, 5, NetworkConfig::lossy());
+            assert!(simulation.run_until(20000, |s| s.leader().is_some()),
+                    "seed {}: no leader was elected.", seed);
+            // Keep going, checking election safety along the way.
+            simulation.run_for(10000);
+        }
+    }
+
+    /// Tests that the majority elects a new leader when the leader is partitioned away, and that
+    /// the old leader steps down once the partition heals.
+    #[test]
+    fn test_partitioned_leader() {
+        for seed in 0..20 {
+            let mut simulation = Simulation::new(seed, 5, NetworkConfig::reliable());
+            assert!(simulation.run_until(5000, |s| s.leader().is_some()),
+                    "seed {}: no leader was elected.", seed);
+            let old_leader = simulation.leader().unwrap();
+            simulation.partition(&[old_leader]);
+            assert!(simulation.run_until(5000, |s| s.leader().map(|l| l != old_leader).unwrap_or(false)),
+                    "seed {}: the majority did not elect a new leader.", seed);
+            simulation.heal();
+            assert!(simulation.run_until(5000, |s| !s.is_leader(old_leader)),
+                    "seed {}: the old leader did not step down.", seed);
+        }
+    }
+
+    /// Tests that a cluster with wide area network timeouts elects a leader on a slow network.
+    #[test]
+    fn test_wan_election() {
+        let config = Config {
+            election_timeout_min: 1500,
+            election_timeout_max: 3000,
+            heartbeat_interval: 500,
+            ..Config::default()
+        };
+        let network = NetworkConfig { drop: 0.05, duplicate: 0.0, min_delay: 50, max_delay: 250 };
+        for seed in 0..10 {
+            let mut simulation = Simulation::with_config(seed, 5, network, config.clone());
+            assert!(simulation.run_until(60000, |s| s.leader().is_some()),
+                    "seed {}: no leader was elected.", seed);
+        }
+    }
+
+    /// Tests that every append to a stable leader is acknowledged, and applied by every replica in
+    /// the order it was acknowledged.
+    #[test]
+    fn test_reliable_appends() {
+        for seed in 0..10 {
+            let mut simulation = Simulation::new(seed, 3, NetworkConfig::reliable());
+            assert!(simulation.run_until(5000, |s| s.leader().is_some()),
+                    "seed {}: no leader was elected.", seed);
+            for n in 0..20u8 {
+                assert!(simulation.append(&[n]), "seed {}: the leader was lost.", seed);
+                simulation.run_for(10);
+            }
+            assert!(simulation.run_until(5000, |s| s.acknowledged().len() == 20),
+                    "seed {}: not every append was acknowledged.", seed);
+            let acknowledged = simulation.acknowledged();
+            for id in simulation.ids() {
+                assert!(simulation.run_until(5000, |s| s.applied(id).len() == 20),
+                        "seed {}: {} did not apply every entry.", seed, id);
+                assert_eq!(acknowledged, simulation.applied(id));
+            }
+        }
+    }
+
+    /// Tests that no acknowledged append is lost while the network drops, duplicates and reorders
+    /// messages, and the leader is repeatedly partitioned away. The safety properties are checked
+    /// after every step.
+    #[test]
+    fn test_lossy_appends() {
+        for seed in 0..10 {
+            let mut simulation = Simulation::new(seed, 5, NetworkConfig::lossy());
+            for round in 0..6u8 {
+                if !simulation.run_until(20000, |s| s.leader().is_some()) {
+                    continue;
+                }
+                for n in 0..10u8 {
+                    simulation.append(&[round, n]);
+                    simulation.run_for(15);
+                }
+                if round % 2 == 0 {
+                    if let Some(leader) = simulation.leader() {
+                        simulation.partition(&[leader]);
+                    }
+                } else {
+                    simulation.heal();
+                }
+                simulation.run_for(1000);
+            }
+            simulation.heal();
+            simulation.run_for(20000);
+            assert!(!simulation.acknowledged().is_empty(),
+                    "seed {}: no append was acknowledged.", seed);
+        }
+    }
+
+    /// Tests elections under partitions on a lossy network. The leader and another replica are cut
+    /// off while appends continue on both sides, so that the minority holds uncommitted entries
+    /// and stale logs when it rejoins and campaigns. The safety properties are checked after every
+    /// step, and each seed must see a leader in several terms.
+    #[test]
+    fn test_partitioned_elections() {
+        for seed in 0..20 {
+            let mut simulation = Simulation::new(seed, 5, NetworkConfig::lossy());
+            for round in 0..4u8 {
+                assert!(simulation.run_until(20000, |s| s.leader().is_some()),
+                        "seed {}: no leader was elected.", seed);
+                let leader = simulation.leader().unwrap();
+                let other = simulation.ids().into_iter().find(|&id| id != leader).unwrap();
+                simulation.partition(&[leader, other]);
+                for n in 0..5u8 {
+                    simulation.append(&[round, n]);
+                    simulation.run_for(15);
+                }
+                assert!(simulation.run_until(20000, |s| s.leader().map(|l| l != leader).unwrap_or(false)),
+                        "seed {}: the majority did not elect a new leader.", seed);
+                for n in 5..10u8 {
+                    simulation.append(&[round, n]);
+                    simulation.run_for(15);
+                }
+                simulation.heal();
+                assert!(simulation.run_until(20000, |s| !s.is_leader(leader)),
+                        "seed {}: the old leader did not step down.", seed);
+            }
+            assert!(simulation.leader_history().len() >= 5,
+                    "seed {}: too few elections took place.", seed);
+        }
+    }
+
+    /// Tests that the appends pending at a leader which is partitioned away before it can commit
+    /// them are answered once it learns of the new leader, without being acknowledged, since the
+    /// new leader replaces them in its log.
+    #[test]
+    fn test_deposed_leader_proposals() {
+        for seed in 0..10 {
+            let mut simulation = Simulation::new(seed, 5, NetworkConfig::reliable());
+            assert!(simulation.run_until(5000, |s| s.leader().is_some()),
+                    "seed {}: no leader was elected.", seed);
+            let old_leader = simulation.leader().unwrap();
+            simulation.partition(&[old_leader]);
+            for n in 0..5u8 {
+                assert!(simulation.append(&[0, n]), "seed {}: the leader was lost.", seed);
+                simulation.run_for(5);
+            }
+            assert!(simulation.run_until(5000, |s| s.leader().map(|l| l != old_leader).unwrap_or(false)),
+                    "seed {}: the majority did not elect a new leader.", seed);
+            for n in 0..5u8 {
+                assert!(simulation.append(&[1, n]), "seed {}: the leader was lost.", seed);
+                simulation.run_for(5);
+            }
+            simulation.heal();
+            assert!(simulation.run_until(5000, |s| s.unanswered() == 0),
+                    "seed {}: not every append was answered.", seed);
+            let acknowledged = simulation.acknowledged();
+            assert_eq!(5, acknowledged.len());
+            assert!(acknowledged.iter().all(|data| data[0] == 1),
+                    "seed {}: an entry of the deposed leader was acknowledged.", seed);
+            for id in simulation.ids() {
+                assert!(simulation.run_until(5000, |s| s.applied(id).ends_with(&acknowledged)),
+                        "seed {}: {} did not apply every acknowledged entry.", seed, id);
+            }
+        }
+    }
+
+    /// Tests that appends survive a network which delivers every message twice, each copy after
+    /// its own delay, so that AppendEntries requests and their responses arrive duplicated and out
+    /// of order. The seed is pinned so that a failure replays exactly.
+    #[test]
+    fn test_duplicated_and_reordered_appends() {
+        let seed = 1337;
+        let network = NetworkConfig { drop: 0.0, duplicate: 1.0, min_delay: 1, max_delay: 60 };
+        let mut simulation = Simulation::new(seed, 3, network);
+        assert!(simulation.run_until(20000, |s| s.leader().is_some()),
+                "seed {}: no leader was elected.", seed);
+        for n in 0..20u8 {
+            simulation.append(&[n]);
+            simulation.run_for(5);
+        }
+        // The safety properties are checked after every step; the run must also make progress.
+        simulation.run_for(5000);
+        let acknowledged = simulation.acknowledged();
+        assert!(!acknowledged.is_empty(), "seed {}: no append was acknowledged.", seed);
+        for id in simulation.ids() {
+            let applied = simulation.applied(id);
+            assert!(acknowledged.iter().all(|data| applied.contains(data)),
+                    "seed {}: {} did not apply every acknowledged entry.", seed, id);
+        }
+    }
+
+    /// Tests that a seed always produces the same run.
+    #[test]
+    fn test_reproducible() {
+        let mut first = Simulation::new(42, 5, NetworkConfig::lossy());
+        let mut second = Simulation::new(42, 5, NetworkConfig::lossy());
+        first.run_for(20000);
+        second.run_for(20000);
+        assert_eq!(first.leader_history(), second.leader_history());
+        assert_eq!(first.leader(), second.leader());
+        for n in 0..10u8 {
+            assert_eq!(first.append(&[n]), second.append(&[n]));
+            first.run_for(20);
+            second.run_for(20);
+        }
+        first.run_for(5000);
+        second.run_for(5000);
+        assert_eq!(first.acknowledged(), second.acknowledged());
+    }
+}
@@ -1,7 +1,6 @@
 use std::collections::{HashMap, HashSet};
-use std::net::SocketAddr;
 
-use LogIndex;
+use {LogIndex, NodeId};
 
 /// Replicas can be in one of three state:
 ///
@@ -21,8 +20,8 @@ pub enum ReplicaState {
 /// The state associated with a Raft replica in the `Leader` state.
 #[derive(Clone, Debug)]
 pub struct LeaderState {
-    next_index: HashMap<SocketAddr, LogIndex>,
-    match_index: HashMap<SocketAddr, LogIndex>,
+    next_index: HashMap<NodeId, LogIndex>,
+    match_index: HashMap<NodeId, LogIndex>,
 }
 
 impl LeaderState {
@@ -34,7 +33,7 @@ impl LeaderState {
     /// * `latest_log_index` - The index of the leader's most recent log entry at the
     ///                        time of election.
     /// * `peers` - The set of peer cluster members.
-    pub fn new(latest_log_index: LogIndex, peers: &HashSet<SocketAddr>) -> LeaderState {
+    pub fn new(latest_log_index: LogIndex, peers: &HashSet<NodeId>) -> LeaderState {
         let next_index = peers.iter().cloned().map(|peer| (peer, latest_log_index + 1)).collect();
         let match_index = peers.iter().cloned().map(|peer| (peer, LogIndex::from(0))).collect();
 
@@ -45,24 +44,24 @@ impl LeaderState {
     }
 
     /// Returns the next log entry index of the follower node.
-    pub fn next_index(&mut self, node: &SocketAddr) -> LogIndex {
+    pub fn next_index(&self, node: &NodeId) -> LogIndex {
         self.next_index[node]
     }
 
     /// Sets the next log entry index of the follower node.
-    pub fn set_next_index(&mut self, node: SocketAddr, index: LogIndex) {
+    pub fn set_next_index(&mut self, node: NodeId, index: LogIndex) {
         self.next_index.insert(node, index);
     }
 
     /// Returns the index of the highest log entry known to be replicated on
     /// the follower node.
-    pub fn match_index(&self, node: &SocketAddr) -> LogIndex {
+    pub fn match_index(&self, node: &NodeId) -> LogIndex {
         self.match_index[node]
     }
 
     /// Sets the index of the highest log entry known to be replicated on the
     /// follower node.
-    pub fn set_match_index(&mut self, node: SocketAddr, index: LogIndex) {
+    pub fn set_match_index(&mut self, node: NodeId, index: LogIndex) {
         self.match_index.insert(node, index);
     }
 
@@ -71,6 +70,18 @@ impl LeaderState {
         self.match_index.values().filter(|&&i| i >= index).count()
     }
 
+    /// Replaces the set of peers following a configuration change. The progress of the peers which
+    /// remain is kept, and the peers which were added start after the leader's most recent log
+    /// entry.
+    pub fn set_peers(&mut self, latest_log_index: LogIndex, peers: &HashSet<NodeId>) {
+        self.next_index.retain(|peer, _| peers.contains(peer));
+        self.match_index.retain(|peer, _| peers.contains(peer));
+        for &peer in peers {
+            self.next_index.entry(peer).or_insert(latest_log_index + 1);
+            self.match_index.entry(peer).or_insert(LogIndex::from(0));
+        }
+    }
+
     /// Reinitializes the state following an election.
     pub fn reinitialize(&mut self, latest_log_index: LogIndex) {
         for (_, next_index) in self.next_index.iter_mut() {
@@ -85,7 +96,7 @@ impl LeaderState {
 /// The state associated with a Raft replica in the `Candidate` state.
 #[derive(Clone, Debug)]
 pub struct CandidateState {
-    granted_votes: HashSet<SocketAddr>,
+    granted_votes: HashSet<NodeId>,
 }
 
 impl CandidateState {
@@ -96,7 +107,7 @@ impl CandidateState {
     }
 
     /// Records a vote from `voter`.
-    pub fn record_vote(&mut self, voter: SocketAddr) {
+    pub fn record_vote(&mut self, voter: NodeId) {
         self.granted_votes.insert(voter);
     }
 
@@ -116,7 +127,7 @@ impl CandidateState {
 pub struct FollowerState {
     /// The most recent leader of the follower. The leader is not guaranteed to be active, so this
     /// should only be used as a hint.
-    leader: Option<SocketAddr>,
+    leader: Option<NodeId>,
 }
 
 impl FollowerState {
@@ -127,7 +138,12 @@ impl FollowerState {
     }
 
     /// Sets a new leader.
-    pub fn set_leader(&mut self, leader: SocketAddr) {
+    pub fn set_leader(&mut self, leader: NodeId) {
         self.leader = Some(leader)
     }
+
+    /// Returns the most recent leader, if known.
+    pub fn leader(&self) -> Option<NodeId> {
+        self.leader
+    }
 }
@@ -2,19 +2,21 @@ use std::result;
 use std::fmt::{self, Debug};
 use std::sync::mpsc;
 
+use LogIndex;
 use state_machine::StateMachine;
 
 /// A state machine that simply redirects all commands to a channel.
 ///
 /// This state machine is chiefly meant for testing.
 pub struct ChannelStateMachine {
-    tx: mpsc::Sender<Vec<u8>>
+    tx: mpsc::Sender<Vec<u8>>,
+    last_applied: LogIndex,
 }
 
 impl ChannelStateMachine {
     pub fn new() -> (ChannelStateMachine, mpsc::Receiver<Vec<u8>>) {
         let (tx, recv) = mpsc::channel();
-        (ChannelStateMachine { tx: tx }, recv)
+        (ChannelStateMachine { tx: tx, last_applied: LogIndex::from(0) }, recv)
     }
 }
 
@@ -22,8 +24,14 @@ impl StateMachine for ChannelStateMachine {
 
     type Error = mpsc::SendError<Vec<u8>>;
 
-    fn apply(&mut self, command: &[u8]) -> result::Result<(), mpsc::SendError<Vec<u8>>> {
-        self.tx.send(command.to_vec())
+    fn apply(&mut self, index: LogIndex, command: &[u8]) -> result::Result<(), mpsc::SendError<Vec<u8>>> {
+        try!(self.tx.send(command.to_vec()));
+        self.last_applied = index;
+        Ok(())
+    }
+
+    fn last_applied(&self) -> result::Result<LogIndex, mpsc::SendError<Vec<u8>>> {
+        Ok(self.last_applied)
     }
 
     fn snapshot(&self) -> result::Result<Vec<u8>, mpsc::SendError<Vec<u8>>> {
@@ -11,6 +11,8 @@ mod null;
 use std::{error, result};
 use std::fmt::Debug;
 
+use LogIndex;
+
 pub use state_machine::channel::ChannelStateMachine;
 pub use state_machine::null::NullStateMachine;
 
@@ -18,8 +20,13 @@ pub trait StateMachine: Debug + Send + 'static {
 
     type Error: Debug + error::Error + Send + 'static;
 
-    /// Applies a command to the state machine.
-    fn apply(&mut self, command: &[u8]) -> result::Result<(), Self::Error>;
+    /// Applies the command at the provided log index to the state machine.
+    fn apply(&mut self, index: LogIndex, command: &[u8]) -> result::Result<(), Self::Error>;
+
+    /// Returns the index of the latest command which has been durably applied to the state
+    /// machine (0 if no command has been applied). A restarted `Replica` resumes applying
+    /// commands after this index.
+    fn last_applied(&self) -> result::Result<LogIndex, Self::Error>;
 
     /// Take a snapshot of the state machine.
     fn snapshot(&self) -> result::Result<Vec<u8>, Self::Error>;
@@ -1,5 +1,6 @@
 use std::{io, result};
 
+use LogIndex;
 use state_machine::StateMachine;
 
 /// A state machine with no states.
@@ -11,10 +12,14 @@ impl StateMachine for NullStateMachine {
     // The error type is not significant to this state machine
     type Error = io::Error;
 
-    fn apply(&mut self, _command: &[u8]) -> result::Result<(), io::Error> {
+    fn apply(&mut self, _index: LogIndex, _command: &[u8]) -> result::Result<(), io::Error> {
         Ok(())
     }
 
+    fn last_applied(&self) -> result::Result<LogIndex, io::Error> {
+        Ok(LogIndex::from(0))
+    }
+
     fn snapshot(&self) -> result::Result<Vec<u8>, io::Error> {
         Ok(Vec::new())
     }
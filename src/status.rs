@@ -0,0 +1,94 @@
+//! Snapshots of the state of a node, as reported to operators and health checks, and the
+//! changes to it delivered to subscribers.
+
+use std::collections::HashMap;
+
+use messages_capnp::{status, Role as WireRole};
+use {Error, ErrorKind, LogIndex, NodeId, Result, Term};
+
+/// The role of a node in the Raft protocol.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// A change to the state of the node related to a `Raft`, delivered to the subscribers returned by
+/// `Raft::subscribe()`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// The node took on a new role.
+    RoleChanged(Role),
+    /// The node moved to a new term.
+    TermChanged(Term),
+    /// The node learned of a new leader, or no longer knows the leader.
+    LeaderChanged(Option<NodeId>),
+}
+
+/// The replication progress of a peer, as tracked by the leader.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PeerStatus {
+    /// The index of the next log entry to send to the peer.
+    pub next_index: LogIndex,
+    /// The index of the latest log entry known to be replicated on the peer.
+    pub match_index: LogIndex,
+}
+
+/// A snapshot of the state of a node, returned by `Raft::status()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Status {
+    /// The ID of the node.
+    pub id: NodeId,
+    pub role: Role,
+    /// The current term of the node.
+    pub term: Term,
+    /// The index of the latest log entry known to be committed.
+    pub commit_index: LogIndex,
+    /// The index of the latest log entry applied to the `StateMachine`.
+    pub last_applied: LogIndex,
+    /// The ID of the leader known to the node, if any.
+    pub leader: Option<NodeId>,
+    /// The replication progress of each peer. Empty unless the node is the leader.
+    pub peers: HashMap<NodeId, PeerStatus>,
+    /// The number of messages the node dropped because they were not authenticated by the
+    /// cluster keys, or were replayed.
+    pub rejected_messages: u64,
+}
+
+/// Decodes a `Status` from its Cap'n Proto representation.
+pub fn decode(reader: status::Reader) -> Result<Status> {
+    let id = try!(decode_id(try!(reader.get_id())));
+    let leader = match try!(reader.get_leader()) {
+        leader if leader.is_empty() => None,
+        leader => Some(try!(decode_id(leader))),
+    };
+    let role = match try!(reader.get_role()) {
+        WireRole::Follower => Role::Follower,
+        WireRole::Candidate => Role::Candidate,
+        WireRole::Leader => Role::Leader,
+    };
+    let mut peers = HashMap::new();
+    let peer_statuses = try!(reader.get_peers());
+    for n in 0..peer_statuses.len() {
+        let peer_status = peer_statuses.get(n);
+        peers.insert(try!(decode_id(try!(peer_status.get_id()))), PeerStatus {
+            next_index: LogIndex::from(peer_status.get_next_index()),
+            match_index: LogIndex::from(peer_status.get_match_index()),
+        });
+    }
+    Ok(Status {
+        id: id,
+        role: role,
+        term: Term::from(reader.get_term()),
+        commit_index: LogIndex::from(reader.get_commit_index()),
+        last_applied: LogIndex::from(reader.get_last_applied()),
+        leader: leader,
+        peers: peers,
+        rejected_messages: reader.get_rejected_messages(),
+    })
+}
+
+fn decode_id(bytes: &[u8]) -> Result<NodeId> {
+    NodeId::from_bytes(bytes).ok_or(Error::Raft(ErrorKind::BadResponse))
+}
@@ -1,9 +1,11 @@
 use std::{error, fmt, result};
-use std::net::SocketAddr;
 
 use store::Store;
 
+use Configuration;
+use EntryKind;
 use LogIndex;
+use NodeId;
 use Term;
 
 /// This is a `Store` implementation that stores entries in a simple in-memory vector. Other data
@@ -11,8 +13,10 @@ use Term;
 #[derive(Clone, Debug)]
 pub struct MemStore {
     current_term: Term,
-    voted_for: Option<SocketAddr>,
-    entries: Vec<(Term, Vec<u8>)>,
+    voted_for: Option<NodeId>,
+    entries: Vec<(Term, EntryKind, Vec<u8>)>,
+    committed_configuration: Option<(LogIndex, Configuration)>,
+    uncommitted_configuration: Option<(LogIndex, Configuration)>,
 }
 
 /// Non-instantiable error type for MemStore
@@ -43,6 +47,8 @@ impl MemStore {
             current_term: Term(0),
             voted_for: None,
             entries: Vec::new(),
+            committed_configuration: None,
+            uncommitted_configuration: None,
         }
     }
 }
@@ -66,12 +72,12 @@ impl Store for MemStore {
         self.current_term()
     }
 
-    fn voted_for(&self) -> result::Result<Option<SocketAddr>, Error> {
+    fn voted_for(&self) -> result::Result<Option<NodeId>, Error> {
         Ok(self.voted_for)
     }
 
-    fn set_voted_for(&mut self, address: SocketAddr) -> result::Result<(), Error> {
-        Ok(self.voted_for = Some(address))
+    fn set_voted_for(&mut self, candidate: NodeId) -> result::Result<(), Error> {
+        Ok(self.voted_for = Some(candidate))
     }
 
     fn latest_log_index(&self) -> result::Result<LogIndex, Error> {
@@ -87,37 +93,62 @@ impl Store for MemStore {
         }
     }
 
-    fn entry(&self, index: LogIndex) -> result::Result<(Term, &[u8]), Error> {
-        let (term, ref bytes) = self.entries[Into::<u64>::into(index) as usize - 1];
-        Ok((term, &bytes))
+    fn entry(&self, index: LogIndex) -> result::Result<(Term, EntryKind, &[u8]), Error> {
+        let (term, kind, ref bytes) = self.entries[Into::<u64>::into(index) as usize - 1];
+        Ok((term, kind, &bytes))
     }
 
     fn append_entries(&mut self,
                       from: LogIndex,
-                      entries: &[(Term, &[u8])])
+                      entries: &[(Term, EntryKind, &[u8])])
                       -> result::Result<(), Error> {
         assert!(self.latest_log_index().unwrap() + 1 >= from);
         self.entries.truncate(Into::<u64>::into(from) as usize - 1);
-        Ok(self.entries.extend(entries.iter().map(|&(term, command)| (term, command.to_vec()))))
+        Ok(self.entries.extend(entries.iter().map(|&(term, kind, command)| (term, kind, command.to_vec()))))
+    }
+
+    fn committed_configuration(&self) -> result::Result<Option<(LogIndex, Configuration)>, Error> {
+        Ok(self.committed_configuration.clone())
+    }
+
+    fn set_committed_configuration(&mut self,
+                                   index: LogIndex,
+                                   configuration: Configuration)
+                                   -> result::Result<(), Error> {
+        Ok(self.committed_configuration = Some((index, configuration)))
+    }
+
+    fn uncommitted_configuration(&self) -> result::Result<Option<(LogIndex, Configuration)>, Error> {
+        Ok(self.uncommitted_configuration.clone())
+    }
+
+    fn set_uncommitted_configuration(&mut self,
+                                     configuration: Option<(LogIndex, Configuration)>)
+                                     -> result::Result<(), Error> {
+        Ok(self.uncommitted_configuration = configuration)
+    }
+
+    fn sync(&mut self) -> result::Result<(), Error> {
+        // Nothing is durable.
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
 
-    use std::str::FromStr;
-    use std::net::SocketAddr;
-
     use super::*;
+    use EntryKind;
     use LogIndex;
+    use NodeId;
     use Term;
-    use store::Store;
+    use store::{testing, Store};
 
     #[test]
     fn test_current_term() {
         let mut store = MemStore::new();
         assert_eq!(Term(0), store.current_term().unwrap());
-        store.set_voted_for(SocketAddr::from_str("127.0.0.1:0").unwrap()).unwrap();
+        store.set_voted_for(NodeId::new()).unwrap();
         store.set_current_term(Term(42)).unwrap();
         assert_eq!(None, store.voted_for().unwrap());
         assert_eq!(Term(42), store.current_term().unwrap());
@@ -129,9 +160,9 @@ mod test {
     fn test_voted_for() {
         let mut store = MemStore::new();
         assert_eq!(None, store.voted_for().unwrap());
-        let addr = SocketAddr::from_str("127.0.0.1:0").unwrap();
-        store.set_voted_for(addr.clone()).unwrap();
-        assert_eq!(Some(addr), store.voted_for().unwrap());
+        let id = NodeId::new();
+        store.set_voted_for(id).unwrap();
+        assert_eq!(Some(id), store.voted_for().unwrap());
     }
 
     #[test]
@@ -140,30 +171,35 @@ mod test {
         assert_eq!(LogIndex::from(0), store.latest_log_index().unwrap());
         assert_eq!(Term::from(0), store.latest_log_term().unwrap());
 
-        store.append_entries(LogIndex(1), &[(Term::from(0), &[1]),
-                                            (Term::from(0), &[2]),
-                                            (Term::from(0), &[3]),
-                                            (Term::from(1), &[4])]).unwrap();
+        store.append_entries(LogIndex(1), &[(Term::from(0), EntryKind::Application, &[1]),
+                                            (Term::from(0), EntryKind::Application, &[2]),
+                                            (Term::from(0), EntryKind::Application, &[3]),
+                                            (Term::from(1), EntryKind::Application, &[4])]).unwrap();
         assert_eq!(LogIndex::from(4), store.latest_log_index().unwrap());
         assert_eq!(Term::from(1), store.latest_log_term().unwrap());
-        assert_eq!((Term::from(0), &*vec![1u8]), store.entry(LogIndex::from(1)).unwrap());
-        assert_eq!((Term::from(0), &*vec![2u8]), store.entry(LogIndex::from(2)).unwrap());
-        assert_eq!((Term::from(0), &*vec![3u8]), store.entry(LogIndex::from(3)).unwrap());
-        assert_eq!((Term::from(1), &*vec![4u8]), store.entry(LogIndex::from(4)).unwrap());
+        assert_eq!((Term::from(0), EntryKind::Application, &*vec![1u8]), store.entry(LogIndex::from(1)).unwrap());
+        assert_eq!((Term::from(0), EntryKind::Application, &*vec![2u8]), store.entry(LogIndex::from(2)).unwrap());
+        assert_eq!((Term::from(0), EntryKind::Application, &*vec![3u8]), store.entry(LogIndex::from(3)).unwrap());
+        assert_eq!((Term::from(1), EntryKind::Application, &*vec![4u8]), store.entry(LogIndex::from(4)).unwrap());
 
         store.append_entries(LogIndex::from(4), &[]).unwrap();
         assert_eq!(LogIndex(3), store.latest_log_index().unwrap());
         assert_eq!(Term::from(0), store.latest_log_term().unwrap());
-        assert_eq!((Term::from(0), &*vec![1u8]), store.entry(LogIndex::from(1)).unwrap());
-        assert_eq!((Term::from(0), &*vec![2u8]), store.entry(LogIndex::from(2)).unwrap());
-        assert_eq!((Term::from(0), &*vec![3u8]), store.entry(LogIndex::from(3)).unwrap());
+        assert_eq!((Term::from(0), EntryKind::Application, &*vec![1u8]), store.entry(LogIndex::from(1)).unwrap());
+        assert_eq!((Term::from(0), EntryKind::Application, &*vec![2u8]), store.entry(LogIndex::from(2)).unwrap());
+        assert_eq!((Term::from(0), EntryKind::Application, &*vec![3u8]), store.entry(LogIndex::from(3)).unwrap());
 
-        store.append_entries(LogIndex::from(3), &[(Term(2), &[3]), (Term(3), &[4])]).unwrap();
+        store.append_entries(LogIndex::from(3), &[(Term(2), EntryKind::Application, &[3]), (Term(3), EntryKind::Application, &[4])]).unwrap();
         assert_eq!(LogIndex(4), store.latest_log_index().unwrap());
         assert_eq!(Term::from(3), store.latest_log_term().unwrap());
-        assert_eq!((Term::from(0), &*vec![1u8]), store.entry(LogIndex::from(1)).unwrap());
-        assert_eq!((Term::from(0), &*vec![2u8]), store.entry(LogIndex::from(2)).unwrap());
-        assert_eq!((Term::from(2), &*vec![3u8]), store.entry(LogIndex::from(3)).unwrap());
-        assert_eq!((Term::from(3), &*vec![4u8]), store.entry(LogIndex::from(4)).unwrap());
+        assert_eq!((Term::from(0), EntryKind::Application, &*vec![1u8]), store.entry(LogIndex::from(1)).unwrap());
+        assert_eq!((Term::from(0), EntryKind::Application, &*vec![2u8]), store.entry(LogIndex::from(2)).unwrap());
+        assert_eq!((Term::from(2), EntryKind::Application, &*vec![3u8]), store.entry(LogIndex::from(3)).unwrap());
+        assert_eq!((Term::from(3), EntryKind::Application, &*vec![4u8]), store.entry(LogIndex::from(4)).unwrap());
+    }
+
+    #[test]
+    fn test_conformance() {
+        testing::run_all(MemStore::new);
     }
 }
@@ -7,13 +7,16 @@
 //! for internal use by the library, we simply chose not to be opinionated about how data is stored.
 
 mod mem;
+pub mod testing;
 
 use std::error;
 use std::fmt::Debug;
-use std::net::SocketAddr;
 use std::result;
 
+use Configuration;
+use EntryKind;
 use LogIndex;
+use NodeId;
 use Term;
 
 pub use store::mem::{MemStore, Error};
@@ -21,7 +24,7 @@ pub use store::mem::{MemStore, Error};
 /// A store of persistent Raft state.
 pub trait Store: Clone + Debug + Send + 'static {
 
-    type Error: error::Error + Debug + Sized + 'static;
+    type Error: error::Error + Debug + Sized + Send + 'static;
 
     /// Returns the latest known term.
     fn current_term(&self) -> result::Result<Term, Self::Error>;
@@ -34,10 +37,10 @@ pub trait Store: Clone + Debug + Send + 'static {
     fn inc_current_term(&mut self) -> result::Result<Term, Self::Error>;
 
     /// Returns the candidate id of the candidate voted for in the current term (or none).
-    fn voted_for(&self) -> result::Result<Option<SocketAddr>, Self::Error>;
+    fn voted_for(&self) -> result::Result<Option<NodeId>, Self::Error>;
 
     /// Sets the candidate id voted for in the current term.
-    fn set_voted_for(&mut self, address: SocketAddr) -> result::Result<(), Self::Error>;
+    fn set_voted_for(&mut self, candidate: NodeId) -> result::Result<(), Self::Error>;
 
     /// Returns the index of the latest persisted log entry (0 if the log is empty).
     fn latest_log_index(&self) -> result::Result<LogIndex, Self::Error>;
@@ -45,13 +48,39 @@ pub trait Store: Clone + Debug + Send + 'static {
     /// Returns the term of the latest persisted log entry (0 if the log is empty).
     fn latest_log_term(&self) -> result::Result<Term, Self::Error>;
 
-    /// Returns the entry at the provided log index.
+    /// Returns the term, kind and payload of the entry at the provided log index.
     ///
     /// # Panic
     ///
     /// This method will panic if the index greater than the largest index.
-    fn entry(&self, index: LogIndex) -> result::Result<(Term, &[u8]), Self::Error>;
+    fn entry(&self, index: LogIndex) -> result::Result<(Term, EntryKind, &[u8]), Self::Error>;
 
     /// Appends the provided entries to the log beginning at the given index.
-    fn append_entries(&mut self, from: LogIndex, entries: &[(Term, &[u8])]) -> result::Result<(), Self::Error>;
+    fn append_entries(&mut self,
+                      from: LogIndex,
+                      entries: &[(Term, EntryKind, &[u8])])
+                      -> result::Result<(), Self::Error>;
+
+    /// Returns the latest committed cluster configuration along with the index of the log entry
+    /// which carried it, or `None` if no configuration has been stored.
+    fn committed_configuration(&self) -> result::Result<Option<(LogIndex, Configuration)>, Self::Error>;
+
+    /// Sets the latest committed cluster configuration.
+    fn set_committed_configuration(&mut self,
+                                   index: LogIndex,
+                                   configuration: Configuration)
+                                   -> result::Result<(), Self::Error>;
+
+    /// Returns the latest cluster configuration which has been appended to the log but not yet
+    /// committed, along with the index of its log entry, or `None` if there is no such
+    /// configuration.
+    fn uncommitted_configuration(&self) -> result::Result<Option<(LogIndex, Configuration)>, Self::Error>;
+
+    /// Sets the uncommitted cluster configuration, or clears it if `None` is provided.
+    fn set_uncommitted_configuration(&mut self,
+                                     configuration: Option<(LogIndex, Configuration)>)
+                                     -> result::Result<(), Self::Error>;
+
+    /// Flushes all stored state to durable storage. Called when the `Server` shuts down.
+    fn sync(&mut self) -> result::Result<(), Self::Error>;
 }
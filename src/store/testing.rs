@@ -0,0 +1,169 @@
+//! A conformance test suite for `Store` implementations.
+//!
+//! The `Replica` relies on a number of properties of the `Store` which are not expressed in the
+//! type system, for instance that setting the term resets the vote, or that appending entries
+//! truncates any conflicting entries already in the log. Implementors of `Store` should run this
+//! suite against their own backend:
+//!
+//! ```ignore
+//! #[test]
+//! fn test_store_conformance() {
+//!     raft::store::testing::run_all(MyStore::new);
+//! }
+//! ```
+//!
+//! Each check takes a freshly created, empty store and panics if the store does not hold up its
+//! end of the contract.
+//!
+//! *Note:* `Store` does not support log compaction yet. Checks covering it will be added to
+//! `run_all()` along with it.
+
+use std::collections::HashSet;
+
+use store::Store;
+
+use Configuration;
+use EntryKind;
+use LogIndex;
+use NodeId;
+use Term;
+
+/// Runs every check in the suite, each against a new store created by `factory`.
+pub fn run_all<S, F>(factory: F) where S: Store, F: Fn() -> S {
+    test_current_term(factory());
+    test_voted_for(factory());
+    test_empty_log(factory());
+    test_append_entries(factory());
+    test_truncate_entries(factory());
+    test_overwrite_entries(factory());
+    test_configuration(factory());
+    test_sync(factory());
+}
+
+/// Checks that the term starts at 0, can be set and incremented, and that both operations reset
+/// the vote.
+pub fn test_current_term<S>(mut store: S) where S: Store {
+    let id = NodeId::new();
+    assert_eq!(Term::from(0), store.current_term().unwrap());
+
+    store.set_voted_for(id).unwrap();
+    store.set_current_term(Term::from(42)).unwrap();
+    assert_eq!(None, store.voted_for().unwrap());
+    assert_eq!(Term::from(42), store.current_term().unwrap());
+
+    store.set_voted_for(id).unwrap();
+    assert_eq!(Term::from(43), store.inc_current_term().unwrap());
+    assert_eq!(None, store.voted_for().unwrap());
+    assert_eq!(Term::from(43), store.current_term().unwrap());
+}
+
+/// Checks that no vote is recorded initially, and that a vote can be recorded and replaced within
+/// a term.
+pub fn test_voted_for<S>(mut store: S) where S: Store {
+    let a = NodeId::new();
+    let b = NodeId::new();
+    assert_eq!(None, store.voted_for().unwrap());
+
+    store.set_voted_for(a).unwrap();
+    assert_eq!(Some(a), store.voted_for().unwrap());
+    store.set_voted_for(b).unwrap();
+    assert_eq!(Some(b), store.voted_for().unwrap());
+}
+
+/// Checks that an empty log reports index 0 and term 0 for its latest entry.
+pub fn test_empty_log<S>(mut store: S) where S: Store {
+    assert_eq!(LogIndex::from(0), store.latest_log_index().unwrap());
+    assert_eq!(Term::from(0), store.latest_log_term().unwrap());
+
+    // The latest log term is that of the latest entry, not the current term.
+    store.set_current_term(Term::from(3)).unwrap();
+    assert_eq!(Term::from(0), store.latest_log_term().unwrap());
+}
+
+/// Checks that entries appended to the end of the log are stored in order, and that appending
+/// again after the latest entry extends the log.
+pub fn test_append_entries<S>(mut store: S) where S: Store {
+    store.append_entries(LogIndex::from(1), &[(Term::from(1), EntryKind::Application, &[1]),
+                                              (Term::from(1), EntryKind::Application, &[2])]).unwrap();
+    assert_eq!(LogIndex::from(2), store.latest_log_index().unwrap());
+    assert_eq!(Term::from(1), store.latest_log_term().unwrap());
+
+    store.append_entries(LogIndex::from(3), &[(Term::from(2), EntryKind::Application, &[3])]).unwrap();
+    assert_eq!(LogIndex::from(3), store.latest_log_index().unwrap());
+    assert_eq!(Term::from(2), store.latest_log_term().unwrap());
+    assert_eq!((Term::from(1), EntryKind::Application, &[1u8][..]), store.entry(LogIndex::from(1)).unwrap());
+    assert_eq!((Term::from(1), EntryKind::Application, &[2u8][..]), store.entry(LogIndex::from(2)).unwrap());
+    assert_eq!((Term::from(2), EntryKind::Application, &[3u8][..]), store.entry(LogIndex::from(3)).unwrap());
+}
+
+/// Checks that appending no entries at an index within the log truncates the log from that index.
+pub fn test_truncate_entries<S>(mut store: S) where S: Store {
+    store.append_entries(LogIndex::from(1), &[(Term::from(1), EntryKind::Application, &[1]),
+                                              (Term::from(1), EntryKind::Application, &[2]),
+                                              (Term::from(2), EntryKind::Application, &[3])]).unwrap();
+
+    store.append_entries(LogIndex::from(3), &[]).unwrap();
+    assert_eq!(LogIndex::from(2), store.latest_log_index().unwrap());
+    assert_eq!(Term::from(1), store.latest_log_term().unwrap());
+    assert_eq!((Term::from(1), EntryKind::Application, &[2u8][..]), store.entry(LogIndex::from(2)).unwrap());
+
+    store.append_entries(LogIndex::from(1), &[]).unwrap();
+    assert_eq!(LogIndex::from(0), store.latest_log_index().unwrap());
+    assert_eq!(Term::from(0), store.latest_log_term().unwrap());
+}
+
+/// Checks that appending entries at an index within the log replaces the entries from that index
+/// onwards, including those beyond the newly appended entries.
+pub fn test_overwrite_entries<S>(mut store: S) where S: Store {
+    store.append_entries(LogIndex::from(1), &[(Term::from(1), EntryKind::Application, &[1]),
+                                              (Term::from(1), EntryKind::Application, &[2]),
+                                              (Term::from(1), EntryKind::Application, &[3]),
+                                              (Term::from(1), EntryKind::Application, &[4])]).unwrap();
+
+    store.append_entries(LogIndex::from(2), &[(Term::from(2), EntryKind::Application, &[5])]).unwrap();
+    assert_eq!(LogIndex::from(2), store.latest_log_index().unwrap());
+    assert_eq!(Term::from(2), store.latest_log_term().unwrap());
+    assert_eq!((Term::from(1), EntryKind::Application, &[1u8][..]), store.entry(LogIndex::from(1)).unwrap());
+    assert_eq!((Term::from(2), EntryKind::Application, &[5u8][..]), store.entry(LogIndex::from(2)).unwrap());
+}
+
+/// Checks that no configuration is stored initially, and that the committed and uncommitted
+/// configurations are stored independently along with their log index.
+pub fn test_configuration<S>(mut store: S) where S: Store {
+    let a = NodeId::new();
+    let b = NodeId::new();
+    let mut members = HashSet::new();
+    members.insert(a);
+    let committed = Configuration::new(members.clone());
+    members.insert(b);
+    let uncommitted = Configuration::new(members);
+
+    assert_eq!(None, store.committed_configuration().unwrap());
+    assert_eq!(None, store.uncommitted_configuration().unwrap());
+
+    store.set_committed_configuration(LogIndex::from(1), committed.clone()).unwrap();
+    store.set_uncommitted_configuration(Some((LogIndex::from(2), uncommitted.clone()))).unwrap();
+    assert_eq!(Some((LogIndex::from(1), committed.clone())), store.committed_configuration().unwrap());
+    assert_eq!(Some((LogIndex::from(2), uncommitted.clone())), store.uncommitted_configuration().unwrap());
+
+    store.set_uncommitted_configuration(None).unwrap();
+    assert_eq!(Some((LogIndex::from(1), committed)), store.committed_configuration().unwrap());
+    assert_eq!(None, store.uncommitted_configuration().unwrap());
+}
+
+/// Checks that syncing leaves the stored state unchanged.
+pub fn test_sync<S>(mut store: S) where S: Store {
+    let id = NodeId::new();
+    store.set_current_term(Term::from(3)).unwrap();
+    store.set_voted_for(id).unwrap();
+    store.append_entries(LogIndex::from(1),
+                         &[(Term::from(1), EntryKind::Application, b"one"),
+                           (Term::from(3), EntryKind::Noop, b"")]).unwrap();
+    store.sync().unwrap();
+    assert_eq!(Term::from(3), store.current_term().unwrap());
+    assert_eq!(Some(id), store.voted_for().unwrap());
+    assert_eq!(LogIndex::from(2), store.latest_log_index().unwrap());
+    assert_eq!(Term::from(3), store.latest_log_term().unwrap());
+    assert_eq!((Term::from(1), EntryKind::Application, &b"one"[..]),
+               store.entry(LogIndex::from(1)).unwrap());
+}
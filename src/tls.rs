@@ -0,0 +1,39 @@
+//! TLS for the connections between `Server`s and from `Raft` clients.
+//!
+//! TLS is enabled by setting `Config::tls`. Every node then presents its certificate on both the
+//! connections it accepts and the connections it opens, and refuses any peer or client whose
+//! certificate is not signed by the configured certificate authority. Since a `Raft` client is
+//! itself a member of the cluster, it authenticates with the certificate of its node.
+//!
+//! Only the certificate authority is checked; the name in a certificate is not matched against
+//! the address of the node presenting it.
+
+use std::path::PathBuf;
+
+use openssl::ssl::{SslContext, SslMethod, SSL_VERIFY_PEER, SSL_VERIFY_FAIL_IF_NO_PEER_CERT};
+use openssl::x509::X509FileType;
+
+use Result;
+
+/// The certificates a node uses for TLS. Every file is in PEM format.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// The certificate authority which signed the certificates of every node in the cluster.
+    pub ca_file: PathBuf,
+    /// The certificate of this node.
+    pub cert_file: PathBuf,
+    /// The private key of the certificate of this node.
+    pub key_file: PathBuf,
+}
+
+/// Creates a context for both accepting and opening TLS connections, which requires the other end
+/// to present a certificate signed by the certificate authority.
+pub fn context(config: &TlsConfig) -> Result<SslContext> {
+    let mut context = try!(SslContext::new(SslMethod::Sslv23));
+    try!(context.set_CA_file(&config.ca_file));
+    try!(context.set_certificate_file(&config.cert_file, X509FileType::PEM));
+    try!(context.set_private_key_file(&config.key_file, X509FileType::PEM));
+    try!(context.check_private_key());
+    context.set_verify(SSL_VERIFY_PEER | SSL_VERIFY_FAIL_IF_NO_PEER_CERT, None);
+    Ok(context)
+}
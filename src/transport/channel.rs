@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{mpsc, Arc, Mutex};
+
+use capnp::OwnedSpaceMessageReader;
+use mio::{EventLoop, EventLoopSender, Handler, ReadHint, Token};
+
+use transport::{Notification, Transport};
+use {NodeId, Result};
+
+/// Where the messages sent to an address on a `ChannelNetwork` are delivered.
+enum Route {
+    /// The event loop of a `Server` using a `ChannelTransport`.
+    Server(EventLoopSender<Notification>),
+    /// A `ChannelEndpoint`.
+    Endpoint(mpsc::Sender<(SocketAddr, Vec<u8>)>),
+}
+
+/// An in-process network which connects `ChannelTransport`s and `ChannelEndpoint`s by address.
+/// No sockets are bound, so the addresses are only used as names.
+///
+/// This network is chiefly meant for testing many `Server`s in a single process.
+#[derive(Clone)]
+pub struct ChannelNetwork {
+    routes: Arc<Mutex<HashMap<SocketAddr, Route>>>,
+}
+
+impl ChannelNetwork {
+
+    /// Creates a new, empty network.
+    pub fn new() -> ChannelNetwork {
+        ChannelNetwork { routes: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    /// Creates a transport for the `Server` with the provided ID at the provided address. The
+    /// `Server` joins the network when it registers the transport with its event loop.
+    pub fn transport(&self, id: NodeId, addr: SocketAddr) -> ChannelTransport {
+        ChannelTransport { id: id, addr: addr, network: self.clone() }
+    }
+
+    /// Creates an endpoint at the provided address, which can exchange messages with the
+    /// `Server`s on the network in the place of a client.
+    pub fn endpoint(&self, addr: SocketAddr) -> ChannelEndpoint {
+        let (tx, rx) = mpsc::channel();
+        self.routes.lock().unwrap().insert(addr, Route::Endpoint(tx));
+        ChannelEndpoint { id: NodeId::new(), addr: addr, network: self.clone(), rx: rx }
+    }
+
+    /// Delivers a packed message from the node with the provided ID to the provided address. Like
+    /// a real network, messages to an unknown or unreachable address are silently dropped.
+    fn deliver(&self, from: SocketAddr, id: NodeId, to: SocketAddr, message: &[u8]) {
+        let routes = self.routes.lock().unwrap();
+        let delivered = match routes.get(&to) {
+            Some(&Route::Server(ref sender)) => {
+                sender.send(Notification::Received(from, id, message.to_vec())).is_ok()
+            },
+            Some(&Route::Endpoint(ref sender)) => sender.send((from, message.to_vec())).is_ok(),
+            None => false,
+        };
+        if !delivered {
+            debug!("ChannelNetwork: dropping message from {} to {}", from, to);
+        }
+    }
+
+    /// Removes the address from the network.
+    fn remove(&self, addr: &SocketAddr) {
+        self.routes.lock().unwrap().remove(addr);
+    }
+}
+
+/// A `Transport` which exchanges messages over a `ChannelNetwork`.
+pub struct ChannelTransport {
+    id: NodeId,
+    addr: SocketAddr,
+    network: ChannelNetwork,
+}
+
+impl Transport for ChannelTransport {
+
+    fn register<H>(&mut self, event_loop: &mut EventLoop<H>) -> Result<()>
+    where H: Handler<Message=Notification> {
+        self.network.routes.lock().unwrap().insert(self.addr, Route::Server(event_loop.channel()));
+        Ok(())
+    }
+
+    fn readable<H>(&mut self, _event_loop: &mut EventLoop<H>, _token: Token, _hint: ReadHint)
+                   -> Result<Vec<(SocketAddr, NodeId, OwnedSpaceMessageReader)>>
+    where H: Handler {
+        // Messages are delivered through the event loop channel instead.
+        unreachable!()
+    }
+
+    fn writable<H>(&mut self, _event_loop: &mut EventLoop<H>, _token: Token) -> Result<()>
+    where H: Handler {
+        unreachable!()
+    }
+
+    fn send<H>(&mut self, _event_loop: &mut EventLoop<H>, to: SocketAddr, message: &[u8]) -> Result<()>
+    where H: Handler {
+        self.network.deliver(self.addr, self.id, to, message);
+        Ok(())
+    }
+
+    fn set_cluster_keys(&mut self, _keys: Vec<Vec<u8>>) {
+        // Messages never leave the process, so they are not sealed.
+    }
+
+    fn rejected_messages(&self) -> u64 {
+        0
+    }
+
+    fn close_idle<H>(&mut self, _event_loop: &mut EventLoop<H>) -> Result<()>
+    where H: Handler {
+        // There are no connections.
+        Ok(())
+    }
+
+    fn shutdown<H>(&mut self, _event_loop: &mut EventLoop<H>) -> Result<()>
+    where H: Handler {
+        // Messages are delivered as they are sent, so there is nothing to flush.
+        self.network.remove(&self.addr);
+        Ok(())
+    }
+}
+
+impl Drop for ChannelTransport {
+    fn drop(&mut self) {
+        self.network.remove(&self.addr);
+    }
+}
+
+/// A client's end of a `ChannelNetwork`.
+pub struct ChannelEndpoint {
+    /// The ID the endpoint presents to the `Server`s it sends messages to.
+    id: NodeId,
+    addr: SocketAddr,
+    network: ChannelNetwork,
+    rx: mpsc::Receiver<(SocketAddr, Vec<u8>)>,
+}
+
+impl ChannelEndpoint {
+
+    /// Sends a packed message to the provided address.
+    pub fn send(&self, to: SocketAddr, message: &[u8]) {
+        self.network.deliver(self.addr, self.id, to, message);
+    }
+
+    /// Blocks until a packed message is received, and returns it along with the address of its
+    /// sender. Returns `None` if the endpoint can no longer receive messages.
+    pub fn recv(&self) -> Option<(SocketAddr, Vec<u8>)> {
+        self.rx.recv().ok()
+    }
+}
+
+impl Drop for ChannelEndpoint {
+    fn drop(&mut self) {
+        self.network.remove(&self.addr);
+    }
+}
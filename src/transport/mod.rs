@@ -0,0 +1,94 @@
+//! The transport of messages between `Server`s and their clients.
+//!
+//! A `Transport` moves packed Cap'n Proto messages (`RpcRequest`s and `RpcResponse`s between
+//! `Server`s, `ClientRequest`s and `ClientResponse`s between `Server`s and clients) between
+//! addresses. Each `Server` owns a single transport, which it registers with its event loop when
+//! it starts.
+//!
+//! Messages may reach the `Server` in one of two ways:
+//!
+//! * From `readable()`, for transports which register IO handles with the event loop, such as
+//!   `TcpTransport`.
+//! * As a `Notification` through the event loop's channel, for transports which are not backed by
+//!   IO handles, such as `ChannelTransport`.
+//!
+//! Transports which carry messages out of the process authenticate them with the cluster keys, if
+//! there are any.
+//!
+//! A `Raft` created with `Raft::new()` uses a `TcpTransport`. Another transport is given with
+//! `Raft::with_transport()`, for instance a `ChannelTransport` to run several nodes in a single
+//! process.
+
+mod channel;
+mod tcp;
+
+use std::net::SocketAddr;
+use std::sync::mpsc::Sender;
+
+use capnp::OwnedSpaceMessageReader;
+use mio::{EventLoop, Handler, ReadHint, Token};
+
+use client::Reply;
+use {Event, NodeId, Result};
+
+pub use transport::channel::{ChannelEndpoint, ChannelNetwork, ChannelTransport};
+pub use transport::tcp::TcpTransport;
+
+/// The notifications which can be delivered to a `Server` through its event loop channel.
+pub enum Notification {
+    /// A packed message was received from the provided address, sent by the node with the
+    /// provided ID.
+    Received(SocketAddr, NodeId, Vec<u8>),
+    /// The `Server` should shut down.
+    Shutdown,
+    /// The `Server` should authenticate messages with the provided cluster keys from now on.
+    ClusterKeys(Vec<Vec<u8>>),
+    /// The `Server` should send the changes to the state of its replica to the provided channel.
+    Subscribe(Sender<Event>),
+    /// A packed `ClientRequest` was received from the `Raft` in the same process. Its response is
+    /// sent through the provided `Reply` rather than through the transport.
+    Request(Vec<u8>, Reply),
+}
+
+/// A transport of messages between addresses.
+pub trait Transport: Send + 'static {
+
+    /// Registers the transport with the event loop of the `Server` which owns it. All IO tokens
+    /// on the event loop belong to the transport.
+    fn register<H>(&mut self, event_loop: &mut EventLoop<H>) -> Result<()>
+    where H: Handler<Message=Notification>;
+
+    /// Called when an IO handle registered by the transport is readable, or has hung up or failed
+    /// as told by the hint. Returns every message which has been received in full, along with
+    /// the address of its sender and the ID the sender presented in the handshake of its
+    /// connection. Transports authenticate that ID only if they have cluster keys.
+    fn readable<H>(&mut self, event_loop: &mut EventLoop<H>, token: Token, hint: ReadHint)
+                   -> Result<Vec<(SocketAddr, NodeId, OwnedSpaceMessageReader)>>
+    where H: Handler;
+
+    /// Called when an IO handle registered by the transport is writable.
+    fn writable<H>(&mut self, event_loop: &mut EventLoop<H>, token: Token) -> Result<()>
+    where H: Handler;
+
+    /// Sends a packed message to the provided address. This may only queue the message; it is
+    /// not guaranteed to have been delivered when this returns.
+    fn send<H>(&mut self, event_loop: &mut EventLoop<H>, to: SocketAddr, message: &[u8]) -> Result<()>
+    where H: Handler;
+
+    /// Replaces the cluster keys which the messages are sealed and unsealed with.
+    fn set_cluster_keys(&mut self, keys: Vec<Vec<u8>>);
+
+    /// Returns the number of received messages which have been dropped because they failed to
+    /// unseal.
+    fn rejected_messages(&self) -> u64;
+
+    /// Closes the connections which have been idle since the previous call. Called periodically
+    /// by the `Server`.
+    fn close_idle<H>(&mut self, event_loop: &mut EventLoop<H>) -> Result<()>
+    where H: Handler;
+
+    /// Sends as many of the queued messages as possible without blocking, then closes all
+    /// connections. Called once, when the `Server` shuts down.
+    fn shutdown<H>(&mut self, event_loop: &mut EventLoop<H>) -> Result<()>
+    where H: Handler;
+}
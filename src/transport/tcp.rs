@@ -0,0 +1,537 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::mem;
+use std::net::SocketAddr;
+use std::result;
+
+// MIO
+use mio::tcp::{self, listen, TcpListener, TcpStream};
+use mio::util::Slab;
+use mio::Socket;
+use mio::{Interest, PollOpt, NonBlock, ReadHint, Token, EventLoop, Handler};
+use mio::{TryRead, TryWrite};
+
+// Cap'n Proto
+use capnp::OwnedSpaceMessageReader;
+
+// OpenSSL
+use openssl::ssl::{NonblockingSslStream, SslContext};
+use openssl::ssl::error::NonblockingSslError;
+
+use auth::{self, Opener, Sealer};
+use frame::{self, FrameDecoder};
+use handshake;
+use tls;
+use transport::{Notification, Transport};
+use {Config, Error, ErrorKind, NodeId, Result};
+
+// MIO Tokens
+const LISTENER: Token = Token(0);
+
+/// The size of the chunks read off a connection.
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// A `Transport` which exchanges messages over TCP.
+///
+/// A connection to an address is opened the first time a message is sent to it. Messages are
+/// received on both the opened connections and the connections accepted by the listener, and
+/// responses to a message received on a connection are sent back on that same connection.
+///
+/// Messages are framed with their length, and messages larger than the maximum message size of
+/// the `Config` are refused. A connection which delivers an oversized or malformed frame is
+/// closed.
+///
+/// Connections are closed when they hang up or fail, and when they are idle between two calls to
+/// `close_idle()`. At most `max_connections` of the `Config` are open at once; further
+/// connections are refused.
+///
+/// If the `Config` holds a `TlsConfig`, every connection is secured with TLS, and connections
+/// whose other end does not present a certificate signed by the configured certificate authority
+/// are closed.
+///
+/// Both ends of a connection start with a handshake. Connections from nodes of another cluster or
+/// with an incompatible protocol version are closed.
+///
+/// If the `Config` holds cluster keys, the messages sent on a connection are sealed with the nonce
+/// announced by the other end (see `auth`), and received messages which fail to unseal are
+/// dropped. Messages sent before the handshake of the other end arrives wait for it. The handshakes
+/// are then authenticated with the keys as well, so that the ID of the other end can be trusted;
+/// without keys, it is taken as announced, even over TLS.
+pub struct TcpTransport {
+    addr: SocketAddr,
+    listener: Option<NonBlock<TcpListener>>,
+    connections: Slab<Connection>,
+    /// The connection to each remote address.
+    tokens: HashMap<SocketAddr, Token>,
+    max_message_size: usize,
+    max_connections: usize,
+    /// The TLS context of the connections, if TLS is enabled.
+    tls: Option<SslContext>,
+    /// The ID of the node, sent in handshakes.
+    id: NodeId,
+    cluster_id: String,
+    cluster_keys: Vec<Vec<u8>>,
+    /// The number of received messages which failed to unseal.
+    rejected_messages: u64,
+}
+
+impl TcpTransport {
+
+    /// Creates a new `TcpTransport` for the node with the provided ID, which will listen on the
+    /// provided address once it is registered. The `Config` limits the number of connections and
+    /// the size of messages, enables TLS, and holds the cluster ID exchanged in handshakes and the
+    /// cluster keys.
+    ///
+    /// Returns an error if the TLS certificates can not be loaded.
+    pub fn new(id: NodeId, addr: SocketAddr, config: &Config) -> Result<TcpTransport> {
+        let tls = match config.tls {
+            Some(ref tls) => Some(try!(tls::context(tls))),
+            None => None,
+        };
+        Ok(TcpTransport {
+            addr: addr,
+            listener: None,
+            connections: Slab::new_starting_at(Token(1), config.max_connections),
+            tokens: HashMap::new(),
+            max_message_size: config.max_message_size,
+            max_connections: config.max_connections,
+            tls: tls,
+            id: id,
+            cluster_id: config.cluster_id.clone(),
+            cluster_keys: config.cluster_keys.clone(),
+            rejected_messages: 0,
+        })
+    }
+
+    /// Secures a connection accepted by the listener, if TLS is enabled.
+    fn accept_stream(&self, stream: NonBlock<TcpStream>) -> Result<Stream> {
+        match self.tls {
+            Some(ref context) => Ok(Stream::Tls(try!(NonblockingSslStream::accept(context, stream)))),
+            None => Ok(Stream::Plain(stream)),
+        }
+    }
+
+    /// Secures a connection opened to a remote address, if TLS is enabled.
+    fn connect_stream(&self, stream: NonBlock<TcpStream>) -> Result<Stream> {
+        match self.tls {
+            Some(ref context) => Ok(Stream::Tls(try!(NonblockingSslStream::connect(context, stream)))),
+            None => Ok(Stream::Plain(stream)),
+        }
+    }
+
+    /// Adds the connection to the slab, registers it with the event loop, and starts the
+    /// handshake with a fresh nonce.
+    fn add_connection<H>(&mut self,
+                         event_loop: &mut EventLoop<H>,
+                         stream: Stream,
+                         remote: SocketAddr)
+                         -> Result<Token>
+    where H: Handler {
+        let nonce = auth::nonce();
+        let handshake = handshake::handshake(&self.cluster_id, self.id, &nonce, &self.cluster_keys);
+        let handshake = try!(frame::frame(&handshake, self.max_message_size));
+        let conn = Connection::new(stream, remote, self.max_message_size, self.cluster_id.clone(),
+                                   nonce);
+        let tok = match self.connections.insert(conn) {
+            Ok(tok) => tok,
+            // The stream is dropped, which closes it.
+            Err(_) => return Err(Error::Raft(ErrorKind::ConnectionLimit)),
+        };
+
+        // Register the connection
+        self.connections[tok].token = tok;
+        if let Err(error) = event_loop.register_opt(self.connections[tok].stream.socket(), tok,
+                                                    Interest::readable(),
+                                                    PollOpt::edge() | PollOpt::oneshot()) {
+            // Free the slot, or it would count against the connection limit forever.
+            self.connections.remove(tok);
+            return Err(Error::from(error));
+        }
+        self.tokens.insert(remote, tok);
+        if let Err(error) = self.connections[tok].add_write(event_loop, handshake) {
+            self.close(event_loop, tok);
+            return Err(error);
+        }
+        Ok(tok)
+    }
+
+    /// Closes the connection and removes it from the slab.
+    fn close<H>(&mut self, event_loop: &mut EventLoop<H>, token: Token) where H: Handler {
+        if let Some(connection) = self.connections.remove(token) {
+            if self.tokens.get(&connection.remote) == Some(&token) {
+                self.tokens.remove(&connection.remote);
+            }
+            if let Err(error) = event_loop.deregister(connection.stream.socket()) {
+                warn!("TcpTransport: unable to deregister the connection to {}: {:?}",
+                      connection.remote, error);
+            }
+        }
+    }
+}
+
+impl Transport for TcpTransport {
+
+    fn register<H>(&mut self, event_loop: &mut EventLoop<H>) -> Result<()>
+    where H: Handler<Message=Notification> {
+        // Setup the socket, make it not block.
+        let listener = try!(listen(&self.addr));
+        try!(listener.set_reuseaddr(true));
+        try!(event_loop.register(&listener, LISTENER));
+        self.listener = Some(listener);
+        Ok(())
+    }
+
+    fn readable<H>(&mut self, event_loop: &mut EventLoop<H>, token: Token, hint: ReadHint)
+                   -> Result<Vec<(SocketAddr, NodeId, OwnedSpaceMessageReader)>>
+    where H: Handler {
+        match token {
+            LISTENER => {
+                let accepted = try!(self.listener.as_mut()
+                                        .expect("TcpTransport is not registered.")
+                                        .accept());
+                let stream = match accepted {
+                    Some(s) => s,
+                    None => return Ok(Vec::new()), // Socket isn't quite ready.
+                };
+                let remote = try!(stream.peer_addr());
+                let stream = match self.accept_stream(stream) {
+                    Ok(stream) => stream,
+                    Err(error) => {
+                        warn!("TcpTransport: refusing the connection from {}: {:?}", remote, error);
+                        return Ok(Vec::new());
+                    },
+                };
+                match self.add_connection(event_loop, stream, remote) {
+                    Ok(_) => (),
+                    Err(Error::Raft(ErrorKind::ConnectionLimit)) => {
+                        warn!("TcpTransport: refusing the connection from {}: the limit of {} connections is reached.",
+                              remote, self.max_connections);
+                    },
+                    Err(error) => return Err(error),
+                }
+                Ok(Vec::new())
+            },
+            tok => {
+                if !self.connections.contains(tok) {
+                    // The connection has already been closed.
+                    return Ok(Vec::new());
+                }
+                let result = self.connections[tok].readable(event_loop, &self.cluster_keys,
+                                                            &mut self.rejected_messages);
+                match result {
+                    Ok(messages) => {
+                        if self.connections[tok].closed || hint.is_hup() || hint.is_error() {
+                            debug!("TcpTransport: the connection to {} hung up.", self.connections[tok].remote);
+                            self.close(event_loop, tok);
+                        }
+                        Ok(messages)
+                    },
+                    Err(error) => {
+                        // Only this connection is affected.
+                        error!("TcpTransport: closing the connection to {}: {:?}",
+                               self.connections[tok].remote, error);
+                        self.close(event_loop, tok);
+                        Ok(Vec::new())
+                    },
+                }
+            },
+        }
+    }
+
+    fn writable<H>(&mut self, event_loop: &mut EventLoop<H>, token: Token) -> Result<()>
+    where H: Handler {
+        match token {
+            LISTENER => unreachable!(),
+            tok => {
+                if !self.connections.contains(tok) {
+                    // The connection has already been closed.
+                    return Ok(());
+                }
+                let result = self.connections[tok].writable(event_loop);
+                if let Err(error) = result {
+                    error!("TcpTransport: closing the connection to {}: {:?}",
+                           self.connections[tok].remote, error);
+                    self.close(event_loop, tok);
+                }
+                Ok(())
+            },
+        }
+    }
+
+    fn send<H>(&mut self, event_loop: &mut EventLoop<H>, to: SocketAddr, message: &[u8]) -> Result<()>
+    where H: Handler {
+        // Refuse oversized messages before opening a connection for them.
+        try!(frame::frame(message, self.max_message_size));
+        let existing = self.tokens.get(&to).cloned();
+        let tok = match existing {
+            Some(tok) => tok,
+            None => {
+                let (stream, _) = try!(tcp::connect(&to));
+                let stream = try!(self.connect_stream(stream));
+                try!(self.add_connection(event_loop, stream, to))
+            },
+        };
+        self.connections[tok].send(event_loop, &self.cluster_keys, message.to_vec())
+    }
+
+    fn set_cluster_keys(&mut self, keys: Vec<Vec<u8>>) {
+        self.cluster_keys = keys;
+    }
+
+    fn rejected_messages(&self) -> u64 {
+        self.rejected_messages
+    }
+
+    fn close_idle<H>(&mut self, event_loop: &mut EventLoop<H>) -> Result<()>
+    where H: Handler {
+        let idle: Vec<Token> = self.tokens.values()
+            .cloned()
+            .filter(|&tok| !self.connections[tok].active)
+            .collect();
+        for tok in idle {
+            debug!("TcpTransport: closing the idle connection to {}.", self.connections[tok].remote);
+            self.close(event_loop, tok);
+        }
+        for tok in self.tokens.values() {
+            self.connections[*tok].active = false;
+        }
+        Ok(())
+    }
+
+    fn shutdown<H>(&mut self, event_loop: &mut EventLoop<H>) -> Result<()>
+    where H: Handler {
+        for (remote, tok) in self.tokens.drain() {
+            if let Some(mut connection) = self.connections.remove(tok) {
+                if let Err(error) = connection.flush() {
+                    warn!("TcpTransport: unable to flush the connection to {}: {:?}", remote, error);
+                }
+                try!(event_loop.deregister(connection.stream.socket()));
+            }
+        }
+        if let Some(listener) = self.listener.take() {
+            try!(event_loop.deregister(&listener));
+        }
+        Ok(())
+    }
+}
+
+/// The stream of a connection, secured with TLS or not.
+enum Stream {
+    Plain(NonBlock<TcpStream>),
+    Tls(NonblockingSslStream<NonBlock<TcpStream>>),
+}
+
+impl Stream {
+
+    /// Returns the socket which is registered with the event loop.
+    fn socket(&self) -> &NonBlock<TcpStream> {
+        match *self {
+            Stream::Plain(ref stream) => stream,
+            Stream::Tls(ref stream) => stream.get_ref(),
+        }
+    }
+
+    /// Reads without blocking. Returns `None` if the stream would block.
+    fn read_slice(&mut self, buf: &mut [u8]) -> Result<Option<usize>> {
+        match *self {
+            Stream::Plain(ref mut stream) => Ok(try!(stream.read_slice(buf))),
+            Stream::Tls(ref mut stream) => tls_result(stream.read(buf)),
+        }
+    }
+
+    /// Writes without blocking. Returns `None` if the stream would block.
+    fn write_slice(&mut self, buf: &[u8]) -> Result<Option<usize>> {
+        match *self {
+            Stream::Plain(ref mut stream) => Ok(try!(stream.write_slice(buf))),
+            Stream::Tls(ref mut stream) => tls_result(stream.write(buf)),
+        }
+    }
+}
+
+/// Converts the result of a TLS operation. TLS may have to read or write on the socket before it
+/// can go on with the operation, for instance during the handshake; either way the operation is
+/// retried on the next event.
+fn tls_result(result: result::Result<usize, NonblockingSslError>) -> Result<Option<usize>> {
+    match result {
+        Ok(len) => Ok(Some(len)),
+        Err(NonblockingSslError::WantRead) | Err(NonblockingSslError::WantWrite) => Ok(None),
+        Err(NonblockingSslError::SslError(error)) => Err(Error::from(error)),
+    }
+}
+
+struct Connection {
+    stream: Stream,
+    token: Token,
+    remote: SocketAddr,
+    interest: Interest,
+    /// Decodes the frames read off the stream.
+    decoder: FrameDecoder,
+    /// Frames which have not been written in full yet.
+    pending_write: Vec<u8>,
+    /// Whether the remote end has closed the stream.
+    closed: bool,
+    /// Whether data has been read or written since the last check for idle connections.
+    active: bool,
+    /// The ID of the cluster, which the other end must belong to.
+    cluster_id: String,
+    /// The ID of the node at the other end, once its handshake has been received.
+    peer: Option<NodeId>,
+    /// Seals the messages sent, once the handshake of the other end has been received.
+    sealer: Option<Sealer>,
+    /// Opens the messages received, with the nonce announced by this end.
+    opener: Opener,
+    /// The packed messages waiting for the handshake of the other end to be sealed.
+    queued: Vec<Vec<u8>>,
+    max_message_size: usize,
+}
+
+impl Connection {
+    /// Note: The caller must manually assign `token` to what is desired.
+    fn new(sock: Stream, remote: SocketAddr, max_message_size: usize, cluster_id: String,
+           nonce: Vec<u8>) -> Connection {
+        Connection {
+            stream: sock,
+            token: Token(0), // Effectively a `null`. This needs to be assigned by the caller.
+            remote: remote,
+            interest: Interest::hup() | Interest::readable(),
+            decoder: FrameDecoder::new(max_message_size),
+            pending_write: Vec::new(),
+            closed: false,
+            active: true,
+            cluster_id: cluster_id,
+            peer: None,
+            sealer: None,
+            opener: Opener::new(nonce),
+            queued: Vec::new(),
+            max_message_size: max_message_size,
+        }
+    }
+
+    /// A registered IoHandle has available writing space.
+    fn writable<H>(&mut self, event_loop: &mut EventLoop<H>) -> Result<()>
+    where H: Handler {
+        try!(self.flush());
+        if self.pending_write.is_empty() {
+            // We're done writing for now.
+            self.interest.remove(Interest::writable());
+        } else {
+            self.interest.insert(Interest::writable());
+        }
+        self.reregister(event_loop)
+    }
+
+    /// A registered IoHandle has available data to read.
+    /// This does not necessarily mean that there is an entire frame on the stream. We could get
+    /// some, all of it, several, or none. The decoder buffers the data until a frame is complete.
+    /// Messages which fail to unseal under the keys are dropped, and counted in `rejected`.
+    ///
+    /// Only the rest of the frame being read is read off the socket at a time, so that at most
+    /// one frame is buffered, and a frame declaring an oversized message fails the connection as
+    /// soon as its header is read.
+    fn readable<H>(&mut self, event_loop: &mut EventLoop<H>, keys: &[Vec<u8>], rejected: &mut u64)
+                   -> Result<Vec<(SocketAddr, NodeId, OwnedSpaceMessageReader)>>
+    where H: Handler {
+        // The connection is edge triggered, so read until the socket would block.
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        let mut messages = Vec::new();
+        loop {
+            let wanted = cmp::min(try!(self.decoder.remaining()), READ_CHUNK_SIZE);
+            match try!(self.stream.read_slice(&mut chunk[..wanted])) {
+                None => break,
+                Some(0) => {
+                    self.closed = true;
+                    break;
+                },
+                Some(read) => {
+                    self.active = true;
+                    self.decoder.extend(&chunk[..read]);
+                    while let Some(reader) = try!(self.decoder.next_message()) {
+                        try!(self.receive(event_loop, keys, rejected, reader, &mut messages));
+                    }
+                },
+            }
+        }
+        if !self.closed {
+            try!(self.reregister(event_loop));
+        }
+        Ok(messages)
+    }
+
+    /// Handles a message read off the connection: the handshake of the other end, or a sealed
+    /// message which is added to `messages` once unsealed.
+    fn receive<H>(&mut self, event_loop: &mut EventLoop<H>, keys: &[Vec<u8>], rejected: &mut u64,
+                  reader: OwnedSpaceMessageReader,
+                  messages: &mut Vec<(SocketAddr, NodeId, OwnedSpaceMessageReader)>) -> Result<()>
+    where H: Handler {
+        if self.peer.is_none() {
+            // The first message is the handshake.
+            let (peer, nonce) = try!(handshake::verify(&self.cluster_id, keys, &reader));
+            debug!("TcpTransport: the connection to {} is with node {}.", self.remote, peer);
+            self.peer = Some(peer);
+            self.sealer = Some(Sealer::new(nonce));
+            for message in mem::replace(&mut self.queued, Vec::new()) {
+                if let Err(error) = self.send(event_loop, keys, message) {
+                    warn!("TcpTransport: dropping a message to {}: {:?}", self.remote, error);
+                }
+            }
+        } else {
+            // Every other message is from the node which sent the handshake.
+            let peer = self.peer.unwrap();
+            match self.opener.unseal(keys, reader) {
+                Ok(reader) => messages.push((self.remote, peer, reader)),
+                Err(error) => {
+                    *rejected += 1;
+                    warn!("TcpTransport: rejecting a message from {} ({} rejected so far): {:?}",
+                          self.remote, rejected, error);
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes as much of the pending data as possible without blocking.
+    fn flush(&mut self) -> Result<()> {
+        while !self.pending_write.is_empty() {
+            match try!(self.stream.write_slice(&self.pending_write)) {
+                // The socket would block.
+                None | Some(0) => break,
+                Some(written) => {
+                    self.active = true;
+                    self.pending_write.drain(..written);
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Seals, frames and queues the packed message, or keeps it until the handshake of the other
+    /// end has been received.
+    fn send<H>(&mut self, event_loop: &mut EventLoop<H>, keys: &[Vec<u8>], message: Vec<u8>)
+               -> Result<()>
+    where H: Handler {
+        let sealed = match self.sealer {
+            Some(ref mut sealer) => sealer.seal(keys, message),
+            None => {
+                self.queued.push(message);
+                return Ok(());
+            },
+        };
+        let frame = try!(frame::frame(&sealed, self.max_message_size));
+        self.add_write(event_loop, frame)
+    }
+
+    /// Queues a frame to be written once the socket is writable.
+    fn add_write<H>(&mut self, event_loop: &mut EventLoop<H>, frame: Vec<u8>) -> Result<()>
+    where H: Handler {
+        self.pending_write.extend(frame.into_iter());
+        self.interest.insert(Interest::writable());
+        self.reregister(event_loop)
+    }
+
+    fn reregister<H>(&mut self, event_loop: &mut EventLoop<H>) -> Result<()>
+    where H: Handler {
+        try!(event_loop.reregister(self.stream.socket(), self.token, self.interest,
+                                   PollOpt::edge() | PollOpt::oneshot()));
+        Ok(())
+    }
+}
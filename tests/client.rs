@@ -0,0 +1,39 @@
+extern crate raft;
+
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpListener};
+use std::str::FromStr;
+
+use raft::{Config, NodeId, Raft, RaftClient};
+use raft::store::MemStore;
+use raft::state_machine::ChannelStateMachine;
+
+/// Tests that a standalone client finds the leader among the members, and appends to it.
+#[test]
+fn client() {
+    let addr = SocketAddr::from_str("127.0.0.1:2800").unwrap();
+    let (state_machine, recv) = ChannelStateMachine::new();
+    let mut raft = Raft::new(NodeId::new(), addr, HashMap::new(), MemStore::new(), state_machine,
+                             Config::default()).ok().expect("Couldn't start Raft.");
+
+    // The first member is not listening, so the client moves on to the next one.
+    let members = [SocketAddr::from_str("127.0.0.1:2801").unwrap(), addr];
+    let mut client = RaftClient::connect(&members, Config::default())
+        .ok().expect("Couldn't connect.");
+    client.append(b"entry").ok().expect("Couldn't append.");
+    assert_eq!(b"entry".to_vec(), recv.recv().ok().expect("Couldn't recv."));
+
+    client.die(addr, "Client test.".to_string()).ok().expect("Couldn't kill.");
+    raft.shutdown().ok().expect("Node failed while dying.");
+}
+
+/// Tests that a client gives up on a member which accepts connections but never answers the
+/// handshake, once the request timeout has passed.
+#[test]
+fn silent_member() {
+    let addr = SocketAddr::from_str("127.0.0.1:2810").unwrap();
+    let listener = TcpListener::bind(addr).ok().expect("Couldn't bind.");
+    let config = Config { request_timeout: 500, ..Config::default() };
+    assert!(RaftClient::connect(&[addr], config).is_err(), "The silent member was answered.");
+    drop(listener);
+}
@@ -0,0 +1,35 @@
+extern crate raft;
+
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpStream};
+use std::str::FromStr;
+use std::thread;
+
+use raft::{Config, NodeId, Raft, RaftClient};
+use raft::store::MemStore;
+use raft::state_machine::NullStateMachine;
+
+/// Tests that connections over the limit are refused without bringing the node down, and that
+/// closed connections free up their place.
+#[test]
+fn connection_limit() {
+    let addr = SocketAddr::from_str("127.0.0.1:2300").unwrap();
+    let config = Config { max_connections: 2, ..Config::default() };
+    let mut raft = Raft::new(NodeId::new(), addr, HashMap::new(), MemStore::new(), NullStateMachine, config)
+                       .ok().expect("Couldn't start Raft.");
+
+    let streams: Vec<TcpStream> = (0..4).map(|_| TcpStream::connect(addr).unwrap()).collect();
+    thread::sleep_ms(100);
+    // Every place is taken, so a client is refused.
+    let config = Config { request_timeout: 500, ..Config::default() };
+    assert!(RaftClient::connect(&[addr], config).is_err(), "A connection over the limit was answered.");
+    drop(streams);
+    // Let the node notice the hangups.
+    thread::sleep_ms(100);
+
+    // A new connection is accepted once the others are closed.
+    let mut client = RaftClient::connect(&[addr], Config::default())
+                         .ok().expect("The node did not accept a new connection.");
+    client.die(addr, "Connection test.".to_string()).ok().expect("The node did not survive.");
+    raft.shutdown().ok().expect("Node failed while dying.");
+}
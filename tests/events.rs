@@ -0,0 +1,103 @@
+extern crate raft;
+
+use std::{io, result, thread};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::mpsc::{Receiver, TryRecvError};
+
+use raft::{Config, Error, ErrorKind, Event, LogIndex, NodeId, Raft, Role};
+use raft::store::MemStore;
+use raft::state_machine::{NullStateMachine, StateMachine};
+
+/// A state machine which fails to apply the command `fail`, halting its node.
+#[derive(Debug)]
+struct FailingStateMachine;
+
+impl StateMachine for FailingStateMachine {
+
+    type Error = io::Error;
+
+    fn apply(&mut self, _index: LogIndex, command: &[u8]) -> result::Result<(), io::Error> {
+        if command == b"fail" {
+            Err(io::Error::new(io::ErrorKind::Other, "the command failed"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn last_applied(&self) -> result::Result<LogIndex, io::Error> {
+        Ok(LogIndex::from(0))
+    }
+
+    fn snapshot(&self) -> result::Result<Vec<u8>, io::Error> {
+        Ok(Vec::new())
+    }
+
+    fn restore_snapshot(&mut self, _snapshot: Vec<u8>) -> result::Result<(), io::Error> {
+        Ok(())
+    }
+}
+
+/// Returns whether the subscription ends within five seconds, once the pending events are taken.
+fn ends(events: &Receiver<Event>) -> bool {
+    for _ in 0..500 {
+        match events.try_recv() {
+            Ok(_) => (),
+            Err(TryRecvError::Empty) => thread::sleep_ms(10),
+            Err(TryRecvError::Disconnected) => return true,
+        }
+    }
+    false
+}
+
+/// Tests that a subscriber learns the state of the node, and that it became the leader.
+#[test]
+fn events() {
+    let id = NodeId::new();
+    let addr = SocketAddr::from_str("127.0.0.1:3000").unwrap();
+    let mut raft = Raft::new(id, addr, HashMap::new(), MemStore::new(), NullStateMachine,
+                             Config::default()).ok().expect("Couldn't start Raft.");
+    let events = raft.subscribe().ok().expect("Couldn't subscribe.");
+    raft.append(b"entry").ok().expect("Couldn't append.");
+
+    // The node is elected by the time the entry is applied, whether before or after subscribing.
+    let mut role = None;
+    let mut leader = None;
+    while role != Some(Role::Leader) || leader != Some(Some(id)) {
+        match events.recv().ok().expect("Couldn't recv.") {
+            Event::RoleChanged(new_role) => role = Some(new_role),
+            Event::LeaderChanged(new_leader) => leader = Some(new_leader),
+            Event::TermChanged(_) => (),
+        }
+    }
+
+    raft.die(addr, "Events test.".to_string()).ok().expect("Couldn't kill.");
+    raft.shutdown().ok().expect("Node failed while dying.");
+}
+
+/// Tests that the subscriptions to a node end once it halts, including those made afterwards, and
+/// once it shuts down.
+#[test]
+fn halted_events() {
+    let addr = SocketAddr::from_str("127.0.0.1:3001").unwrap();
+    let mut raft = Raft::new(NodeId::new(), addr, HashMap::new(), MemStore::new(),
+                             FailingStateMachine, Config::default())
+                       .ok().expect("Couldn't start Raft.");
+    let events = raft.subscribe().ok().expect("Couldn't subscribe.");
+    match raft.append(b"fail") {
+        Err(Error::Raft(ErrorKind::Halted(_))) => (),
+        outcome => panic!("The failed append returned {:?}.", outcome),
+    }
+    assert!(ends(&events), "The subscription outlived the halt.");
+    let late = raft.subscribe().ok().expect("Couldn't subscribe.");
+    assert!(ends(&late), "A subscription to the halted node did not end.");
+
+    raft.shutdown().ok().expect("Couldn't shut down.");
+    let addr = SocketAddr::from_str("127.0.0.1:3002").unwrap();
+    let mut raft = Raft::new(NodeId::new(), addr, HashMap::new(), MemStore::new(), NullStateMachine,
+                             Config::default()).ok().expect("Couldn't start Raft.");
+    let events = raft.subscribe().ok().expect("Couldn't subscribe.");
+    raft.shutdown().ok().expect("Couldn't shut down.");
+    assert!(ends(&events), "The subscription outlived the shutdown.");
+}
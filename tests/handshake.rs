@@ -0,0 +1,34 @@
+extern crate raft;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use raft::{Config, NodeId, Raft, RaftClient};
+use raft::store::MemStore;
+use raft::state_machine::NullStateMachine;
+
+fn start(addr: SocketAddr, peers: HashMap<NodeId, SocketAddr>, cluster_id: &str) -> Raft {
+    let config = Config { cluster_id: cluster_id.to_string(), ..Config::default() };
+    Raft::new(NodeId::new(), addr, peers, MemStore::new(), NullStateMachine, config)
+        .ok().expect("Couldn't start Raft.")
+}
+
+/// Tests that a node refuses requests from a node of another cluster, and keeps serving its own.
+#[test]
+fn foreign_cluster() {
+    let addr = SocketAddr::from_str("127.0.0.1:2500").unwrap();
+    let mut raft = start(addr, HashMap::new(), "one");
+
+    let foreign_addr = SocketAddr::from_str("127.0.0.1:2501").unwrap();
+    let mut peers = HashMap::new();
+    peers.insert(NodeId::new(), addr);
+    let mut foreign = start(foreign_addr, peers, "two");
+    assert!(foreign.die(addr, "Foreign.".to_string()).is_err(), "A foreign cluster was answered.");
+    foreign.shutdown().ok().expect("Couldn't shut down the foreign node.");
+
+    let config = Config { cluster_id: "one".to_string(), ..Config::default() };
+    let mut client = RaftClient::connect(&[addr], config).ok().expect("The node did not survive.");
+    client.die(addr, "Handshake test.".to_string()).ok().expect("The node did not survive.");
+    raft.shutdown().ok().expect("Node failed while dying.");
+}
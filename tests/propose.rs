@@ -0,0 +1,57 @@
+extern crate raft;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use raft::{Config, NodeId, Raft};
+use raft::store::MemStore;
+use raft::state_machine::ChannelStateMachine;
+
+/// Tests that many entries may be proposed before the first is applied, and that they are all
+/// applied in the order they were proposed.
+#[test]
+fn propose() {
+    let addr = SocketAddr::from_str("127.0.0.1:2600").unwrap();
+    let (state_machine, recv) = ChannelStateMachine::new();
+    let mut raft = Raft::new(NodeId::new(), addr, HashMap::new(), MemStore::new(), state_machine,
+                             Config::default()).ok().expect("Couldn't start Raft.");
+    raft.append(b"first").ok().expect("Couldn't append.");
+    assert_eq!(b"first".to_vec(), recv.recv().ok().expect("Couldn't recv."));
+
+    let proposals: Vec<_> = (0..100).map(|n| {
+        raft.propose(format!("entry {}", n).as_bytes()).ok().expect("Couldn't propose.")
+    }).collect();
+    for proposal in proposals {
+        proposal.wait().ok().expect("A proposal failed.");
+    }
+    for n in 0..100 {
+        assert_eq!(format!("entry {}", n).into_bytes(), recv.recv().ok().expect("Couldn't recv."));
+    }
+
+    raft.die(addr, "Propose test.".to_string()).ok().expect("Couldn't kill.");
+    raft.shutdown().ok().expect("Node failed while dying.");
+}
+
+/// Tests that a batch of entries is appended at consecutive indexes, and applied in order.
+#[test]
+fn append_batch() {
+    let addr = SocketAddr::from_str("127.0.0.1:2601").unwrap();
+    let (state_machine, recv) = ChannelStateMachine::new();
+    let mut raft = Raft::new(NodeId::new(), addr, HashMap::new(), MemStore::new(), state_machine,
+                             Config::default()).ok().expect("Couldn't start Raft.");
+    raft.append(b"first").ok().expect("Couldn't append.");
+    assert_eq!(b"first".to_vec(), recv.recv().ok().expect("Couldn't recv."));
+
+    let batch: [&[u8]; 3] = [b"one", b"two", b"three"];
+    let indexes = raft.append_batch(&batch).ok().expect("Couldn't append the batch.");
+    assert_eq!(3, indexes.len());
+    assert_eq!(indexes[0] + 1, indexes[1]);
+    assert_eq!(indexes[1] + 1, indexes[2]);
+    for &entry in batch.iter() {
+        assert_eq!(entry.to_vec(), recv.recv().ok().expect("Couldn't recv."));
+    }
+
+    raft.die(addr, "Batch test.".to_string()).ok().expect("Couldn't kill.");
+    raft.shutdown().ok().expect("Node failed while dying.");
+}
@@ -0,0 +1,181 @@
+extern crate raft;
+extern crate env_logger;
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::net::SocketAddr;
+use std::result;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex};
+
+use raft::{Config, Configuration, EntryKind, LogIndex, NodeId, Raft, Term};
+use raft::store::{self, MemStore, Store};
+use raft::state_machine::StateMachine;
+
+/// A `Store` which writes through to a `MemStore` shared with the test, so that the persisted
+/// state outlives the `Server` that owns the store.
+#[derive(Clone, Debug)]
+struct DurableStore {
+    local: MemStore,
+    disk: Arc<Mutex<MemStore>>,
+}
+
+impl DurableStore {
+    fn open(disk: Arc<Mutex<MemStore>>) -> DurableStore {
+        let local = disk.lock().unwrap().clone();
+        DurableStore { local: local, disk: disk }
+    }
+}
+
+impl Store for DurableStore {
+
+    type Error = store::Error;
+
+    fn current_term(&self) -> result::Result<Term, store::Error> {
+        self.local.current_term()
+    }
+
+    fn set_current_term(&mut self, term: Term) -> result::Result<(), store::Error> {
+        try!(self.disk.lock().unwrap().set_current_term(term));
+        self.local.set_current_term(term)
+    }
+
+    fn inc_current_term(&mut self) -> result::Result<Term, store::Error> {
+        try!(self.disk.lock().unwrap().inc_current_term());
+        self.local.inc_current_term()
+    }
+
+    fn voted_for(&self) -> result::Result<Option<NodeId>, store::Error> {
+        self.local.voted_for()
+    }
+
+    fn set_voted_for(&mut self, candidate: NodeId) -> result::Result<(), store::Error> {
+        try!(self.disk.lock().unwrap().set_voted_for(candidate));
+        self.local.set_voted_for(candidate)
+    }
+
+    fn latest_log_index(&self) -> result::Result<LogIndex, store::Error> {
+        self.local.latest_log_index()
+    }
+
+    fn latest_log_term(&self) -> result::Result<Term, store::Error> {
+        self.local.latest_log_term()
+    }
+
+    fn entry(&self, index: LogIndex) -> result::Result<(Term, EntryKind, &[u8]), store::Error> {
+        self.local.entry(index)
+    }
+
+    fn append_entries(&mut self,
+                      from: LogIndex,
+                      entries: &[(Term, EntryKind, &[u8])])
+                      -> result::Result<(), store::Error> {
+        try!(self.disk.lock().unwrap().append_entries(from, entries));
+        self.local.append_entries(from, entries)
+    }
+
+    fn committed_configuration(&self) -> result::Result<Option<(LogIndex, Configuration)>, store::Error> {
+        self.local.committed_configuration()
+    }
+
+    fn set_committed_configuration(&mut self,
+                                   index: LogIndex,
+                                   configuration: Configuration)
+                                   -> result::Result<(), store::Error> {
+        try!(self.disk.lock().unwrap().set_committed_configuration(index, configuration.clone()));
+        self.local.set_committed_configuration(index, configuration)
+    }
+
+    fn uncommitted_configuration(&self) -> result::Result<Option<(LogIndex, Configuration)>, store::Error> {
+        self.local.uncommitted_configuration()
+    }
+
+    fn set_uncommitted_configuration(&mut self,
+                                     configuration: Option<(LogIndex, Configuration)>)
+                                     -> result::Result<(), store::Error> {
+        try!(self.disk.lock().unwrap().set_uncommitted_configuration(configuration.clone()));
+        self.local.set_uncommitted_configuration(configuration)
+    }
+
+    fn sync(&mut self) -> result::Result<(), store::Error> {
+        // Every change is written through to the disk immediately.
+        Ok(())
+    }
+}
+
+/// A `StateMachine` which redirects commands to a channel and records the latest applied index
+/// in a location shared with the test.
+struct DurableStateMachine {
+    tx: mpsc::Sender<Vec<u8>>,
+    applied: Arc<Mutex<LogIndex>>,
+}
+
+impl StateMachine for DurableStateMachine {
+
+    type Error = mpsc::SendError<Vec<u8>>;
+
+    fn apply(&mut self, index: LogIndex, command: &[u8]) -> result::Result<(), mpsc::SendError<Vec<u8>>> {
+        try!(self.tx.send(command.to_vec()));
+        *self.applied.lock().unwrap() = index;
+        Ok(())
+    }
+
+    fn last_applied(&self) -> result::Result<LogIndex, mpsc::SendError<Vec<u8>>> {
+        Ok(*self.applied.lock().unwrap())
+    }
+
+    fn snapshot(&self) -> result::Result<Vec<u8>, mpsc::SendError<Vec<u8>>> {
+        Ok(Vec::new())
+    }
+
+    fn restore_snapshot(&mut self, _snapshot: Vec<u8>) -> result::Result<(), mpsc::SendError<Vec<u8>>> {
+        Ok(())
+    }
+}
+
+impl Debug for DurableStateMachine {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "DurableStateMachine")
+    }
+}
+
+/// Starts a solitary node on the persisted state.
+fn start(id: NodeId,
+         addr: SocketAddr,
+         disk: &Arc<Mutex<MemStore>>,
+         applied: &Arc<Mutex<LogIndex>>)
+         -> (Raft, mpsc::Receiver<Vec<u8>>) {
+    let (tx, recv) = mpsc::channel();
+    let state_machine = DurableStateMachine { tx: tx, applied: applied.clone() };
+    let raft = Raft::new(id, addr, HashMap::new(), DurableStore::open(disk.clone()), state_machine,
+                         Config::default())
+                   .ok().expect("Couldn't start Raft.");
+    (raft, recv)
+}
+
+#[test]
+fn restart() {
+    let id = NodeId::new();
+    let addr = SocketAddr::from_str("127.0.0.1:2100").unwrap();
+    let disk = Arc::new(Mutex::new(MemStore::new()));
+    let applied = Arc::new(Mutex::new(LogIndex::from(0)));
+
+    let (mut raft, recv) = start(id, addr, &disk, &applied);
+    raft.append(b"one").ok().expect("Couldn't append.");
+    raft.append(b"two").ok().expect("Couldn't append.");
+    assert_eq!(b"one".to_vec(), recv.recv().ok().expect("Couldn't recv."));
+    assert_eq!(b"two".to_vec(), recv.recv().ok().expect("Couldn't recv."));
+
+    // Tear down the `Server`, then bring it back on the same store, under the same ID but at a
+    // new address.
+    raft.die(addr, "Restart test.".to_string()).ok().expect("Couldn't kill.");
+    raft.shutdown().ok().expect("Node failed while dying.");
+    let addr = SocketAddr::from_str("127.0.0.1:2101").unwrap();
+    let (mut raft, recv) = start(id, addr, &disk, &applied);
+    assert_eq!(LogIndex::from(2), *applied.lock().unwrap());
+
+    raft.append(b"three").ok().expect("Couldn't append.");
+    // The entries applied before the restart must not be applied again.
+    assert_eq!(b"three".to_vec(), recv.recv().ok().expect("Couldn't recv."));
+    assert_eq!(LogIndex::from(3), *applied.lock().unwrap());
+}
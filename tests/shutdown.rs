@@ -0,0 +1,70 @@
+extern crate raft;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::thread;
+
+use raft::{Config, Error, ErrorKind, NodeId, Raft};
+use raft::store::MemStore;
+use raft::state_machine::NullStateMachine;
+
+fn start(addr: SocketAddr) -> Raft {
+    Raft::new(NodeId::new(), addr, HashMap::new(), MemStore::new(), NullStateMachine, Config::default())
+        .ok().expect("Couldn't start Raft.")
+}
+
+/// Tests that a shut down node releases its address, so that a new node can take its place.
+#[test]
+fn shutdown() {
+    let addr = SocketAddr::from_str("127.0.0.1:2200").unwrap();
+    let mut raft = start(addr);
+    raft.shutdown().ok().expect("Couldn't shut down.");
+    // Shutting down twice is harmless.
+    raft.shutdown().ok().expect("Couldn't shut down again.");
+    let mut raft = start(addr);
+    raft.shutdown().ok().expect("Couldn't shut down the replacement.");
+}
+
+/// Tests that a node stopped with `die()` can still be shut down.
+#[test]
+fn die() {
+    let addr = SocketAddr::from_str("127.0.0.1:2201").unwrap();
+    let mut raft = start(addr);
+    raft.die(addr, "Shutdown test.".to_string()).ok().expect("Couldn't kill.");
+    raft.shutdown().ok().expect("Node failed while dying.");
+}
+
+/// Tests that requests fail with `ErrorKind::RelatedNodeDown` once the related Server has died,
+/// and with `ErrorKind::Shutdown` once it has been shut down.
+#[test]
+fn requests_after_stop() {
+    let addr = SocketAddr::from_str("127.0.0.1:2202").unwrap();
+    let mut raft = start(addr);
+    raft.die(addr, "Shutdown test.".to_string()).ok().expect("Couldn't kill.");
+    // The Server stops shortly after answering the request.
+    thread::sleep_ms(200);
+    match raft.append(b"entry") {
+        Err(Error::Raft(ErrorKind::RelatedNodeDown)) => (),
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    raft.shutdown().ok().expect("Node failed while dying.");
+    match raft.append(b"entry") {
+        Err(Error::Raft(ErrorKind::Shutdown)) => (),
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+/// Tests that a node can not be started on an address which is already in use.
+#[test]
+fn address_in_use() {
+    let addr = SocketAddr::from_str("127.0.0.1:2203").unwrap();
+    let mut raft = start(addr);
+    match Raft::new(NodeId::new(), addr, HashMap::new(), MemStore::new(), NullStateMachine,
+                    Config::default()) {
+        Err(Error::Io(_)) => (),
+        Err(error) => panic!("Unexpected error: {:?}", error),
+        Ok(_) => panic!("Started a second node on {}.", addr),
+    }
+    raft.shutdown().ok().expect("Couldn't shut down.");
+}
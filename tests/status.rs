@@ -0,0 +1,31 @@
+extern crate raft;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use raft::{Config, LogIndex, NodeId, Raft, Role};
+use raft::store::MemStore;
+use raft::state_machine::NullStateMachine;
+
+/// Tests that a node reports its state.
+#[test]
+fn status() {
+    let id = NodeId::new();
+    let addr = SocketAddr::from_str("127.0.0.1:2900").unwrap();
+    let mut raft = Raft::new(id, addr, HashMap::new(), MemStore::new(), NullStateMachine,
+                             Config::default()).ok().expect("Couldn't start Raft.");
+    raft.append(b"entry").ok().expect("Couldn't append.");
+
+    let status = raft.status(addr).ok().expect("Couldn't get the status.");
+    assert_eq!(id, status.id);
+    assert_eq!(Role::Leader, status.role);
+    assert_eq!(Some(id), status.leader);
+    assert_eq!(LogIndex::from(1), status.commit_index);
+    assert_eq!(LogIndex::from(1), status.last_applied);
+    assert!(status.peers.is_empty());
+    assert_eq!(0, status.rejected_messages);
+
+    raft.die(addr, "Status test.".to_string()).ok().expect("Couldn't kill.");
+    raft.shutdown().ok().expect("Node failed while dying.");
+}
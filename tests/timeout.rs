@@ -0,0 +1,30 @@
+extern crate raft;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use raft::{Config, Error, ErrorKind, NodeId, Raft};
+use raft::store::MemStore;
+use raft::state_machine::NullStateMachine;
+
+/// Tests that an append to a cluster which can not elect a leader fails once the request timeout
+/// has passed.
+#[test]
+fn timeout() {
+    let addr = SocketAddr::from_str("127.0.0.1:2700").unwrap();
+    // The peer is never started, so no leader can be elected.
+    let mut peers = HashMap::new();
+    peers.insert(NodeId::new(), SocketAddr::from_str("127.0.0.1:2701").unwrap());
+    let config = Config { request_timeout: 500, ..Config::default() };
+    let mut raft = Raft::new(NodeId::new(), addr, peers, MemStore::new(), NullStateMachine, config)
+                       .ok().expect("Couldn't start Raft.");
+
+    match raft.append(b"entry") {
+        Err(Error::Raft(ErrorKind::CannotProceed)) => (),
+        other => panic!("Unexpected outcome: {:?}", other),
+    }
+
+    raft.die(addr, "Timeout test.".to_string()).ok().expect("Couldn't kill.");
+    raft.shutdown().ok().expect("Node failed while dying.");
+}
@@ -0,0 +1,99 @@
+extern crate raft;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use raft::{Config, NodeId, Raft, RaftClient, TlsConfig};
+use raft::store::MemStore;
+use raft::state_machine::NullStateMachine;
+
+/// Runs the `openssl` tool in the directory.
+fn openssl(dir: &Path, args: &[&str]) {
+    let output = Command::new("openssl").args(args).current_dir(dir).output()
+        .ok().expect("Couldn't run openssl.");
+    assert!(output.status.success(), "openssl {:?} failed: {}",
+            args, String::from_utf8_lossy(&output.stderr));
+}
+
+/// Generates a certificate authority in a fresh directory, along with a node certificate signed
+/// by it.
+fn generate_certificates(name: &str) -> TlsConfig {
+    let dir: PathBuf = env::temp_dir().join(format!("raft-tls-{}", name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).ok().expect("Couldn't create the certificate directory.");
+    openssl(&dir, &["req", "-x509", "-newkey", "rsa:2048", "-nodes", "-days", "1",
+                    "-subj", "/CN=raft test CA", "-keyout", "ca.key", "-out", "ca.crt"]);
+    openssl(&dir, &["req", "-newkey", "rsa:2048", "-nodes",
+                    "-subj", "/CN=raft test node", "-keyout", "node.key", "-out", "node.csr"]);
+    openssl(&dir, &["x509", "-req", "-days", "1", "-in", "node.csr", "-CA", "ca.crt",
+                    "-CAkey", "ca.key", "-CAcreateserial", "-out", "node.crt"]);
+    TlsConfig {
+        ca_file: dir.join("ca.crt"),
+        cert_file: dir.join("node.crt"),
+        key_file: dir.join("node.key"),
+    }
+}
+
+fn start(addr: SocketAddr, peers: HashMap<NodeId, SocketAddr>, tls: TlsConfig) -> Raft {
+    let config = Config { tls: Some(tls), ..Config::default() };
+    Raft::new(NodeId::new(), addr, peers, MemStore::new(), NullStateMachine, config)
+        .ok().expect("Couldn't start Raft.")
+}
+
+/// Kills the node over a TLS connection, which fails unless the node still serves clients.
+fn die(addr: SocketAddr, tls: TlsConfig) {
+    let config = Config { tls: Some(tls), ..Config::default() };
+    let mut client = RaftClient::connect(&[addr], config).ok().expect("Couldn't connect over TLS.");
+    client.die(addr, "TLS test.".to_string()).ok().expect("Couldn't kill over TLS.");
+}
+
+/// Tests that a client request is answered over TLS.
+#[test]
+fn tls() {
+    let addr = SocketAddr::from_str("127.0.0.1:2400").unwrap();
+    let tls = generate_certificates("tls");
+    let mut raft = start(addr, HashMap::new(), tls.clone());
+    die(addr, tls);
+    raft.shutdown().ok().expect("Node failed while dying.");
+}
+
+/// Tests that a plain TCP connection to a node using TLS is closed, and that the node keeps
+/// serving.
+#[test]
+fn plaintext_refused() {
+    let addr = SocketAddr::from_str("127.0.0.1:2401").unwrap();
+    let tls = generate_certificates("plaintext");
+    let mut raft = start(addr, HashMap::new(), tls.clone());
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"\0\0\0\x05hello").unwrap();
+    // Returns once the node has closed the connection. At most a TLS alert is sent back.
+    let _ = stream.read_to_end(&mut Vec::new());
+
+    die(addr, tls);
+    raft.shutdown().ok().expect("Node failed while dying.");
+}
+
+/// Tests that a client whose certificate is signed by another certificate authority is refused.
+#[test]
+fn untrusted_certificate() {
+    let addr = SocketAddr::from_str("127.0.0.1:2402").unwrap();
+    let tls = generate_certificates("trusted");
+    let mut raft = start(addr, HashMap::new(), tls.clone());
+
+    let rogue_addr = SocketAddr::from_str("127.0.0.1:2403").unwrap();
+    let mut peers = HashMap::new();
+    peers.insert(NodeId::new(), addr);
+    let mut rogue = start(rogue_addr, peers, generate_certificates("rogue"));
+    assert!(rogue.die(addr, "Rogue.".to_string()).is_err(), "An untrusted client was answered.");
+    rogue.shutdown().ok().expect("Couldn't shut down the rogue node.");
+
+    die(addr, tls);
+    raft.shutdown().ok().expect("Node failed while dying.");
+}
@@ -0,0 +1,29 @@
+extern crate raft;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use raft::{Config, NodeId, Raft};
+use raft::store::MemStore;
+use raft::state_machine::ChannelStateMachine;
+use raft::transport::ChannelNetwork;
+
+/// Tests that a node on a `ChannelTransport` serves the requests of its `Raft` without binding a
+/// socket.
+#[test]
+fn channel_transport() {
+    let network = ChannelNetwork::new();
+    let id = NodeId::new();
+    // The address is only a name on the network.
+    let addr = SocketAddr::from_str("127.0.0.1:1").unwrap();
+    let (state_machine, recv) = ChannelStateMachine::new();
+    let mut raft = Raft::with_transport(id, addr, HashMap::new(), MemStore::new(), state_machine,
+                                        network.transport(id, addr), Config::default())
+                       .ok().expect("Couldn't start Raft.");
+
+    raft.append(b"entry").ok().expect("Couldn't append.");
+    assert_eq!(b"entry".to_vec(), recv.recv().ok().expect("Couldn't recv."));
+
+    raft.shutdown().ok().expect("Couldn't shut down.");
+}